@@ -18,6 +18,7 @@ import {useEffect, useState} from 'react';
 import useModal from '../../hooks/useModal.tsx';
 import Button from '../../ui/Button.tsx';
 import {deleteRecord, saveRecord, uuid} from "../../../records/record.ts";
+import {NOTE_MIME_TYPE} from "../../../notes/note.ts";
 import { useNavigate } from "react-router-dom";
 
 export default function ActionsPlugin({noteId}: {noteId: uuid}): JSX.Element {
@@ -63,7 +64,7 @@ export default function ActionsPlugin({noteId}: {noteId: uuid}): JSX.Element {
                 className="action-button save"
                 disabled={isEditorEmpty}
                 onClick={() => {
-                    saveRecord({body: editor.getEditorState().toJSON(), id: noteId, mime_type: "note"}).then(r=>{
+                    saveRecord({body: {editorState: editor.getEditorState().toJSON()}, id: noteId, mime_type: NOTE_MIME_TYPE}).then(r=>{
                         if(r){
                             navigate(`/n/${noteId}`);
                         }else{
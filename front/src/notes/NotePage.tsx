@@ -37,6 +37,6 @@ export default function NotePage(): JSX.Element {
     }
 
     return (
-        <Composer body={data.body} noteId={noteId}/>
+        <Composer body={data.body.editorState} noteId={noteId}/>
     );
 }
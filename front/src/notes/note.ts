@@ -6,13 +6,20 @@ import PlaygroundEditorTheme from "../lexical/themes/PlaygroundEditorTheme.ts";
 import {ImageNode} from "../lexical/nodes/ImageNode.tsx";
 import {$generateHtmlFromNodes} from "@lexical/html";
 
+// The server checks bodies of this mime type, merges concurrent edits and derives titles from them.
+export const NOTE_MIME_TYPE = "note/lexical";
+
+export type NoteBody = {
+    editorState: SerializedEditorState,
+};
+
 export type Note = Record & {
-    mime_type: "note",
-    body: SerializedEditorState,
+    mime_type: typeof NOTE_MIME_TYPE,
+    body: NoteBody,
 };
 
 
-export const isNote = (record: Record): record is Note => record.mime_type==='note';
+export const isNote = (record: Record): record is Note => record.mime_type===NOTE_MIME_TYPE;
 
 export const generateHtmlFromNote = (record: Note, width: number): string => {
     const editor = createHeadlessEditor({
@@ -22,7 +29,7 @@ export const generateHtmlFromNote = (record: Note, width: number): string => {
         editable: false,
     });
 
-    const editorState = editor.parseEditorState(record.body);
+    const editorState = editor.parseEditorState(record.body.editorState);
     editor.setEditorState(editorState);
     editor.registerNodeTransform(ImageNode,(imageNote)=>{
         imageNote.__width = width-20;
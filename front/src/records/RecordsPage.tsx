@@ -1,10 +1,10 @@
 import {deleteRecord, getRecords, Record} from "./record.ts";
-import {useQuery} from "@tanstack/react-query";
+import {useQuery, useQueryClient} from "@tanstack/react-query";
 import {Link, useNavigate} from "react-router-dom";
 import {isNote} from "../notes/note.ts";
 import Viewer from "../notes/Viewer.tsx";
 import { Masonry } from "masonic";
-import React from "react";
+import React, {useEffect} from "react";
 import styled from "styled-components";
 
 const MasonryItemBox = styled.div`
@@ -21,7 +21,7 @@ const MasonryItem = ({index,data,width}:{index:number, data: Record, width: numb
             <Link to={`/n/${data.id}`}>Go To</Link>
             {isNote(data) &&
                 <>
-                    <Viewer width={width-20} noteId={data.id} body={data.body}/>
+                    <Viewer width={width-20} noteId={data.id} body={data.body.editorState}/>
                 </>
             }
         </MasonryItemBox>
@@ -35,6 +35,15 @@ const MasonryCss: React.CSSProperties = {
 export default function RecordsPage(): JSX.Element {
 
     const {isLoading, isError, data, error} = useQuery({queryKey: ['records'], queryFn: getRecords})
+    const queryClient = useQueryClient();
+
+    // Changes made on other screens arrive through the server's event stream.
+    useEffect(() => {
+        const events = new EventSource("/api/events");
+        const refresh = () => queryClient.invalidateQueries({queryKey: ['records']});
+        ["record_created", "record_updated", "record_deleted"].forEach(type => events.addEventListener(type, refresh));
+        return () => events.close();
+    }, [queryClient]);
 
     if (isLoading) {
         return <>LOADING</>
@@ -24,7 +24,7 @@ const RecordSchema = {
             type: 'object',
         },
         updatedAt: {
-            type: typeof Date,
+            type: 'integer',
         },
     },
 };
@@ -32,8 +32,14 @@ const RecordSchema = {
 export type Record = {
     readonly id: uuid,
     readonly mime_type: string,
+    readonly title: string,
+    readonly excerpt: string,
     readonly body: object,
+    readonly created_at: typeof Date,
     readonly updated_at: typeof Date,
+    readonly revision_count: number,
+    readonly created_by: string | null,
+    readonly updated_by: string | null,
 }
 
 export type RecordToBeSaved = {
@@ -64,6 +70,16 @@ export const getRecords = (): Promise<Record[]> => {
     }).then(r => r.json());
 };
 
+// Latest revision seen of each record, sent back as If-Match so a stale save is refused instead of overwriting.
+const revisions = new Map<uuid, string>();
+
+const rememberRevision = (id: uuid, r: Response) => {
+    const etag = r.headers.get("ETag");
+    if (etag !== null) {
+        revisions.set(id, etag);
+    }
+}
+
 export const getRecord = (id: uuid): Promise<Record> => {
     if(!isUuid(id)){
         return Promise.reject(new NotFoundRecordByIdError(id));
@@ -74,6 +90,7 @@ export const getRecord = (id: uuid): Promise<Record> => {
         if (r.status === 404) {
             return Promise.reject(new NotFoundRecordByIdError(id));
         }
+        rememberRevision(id, r);
         return r.json();
     });
 }
@@ -88,11 +105,16 @@ export const deleteRecord = (id: uuid): Promise<boolean> => {
     }).then(r => r.status === 200);
 }
 export const saveRecord = (record: RecordToBeSaved): Promise<boolean> => {
+    const revision = revisions.get(record.id);
     return fetch(`/api/records/${record.id.toString()}`, {
         method: "POST",
         headers: {
             "Content-Type": "application/json",
+            ...(revision === undefined ? {} : {"If-Match": revision}),
         },
         body: JSON.stringify(record),
-    }).then(r => r.status === 201)
+    }).then(r => {
+        rememberRevision(record.id, r);
+        return r.status === 201;
+    })
 }
\ No newline at end of file
@@ -0,0 +1,212 @@
+pub mod service {
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use uuid::Uuid;
+    use percent_encoding::percent_decode_str;
+
+    use crate::attachment::queries::{Attachment, delete_record_attachments, replace_record_attachments, select_record_attachments};
+    use crate::error::{Error, OptionalExt};
+    use crate::storage::create_service;
+    use crate::storage::storage::service::{RequestReadBlob, ResponseReadMetaDataBlob};
+
+    const FILE_URL_PREFIX: &str = "/api/file/";
+    const URL_KEYS: [&str; 2] = ["src", "url"];
+
+    /// Walks a Lexical body and collects every node pointing at `/api/file/{file}/{filename}`
+    /// through its `src` (image nodes) or `url` (file and link nodes) property.
+    pub fn extract_attachments(record_id: Uuid, body: &serde_json::Value) -> Vec<Attachment> {
+        let mut attachments: Vec<Attachment> = Vec::new();
+        collect_attachments(record_id, body, &mut attachments);
+        attachments
+    }
+
+    fn collect_attachments(record_id: Uuid, value: &serde_json::Value, attachments: &mut Vec<Attachment>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for key in URL_KEYS {
+                    if let Some((file_id, filename)) = map.get(key).and_then(|v| v.as_str()).and_then(parse_file_url) {
+                        if !attachments.iter().any(|a| a.file_id == file_id && a.filename == filename) {
+                            attachments.push(Attachment { record_id, file_id, filename });
+                        }
+                    }
+                }
+                for child in map.values() {
+                    collect_attachments(record_id, child, attachments);
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for child in items {
+                    collect_attachments(record_id, child, attachments);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn parse_file_url(url: &str) -> Option<(Uuid, String)> {
+        let start = url.find(FILE_URL_PREFIX)? + FILE_URL_PREFIX.len();
+        let path = url[start..].split(['?', '#']).next()?;
+        let segments: Vec<&str> = path.split('/').collect();
+        let (id, filename) = match segments.as_slice() {
+            [id, filename] => (id, filename),
+            [id, "download" | "meta", filename] => (id, filename),
+            _ => return None,
+        };
+        let filename = percent_decode_str(filename).decode_utf8().ok()?.to_string();
+        if filename.is_empty() {
+            return None;
+        }
+        Some((Uuid::parse_str(id).ok()?, filename))
+    }
+
+    pub fn link_attachments(record_id: Uuid, body: &serde_json::Value, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        replace_record_attachments(record_id, &extract_attachments(record_id, body), pool)
+    }
+
+    pub fn unlink_attachments(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        delete_record_attachments(record_id, pool)
+    }
+
+    /// Attachments whose file was deleted since are left out.
+    pub fn record_attachments(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<ResponseReadMetaDataBlob>, Error> {
+        let storage_service = create_service(pool);
+        select_record_attachments(record_id, pool)?
+            .into_iter()
+            .filter_map(|attachment| storage_service.read_meta_data(RequestReadBlob {
+                id: attachment.file_id,
+                filename: attachment.filename,
+            }).optional().transpose())
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use uuid::Uuid;
+
+        use crate::attachment::service::extract_attachments;
+
+        #[test]
+        fn test_extract_attachments() {
+            let record_id = Uuid::parse_str("2b4d6f81-3a5c-4e7b-9d1f-0a2c4e6b8d13").unwrap();
+            let body: serde_json::Value = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[{\"children\":[{\"altText\":\"cat\",\"type\":\"image\",\"version\":1,\"src\":\"/api/file/4e6a8c02-5b7d-4f9a-8e2b-1c3d5f7a9b24/cat%20typing.gif\"},{\"children\":[],\"type\":\"link\",\"version\":1,\"url\":\"http://localhost:8080/api/file/5f7b9d13-6c8e-4a0b-9f3c-2d4e6a8b0c35/download/report.pdf?x=1\"},{\"children\":[],\"type\":\"link\",\"version\":1,\"url\":\"https://lexical.dev/\"},{\"type\":\"image\",\"version\":1,\"src\":\"/api/file/4e6a8c02-5b7d-4f9a-8e2b-1c3d5f7a9b24/cat%20typing.gif\"}],\"type\":\"paragraph\",\"version\":1}],\"type\":\"root\",\"version\":1}}}").unwrap();
+
+            let attachments = extract_attachments(record_id, &body);
+
+            assert_eq!(attachments.len(), 2);
+            assert_eq!(attachments[0].file_id, Uuid::parse_str("4e6a8c02-5b7d-4f9a-8e2b-1c3d5f7a9b24").unwrap());
+            assert_eq!(attachments[0].filename, "cat typing.gif");
+            assert_eq!(attachments[1].file_id, Uuid::parse_str("5f7b9d13-6c8e-4a0b-9f3c-2d4e6a8b0c35").unwrap());
+            assert_eq!(attachments[1].filename, "report.pdf");
+        }
+
+        #[test]
+        fn test_extract_attachments_when_body_has_no_files() {
+            let record_id = Uuid::parse_str("3c5e7a92-4b6d-4f8c-8e2a-1b3d5f7c9e24").unwrap();
+            let body: serde_json::Value = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}").unwrap();
+
+            assert!(extract_attachments(record_id, &body).is_empty());
+        }
+    }
+}
+
+pub mod queries {
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use uuid::Uuid;
+
+    use crate::error::{Error, parse_column};
+
+    pub struct Attachment {
+        pub record_id: Uuid,
+        pub file_id: Uuid,
+        pub filename: String,
+    }
+
+    pub fn replace_record_attachments(record_id: Uuid, attachments: &[Attachment], pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let mut connection = pool.get()?;
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM records_attachments WHERE record_id = ?1", [record_id.to_string().as_str()])?;
+        {
+            let mut stmt = transaction.prepare("INSERT INTO records_attachments (record_id, file_id, filename) VALUES (?1, ?2, ?3)")?;
+            for attachment in attachments {
+                stmt.execute([
+                    attachment.record_id.to_string().as_str(),
+                    attachment.file_id.to_string().as_str(),
+                    attachment.filename.as_str(),
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn delete_record_attachments(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("DELETE FROM records_attachments WHERE record_id = ?1")?;
+        stmt.execute([
+            record_id.to_string().as_str(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn select_record_attachments(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<Attachment>, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("SELECT record_id, file_id, filename FROM records_attachments WHERE record_id = ?1 ORDER BY file_id, filename")?;
+
+        let result_of_attachments = stmt.query_map([record_id.to_string().as_str()], |row| Ok(Attachment {
+            record_id: parse_column(row, 0)?,
+            file_id: parse_column(row, 1)?,
+            filename: row.get::<_, String>(2)?,
+        }))?;
+
+        let mut attachments: Vec<Attachment> = Vec::new();
+
+        for result_of_attachment in result_of_attachments {
+            attachments.push(result_of_attachment?);
+        }
+
+        Ok(attachments)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use uuid::Uuid;
+
+        use crate::attachment::queries::{Attachment, delete_record_attachments, replace_record_attachments, select_record_attachments};
+        use crate::testing::init_pool;
+
+        #[test]
+        fn test_replace_record_attachments() {
+            let pool = init_pool();
+            let record_id = Uuid::parse_str("6a8c0e24-7d9f-4b1c-8a4d-3e5f7b9d1f46").unwrap();
+            let file_id = Uuid::parse_str("7b9d1f35-8eaf-4c2d-9b5e-4f6a8c0e2a57").unwrap();
+
+            replace_record_attachments(record_id, &[
+                Attachment { record_id, file_id, filename: "a.png".to_string() },
+                Attachment { record_id, file_id, filename: "b.png".to_string() },
+            ], &pool).unwrap();
+            assert_eq!(select_record_attachments(record_id, &pool).unwrap().len(), 2);
+
+            replace_record_attachments(record_id, &[
+                Attachment { record_id, file_id, filename: "b.png".to_string() },
+            ], &pool).unwrap();
+            let attachments = select_record_attachments(record_id, &pool).unwrap();
+            assert_eq!(attachments.len(), 1);
+            assert_eq!(attachments[0].filename, "b.png");
+        }
+
+        #[test]
+        fn test_delete_record_attachments() {
+            let pool = init_pool();
+            let record_id = Uuid::parse_str("8cae2046-9fb0-4d3e-8c6f-5a7b9d1f3b68").unwrap();
+            let file_id = Uuid::parse_str("9dbf3157-a0c1-4e4f-9d7a-6b8c0e2a4c79").unwrap();
+
+            replace_record_attachments(record_id, &[
+                Attachment { record_id, file_id, filename: "a.png".to_string() },
+            ], &pool).unwrap();
+            delete_record_attachments(record_id, &pool).unwrap();
+
+            assert!(select_record_attachments(record_id, &pool).unwrap().is_empty());
+        }
+    }
+}
@@ -0,0 +1,315 @@
+pub mod service {
+    use std::collections::BTreeMap;
+
+    use chrono::NaiveDate;
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use uuid::Uuid;
+
+    use crate::attribute::queries::{AttributePredicate, AttributeValue, Comparison, delete_attribute, select_attributes, upsert_attribute};
+    use crate::error::{Error, OptionalExt};
+    use crate::events::service::{Event, publish};
+    use crate::record::queries::select_record;
+
+    const NAME_MAX_CHARS: usize = 64;
+    const COMPARISONS: [(&str, Comparison); 6] = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("!=", Comparison::NotEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+        ("=", Comparison::Equal),
+    ];
+
+    fn validate_name(name: &str) -> Result<(), Error> {
+        if name.is_empty() || name.chars().count() > NAME_MAX_CHARS || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(Error::validation("name", format!("Attribute name '{}' must be 1 to {} letters, digits, '_' or '-'", name, NAME_MAX_CHARS)));
+        }
+        Ok(())
+    }
+
+    /// Attributes of a record by name, they are not part of its revisions.
+    pub fn record_attributes(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<BTreeMap<String, AttributeValue>, Error> {
+        select_record(record_id, pool)?;
+        Ok(select_attributes(record_id, pool)?.into_iter().collect())
+    }
+
+    /// Replaces the value of `name`, whatever type it had. A `record` value has to name an existing record.
+    pub fn set_attribute(record_id: Uuid, name: &str, value: AttributeValue, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        validate_name(name)?;
+        select_record(record_id, pool)?;
+        if let AttributeValue::Record(target) = value {
+            if select_record(target, pool).optional()?.is_none() {
+                return Err(Error::validation("value", format!("Record '{}' not found", target)));
+            }
+        }
+        upsert_attribute(record_id, name, &value, pool)?;
+        publish(Event::AttributeSet { id: record_id, name: name.to_string() });
+        Ok(())
+    }
+
+    pub fn unset_attribute(record_id: Uuid, name: &str, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        if !delete_attribute(record_id, name, pool)? {
+            return Err(Error::NotFound(format!("Attribute '{}' of record '{}' not found", name, record_id)));
+        }
+        publish(Event::AttributeUnset { id: record_id, name: name.to_string() });
+        Ok(())
+    }
+
+    /// Parses comma separated predicates such as `due<2026-11-01,priority>=2`. The type of a value
+    /// is inferred: `true` and `false` are bools, then numbers, `YYYY-MM-DD` dates and record ids,
+    /// anything else or a value in double quotes is a string. Commas in double quotes are kept.
+    pub fn parse_filter(filter: &str) -> Result<Vec<AttributePredicate>, Error> {
+        split_outside_quotes(filter)
+            .into_iter()
+            .map(|predicate| predicate.trim())
+            .filter(|predicate| !predicate.is_empty())
+            .map(parse_predicate)
+            .collect()
+    }
+
+    fn split_outside_quotes(filter: &str) -> Vec<&str> {
+        let mut predicates = Vec::new();
+        let mut quoted = false;
+        let mut start = 0;
+        for (position, c) in filter.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                ',' if !quoted => {
+                    predicates.push(&filter[start..position]);
+                    start = position + 1;
+                }
+                _ => {}
+            }
+        }
+        predicates.push(&filter[start..]);
+        predicates
+    }
+
+    fn parse_predicate(predicate: &str) -> Result<AttributePredicate, Error> {
+        // The leftmost operator, the longer one where two start at the same place, so `<=` is not read as `<`.
+        let (position, operator, comparison) = COMPARISONS.iter()
+            .filter_map(|(operator, comparison)| predicate.find(operator).map(|position| (position, *operator, *comparison)))
+            .min_by_key(|(position, operator, _)| (*position, usize::MAX - operator.len()))
+            .ok_or_else(|| Error::validation("filter", format!("Predicate '{}' has none of the operators =, !=, <, <=, >, >=", predicate)))?;
+        let name = predicate[..position].trim();
+        validate_name(name).map_err(|_| Error::validation("filter", format!("Predicate '{}' does not start with an attribute name", predicate)))?;
+        let value = predicate[position + operator.len()..].trim();
+        Ok(AttributePredicate { name: name.to_string(), comparison, value: infer_value(value) })
+    }
+
+    fn infer_value(value: &str) -> AttributeValue {
+        if let Some(quoted) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+            return AttributeValue::String(quoted.to_string());
+        }
+        if let Ok(bool) = value.parse::<bool>() {
+            return AttributeValue::Bool(bool);
+        }
+        if let Some(number) = value.parse::<f64>().ok().filter(|number| number.is_finite()) {
+            return AttributeValue::Number(number);
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            return AttributeValue::Date(date);
+        }
+        if let Ok(id) = Uuid::parse_str(value) {
+            return AttributeValue::Record(id);
+        }
+        AttributeValue::String(value.to_string())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use chrono::NaiveDate;
+        use uuid::Uuid;
+
+        use crate::attribute::queries::{AttributeValue, Comparison, select_attributes};
+        use crate::attribute::service::{parse_filter, record_attributes, set_attribute, unset_attribute};
+        use crate::error::Error;
+        use crate::record::service::remove_record;
+        use crate::testing::init_pool;
+
+        #[test]
+        fn test_parse_filter() {
+            let predicates = parse_filter("due < 2026-11-01, priority>=2,done=false,status!=\"2\",owner=alice").unwrap();
+            assert_eq!(predicates.len(), 5);
+            assert_eq!((predicates[0].name.as_str(), predicates[0].comparison), ("due", Comparison::Less));
+            assert_eq!(predicates[0].value, AttributeValue::Date(NaiveDate::from_ymd_opt(2026, 11, 1).unwrap()));
+            assert_eq!((predicates[1].comparison, &predicates[1].value), (Comparison::GreaterOrEqual, &AttributeValue::Number(2.0)));
+            assert_eq!(predicates[2].value, AttributeValue::Bool(false));
+            assert_eq!((predicates[3].comparison, &predicates[3].value), (Comparison::NotEqual, &AttributeValue::String(String::from("2"))));
+            assert_eq!(predicates[4].value, AttributeValue::String(String::from("alice")));
+
+            let predicates = parse_filter("status=\"a,b\",owner=alice").unwrap();
+            assert_eq!(predicates.len(), 2);
+            assert_eq!(predicates[0].value, AttributeValue::String(String::from("a,b")));
+
+            assert!(matches!(parse_filter("due"), Err(Error::Validation { parameter, .. }) if parameter == "filter"));
+            assert!(matches!(parse_filter("<2"), Err(Error::Validation { parameter, .. }) if parameter == "filter"));
+        }
+
+        #[test]
+        fn test_set_attribute() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("e26ed5b1-28f0-4449-bbc1-2e5d48d3c26a").unwrap();
+
+            set_attribute(id, "priority", AttributeValue::Number(2.0), &pool).unwrap();
+            set_attribute(id, "status", AttributeValue::String(String::from("open")), &pool).unwrap();
+            set_attribute(id, "status", AttributeValue::Bool(true), &pool).unwrap();
+            let attributes = record_attributes(id, &pool).unwrap();
+            assert_eq!(attributes.len(), 2);
+            assert_eq!(attributes["status"], AttributeValue::Bool(true));
+
+            unset_attribute(id, "status", &pool).unwrap();
+            assert!(!record_attributes(id, &pool).unwrap().contains_key("status"));
+            assert!(matches!(unset_attribute(id, "status", &pool), Err(Error::NotFound(_))));
+
+            remove_record(id, &pool).unwrap();
+            assert!(select_attributes(id, &pool).unwrap().is_empty());
+        }
+
+        #[test]
+        fn test_set_attribute_invalid() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("e26ed5b1-28f0-4449-bbc1-2e5d48d3c26a").unwrap();
+
+            assert!(matches!(set_attribute(id, "due date", AttributeValue::Bool(true), &pool), Err(Error::Validation { parameter, .. }) if parameter == "name"));
+            assert!(matches!(set_attribute(Uuid::new_v4(), "due", AttributeValue::Bool(true), &pool), Err(Error::NotFound(_))));
+            assert!(matches!(set_attribute(id, "parent", AttributeValue::Record(Uuid::new_v4()), &pool), Err(Error::Validation { parameter, .. }) if parameter == "value"));
+            assert!(record_attributes(id, &pool).unwrap().is_empty());
+        }
+    }
+}
+
+pub mod queries {
+    use chrono::NaiveDate;
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use r2d2_sqlite::rusqlite::{params, Row, ToSql};
+    use r2d2_sqlite::rusqlite::types::ToSqlOutput;
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+    use uuid::Uuid;
+
+    use crate::error::{Error, parse_column};
+
+    /// Serialized as `{"type": "date", "value": "2026-11-01"}`.
+    #[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+    #[serde(tag = "type", content = "value", rename_all = "lowercase")]
+    pub enum AttributeValue {
+        String(String),
+        Number(f64),
+        Date(NaiveDate),
+        Bool(bool),
+        /// Id of another record.
+        Record(Uuid),
+    }
+
+    impl AttributeValue {
+        pub fn type_name(&self) -> &'static str {
+            match self {
+                AttributeValue::String(_) => "string",
+                AttributeValue::Number(_) => "number",
+                AttributeValue::Date(_) => "date",
+                AttributeValue::Bool(_) => "bool",
+                AttributeValue::Record(_) => "record",
+            }
+        }
+    }
+
+    /// Numbers are stored as reals and bools as integers, which SQLite orders before text;
+    /// dates as `YYYY-MM-DD` so they compare as text.
+    impl ToSql for AttributeValue {
+        fn to_sql(&self) -> r2d2_sqlite::rusqlite::Result<ToSqlOutput<'_>> {
+            Ok(match self {
+                AttributeValue::String(value) => ToSqlOutput::from(value.as_str()),
+                AttributeValue::Number(value) => ToSqlOutput::from(*value),
+                AttributeValue::Date(value) => ToSqlOutput::from(value.format("%Y-%m-%d").to_string()),
+                AttributeValue::Bool(value) => ToSqlOutput::from(*value),
+                AttributeValue::Record(value) => ToSqlOutput::from(value.to_string()),
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Comparison {
+        Equal,
+        NotEqual,
+        Less,
+        LessOrEqual,
+        Greater,
+        GreaterOrEqual,
+    }
+
+    impl Comparison {
+        fn operator(&self) -> &'static str {
+            match self {
+                Comparison::Equal => "=",
+                Comparison::NotEqual => "!=",
+                Comparison::Less => "<",
+                Comparison::LessOrEqual => "<=",
+                Comparison::Greater => ">",
+                Comparison::GreaterOrEqual => ">=",
+            }
+        }
+    }
+
+    /// Holds for records which have the attribute with a value of the same type comparing as
+    /// asked, records without it never match.
+    pub struct AttributePredicate {
+        pub name: String,
+        pub comparison: Comparison,
+        pub value: AttributeValue,
+    }
+
+    impl AttributePredicate {
+        /// Condition on `records_read rr`, `name`, `value` and the type of `value` are the parameters
+        /// at `index`, `index + 1` and `index + 2`.
+        pub fn condition(&self, index: usize) -> String {
+            format!("EXISTS (SELECT 1 FROM records_attributes ra WHERE ra.record_id = rr.id AND ra.name = ?{} AND ra.value {} ?{} AND ra.type = ?{})", index, self.comparison.operator(), index + 1, index + 2)
+        }
+    }
+
+    /// Value of the attribute `name` of `records_read rr`, `name` is the parameter at `index`.
+    pub fn attribute_expression(index: usize) -> String {
+        format!("(SELECT ra.value FROM records_attributes ra WHERE ra.record_id = rr.id AND ra.name = ?{})", index)
+    }
+
+    fn read_attribute(row: &Row) -> r2d2_sqlite::rusqlite::Result<(String, AttributeValue)> {
+        let value = match row.get::<_, String>(1)?.as_str() {
+            "number" => AttributeValue::Number(row.get(2)?),
+            "date" => AttributeValue::Date(parse_column(row, 2)?),
+            "bool" => AttributeValue::Bool(row.get(2)?),
+            "record" => AttributeValue::Record(parse_column(row, 2)?),
+            _ => AttributeValue::String(row.get(2)?),
+        };
+        Ok((row.get(0)?, value))
+    }
+
+    pub fn upsert_attribute(record_id: Uuid, name: &str, value: &AttributeValue, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("INSERT INTO records_attributes (record_id, name, type, value) VALUES (?1, ?2, ?3, ?4)")?;
+        stmt.execute(params![record_id.to_string(), name, value.type_name(), value])?;
+        Ok(())
+    }
+
+    pub fn select_attributes(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<(String, AttributeValue)>, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("SELECT name, type, value FROM records_attributes WHERE record_id = ?1 ORDER BY name")?;
+        let rows = stmt.query_map([record_id.to_string().as_str()], read_attribute)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns whether there was an attribute to delete.
+    pub fn delete_attribute(record_id: Uuid, name: &str, pool: &Pool<SqliteConnectionManager>) -> Result<bool, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("DELETE FROM records_attributes WHERE record_id = ?1 AND name = ?2")?;
+        Ok(stmt.execute([record_id.to_string().as_str(), name])? > 0)
+    }
+
+    pub fn delete_record_attributes(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("DELETE FROM records_attributes WHERE record_id = ?1")?;
+        stmt.execute([record_id.to_string().as_str()])?;
+        Ok(())
+    }
+}
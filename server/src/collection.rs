@@ -0,0 +1,287 @@
+pub mod service {
+    use chrono::{DateTime, SubsecRound, Utc};
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    use crate::attribute::service::parse_filter;
+    use crate::collection::queries::{Collection, delete_collection, insert_collection, select_collection, select_collections, update_collection};
+    use crate::error::Error;
+    use crate::record::service::{list_records, parse_tags, RecordSort, RecordView, RequestListRecords, ResponseRecords, SortOrder};
+
+    const NAME_MAX_CHARS: usize = 200;
+
+    /// The part of a records listing which is saved, see [`RequestListRecords`] for each field.
+    #[derive(Deserialize, Serialize, Clone, Default, PartialEq, Debug)]
+    pub struct CollectionQuery {
+        pub tags: Option<String>,
+        pub text: Option<String>,
+        pub filter: Option<String>,
+        #[serde(default)]
+        pub sort: RecordSort,
+        #[serde(default)]
+        pub order: SortOrder,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct RequestCollection {
+        pub name: String,
+        #[serde(default)]
+        pub query: CollectionQuery,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct ResponseCollection {
+        pub id: Uuid,
+        pub name: String,
+        pub query: CollectionQuery,
+        pub created_at: DateTime<Utc>,
+        pub updated_at: DateTime<Utc>,
+    }
+
+    impl From<Collection> for ResponseCollection {
+        fn from(collection: Collection) -> Self {
+            Self {
+                id: collection.id,
+                name: collection.name,
+                query: collection.query,
+                created_at: collection.created_at,
+                updated_at: collection.updated_at,
+            }
+        }
+    }
+
+    /// Paging of the records of a collection, like paging of `/api/records`.
+    #[derive(Deserialize, Serialize, Default)]
+    pub struct RequestCollectionRecords {
+        pub limit: Option<usize>,
+        pub cursor: Option<String>,
+        #[serde(default)]
+        pub view: RecordView,
+        pub fields: Option<String>,
+    }
+
+    /// A query which cannot be evaluated is refused when it is saved rather than every time it is listed.
+    fn validate(collection: &RequestCollection) -> Result<(), Error> {
+        let name = collection.name.trim();
+        if name.is_empty() || name.chars().count() > NAME_MAX_CHARS {
+            return Err(Error::validation("name", format!("Name must be 1 to {} characters", NAME_MAX_CHARS)));
+        }
+        if let Some(tags) = &collection.query.tags {
+            parse_tags(tags)?;
+        }
+        if let Some(filter) = &collection.query.filter {
+            parse_filter(filter)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_collection(collection: RequestCollection, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseCollection, Error> {
+        validate(&collection)?;
+        // Stored in seconds, so the response matches what is read back later.
+        let now = Utc::now().trunc_subsecs(0);
+        let collection = Collection {
+            id: Uuid::new_v4(),
+            name: collection.name.trim().to_string(),
+            query: collection.query,
+            created_at: now,
+            updated_at: now,
+        };
+        insert_collection(&collection, pool)?;
+        Ok(collection.into())
+    }
+
+    pub fn change_collection(collection_id: Uuid, collection: RequestCollection, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseCollection, Error> {
+        validate(&collection)?;
+        let current = select_collection(collection_id, pool)?;
+        let collection = Collection {
+            name: collection.name.trim().to_string(),
+            query: collection.query,
+            updated_at: Utc::now().trunc_subsecs(0),
+            ..current
+        };
+        update_collection(&collection, pool)?;
+        Ok(collection.into())
+    }
+
+    pub fn get_collection(collection_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseCollection, Error> {
+        select_collection(collection_id, pool).map(ResponseCollection::from)
+    }
+
+    pub fn list_collections(pool: &Pool<SqliteConnectionManager>) -> Result<Vec<ResponseCollection>, Error> {
+        Ok(select_collections(pool)?.into_iter().map(ResponseCollection::from).collect())
+    }
+
+    pub fn remove_collection(collection_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        if !delete_collection(collection_id, pool)? {
+            return Err(Error::NotFound(format!("Collection '{}' not found", collection_id)));
+        }
+        Ok(())
+    }
+
+    /// Evaluates the saved query now, so the records are whatever matches at this moment.
+    pub fn collection_records(collection_id: Uuid, page: RequestCollectionRecords, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseRecords, Error> {
+        let query = select_collection(collection_id, pool)?.query;
+        list_records(RequestListRecords {
+            limit: page.limit,
+            cursor: page.cursor,
+            sort: query.sort,
+            order: query.order,
+            view: page.view,
+            fields: page.fields,
+            filter: query.filter,
+            tags: query.tags,
+            text: query.text,
+        }, pool)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use uuid::Uuid;
+
+        use crate::attribute::queries::AttributeValue;
+        use crate::attribute::service::set_attribute;
+        use crate::collection::service::{add_collection, change_collection, collection_records, CollectionQuery, get_collection, list_collections, remove_collection, RequestCollection, RequestCollectionRecords};
+        use crate::error::Error;
+        use crate::record::service::{RecordSort, SortOrder};
+        use crate::testing::init_pool;
+
+        #[test]
+        fn test_collection_records() {
+            let pool = init_pool();
+            let ids = ["e26ed5b1-28f0-4449-bbc1-2e5d48d3c26a", "95a0243b-d0ef-4c0b-afe2-a1292e3a8077", "18c74eda-732f-44af-bf4a-4f923b153dc9"].map(|id| Uuid::parse_str(id).unwrap());
+            for (id, (status, priority)) in ids.iter().zip([("open", 2.0), ("done", 3.0), ("open", 1.0)]) {
+                set_attribute(*id, "status", AttributeValue::String(status.to_string()), &pool).unwrap();
+                set_attribute(*id, "priority", AttributeValue::Number(priority), &pool).unwrap();
+            }
+            let collection = add_collection(RequestCollection {
+                name: String::from("Open tasks tagged hashtags"),
+                query: CollectionQuery {
+                    tags: Some(String::from("#hashtags")),
+                    text: Some(String::from("PLAYGROUND")),
+                    filter: Some(String::from("status=open")),
+                    sort: RecordSort::Attribute(String::from("priority")),
+                    order: SortOrder::Desc,
+                },
+            }, &pool).unwrap();
+
+            let page = collection_records(collection.id, RequestCollectionRecords { fields: Some(String::from("id")), ..Default::default() }, &pool).unwrap();
+            let found: Vec<&serde_json::Value> = page.records.iter().map(|record| &record["id"]).collect();
+            assert_eq!(found, vec![&serde_json::json!(ids[0]), &serde_json::json!(ids[2])]);
+
+            set_attribute(ids[1], "status", AttributeValue::String(String::from("open")), &pool).unwrap();
+            assert_eq!(collection_records(collection.id, RequestCollectionRecords::default(), &pool).unwrap().records.len(), 3);
+
+            let changed = change_collection(collection.id, RequestCollection {
+                name: String::from("Untagged"),
+                query: CollectionQuery { tags: Some(String::from("-hashtags")), ..Default::default() },
+            }, &pool).unwrap();
+            assert_eq!(changed.created_at, collection.created_at);
+            assert!(collection_records(collection.id, RequestCollectionRecords::default(), &pool).unwrap().records.is_empty());
+        }
+
+        #[test]
+        fn test_collection_invalid() {
+            let pool = init_pool();
+            let invalid = |name: &str, query: CollectionQuery| match add_collection(RequestCollection { name: name.to_string(), query }, &pool) {
+                Err(Error::Validation { parameter, .. }) => parameter,
+                _ => panic!("collection should be invalid"),
+            };
+            assert_eq!(invalid(" ", CollectionQuery::default()), "name");
+            assert_eq!(invalid("Due", CollectionQuery { filter: Some(String::from("due")), ..Default::default() }), "filter");
+            assert_eq!(invalid("Tags", CollectionQuery { tags: Some(String::from("a#b")), ..Default::default() }), "tags");
+            assert!(list_collections(&pool).unwrap().is_empty());
+        }
+
+        #[test]
+        fn test_remove_collection() {
+            let pool = init_pool();
+            let collection = add_collection(RequestCollection { name: String::from("All"), query: CollectionQuery::default() }, &pool).unwrap();
+            assert_eq!(get_collection(collection.id, &pool).unwrap().name, "All");
+
+            remove_collection(collection.id, &pool).unwrap();
+            assert!(matches!(get_collection(collection.id, &pool), Err(Error::NotFound(_))));
+            assert!(matches!(collection_records(collection.id, RequestCollectionRecords::default(), &pool), Err(Error::NotFound(_))));
+            assert!(matches!(remove_collection(collection.id, &pool), Err(Error::NotFound(_))));
+        }
+    }
+}
+
+pub mod queries {
+    use chrono::{DateTime, TimeZone, Utc};
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use r2d2_sqlite::rusqlite::{OptionalExtension, params, Row};
+    use r2d2_sqlite::rusqlite::types::Type;
+    use uuid::Uuid;
+
+    use crate::collection::service::CollectionQuery;
+    use crate::error::{Error, parse_column};
+
+    pub struct Collection {
+        pub id: Uuid,
+        pub name: String,
+        pub query: CollectionQuery,
+        pub created_at: DateTime<Utc>,
+        pub updated_at: DateTime<Utc>,
+    }
+
+    fn read_collection(row: &Row) -> r2d2_sqlite::rusqlite::Result<Collection> {
+        let query: serde_json::Value = row.get(2)?;
+        Ok(Collection {
+            id: parse_column(row, 0)?,
+            name: row.get(1)?,
+            query: serde_json::from_value(query).map_err(|err| r2d2_sqlite::rusqlite::Error::FromSqlConversionFailure(2, Type::Text, err.into()))?,
+            created_at: Utc.timestamp_opt(row.get(3)?, 0).unwrap(),
+            updated_at: Utc.timestamp_opt(row.get(4)?, 0).unwrap(),
+        })
+    }
+
+    pub fn insert_collection(collection: &Collection, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("INSERT INTO collections (id, name, query, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        stmt.execute(params![
+            collection.id.to_string(),
+            collection.name,
+            serde_json::to_string(&collection.query).unwrap(),
+            collection.created_at.timestamp(),
+            collection.updated_at.timestamp(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn update_collection(collection: &Collection, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("UPDATE collections SET name = ?2, query = ?3, updated_at = ?4 WHERE id = ?1")?;
+        stmt.execute(params![
+            collection.id.to_string(),
+            collection.name,
+            serde_json::to_string(&collection.query).unwrap(),
+            collection.updated_at.timestamp(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn select_collection(collection_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<Collection, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("SELECT id, name, query, created_at, updated_at FROM collections WHERE id = ?1")?;
+        stmt.query_row([collection_id.to_string().as_str()], read_collection)
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("Collection '{}' not found", collection_id)))
+    }
+
+    pub fn select_collections(pool: &Pool<SqliteConnectionManager>) -> Result<Vec<Collection>, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("SELECT id, name, query, created_at, updated_at FROM collections ORDER BY name, id")?;
+        let rows = stmt.query_map([], read_collection)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns whether there was a collection to delete.
+    pub fn delete_collection(collection_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<bool, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("DELETE FROM collections WHERE id = ?1")?;
+        Ok(stmt.execute([collection_id.to_string().as_str()])? > 0)
+    }
+}
@@ -0,0 +1,29 @@
+create table records_attachments
+(
+    record_id text not null on conflict fail,
+    file_id   text not null on conflict fail,
+    filename  text not null on conflict fail,
+    constraint records_attachments_pk
+        primary key (record_id, file_id, filename) on conflict ignore
+);
+
+create index records_attachments_file_id_filename_index
+    on records_attachments (file_id, filename);
+
+insert into records_attachments (record_id, file_id, filename)
+select record_id,
+       substr(path, 1, 36),
+       case
+           when substr(path, 38, 9) = 'download/' then substr(path, 47)
+           when substr(path, 38, 5) = 'meta/' then substr(path, 43)
+           else substr(path, 38)
+           end
+from (select rr.id                                                                 as record_id,
+             substr(jt.value, instr(jt.value, '/api/file/') + length('/api/file/')) as path
+      from records_read rr,
+           json_tree(rr.body) jt
+      where jt.key in ('src', 'url')
+        and jt.type = 'text'
+        and instr(jt.value, '/api/file/') > 0)
+where length(path) > 37
+  and substr(path, 37, 1) = '/';
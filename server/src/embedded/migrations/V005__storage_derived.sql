@@ -0,0 +1,11 @@
+create table storage_derived
+(
+    source_hash text    not null on conflict fail,
+    variant     text    not null on conflict fail,
+    mime_type   text    not null on conflict fail,
+    body        blob    not null on conflict fail,
+    size        integer not null on conflict fail,
+    created_at  integer not null on conflict fail,
+    constraint storage_derived_pk
+        primary key (source_hash, variant) on conflict replace
+);
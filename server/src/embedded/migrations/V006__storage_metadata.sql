@@ -0,0 +1,2 @@
+alter table storage
+    add column metadata text not null default '{}';
@@ -0,0 +1,10 @@
+drop view records_read;
+
+CREATE VIEW records_read AS
+SELECT rw.id,
+       rw.created_at AS updated_at,
+       rw.mime_type,
+       rw.body,
+       (SELECT MIN(first.created_at) FROM records_write first WHERE first.id = rw.id) AS created_at
+FROM records_write rw
+WHERE (rw.id, rw.created_at) IN (SELECT id, MAX(created_at) FROM records_write GROUP BY id);
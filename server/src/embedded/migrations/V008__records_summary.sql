@@ -0,0 +1,45 @@
+alter table records_write
+    add column title text not null default '';
+
+alter table records_write
+    add column excerpt text not null default '';
+
+-- Approximates the summary the server derives on save from the text of each top-level Lexical
+-- block: the first heading, otherwise the first block, becomes the title. Every later revision
+-- gets the exact value.
+update records_write
+set title   = coalesce(
+        (select (select group_concat(t.value, '') from json_tree(b.value) t where t.key = 'text' and t.type = 'text')
+         from json_each(records_write.body, '$.editorState.root.children') b
+         where json_extract(b.value, '$.type') = 'heading'
+         order by b.key
+         limit 1),
+        (select (select group_concat(t.value, '') from json_tree(b.value) t where t.key = 'text' and t.type = 'text') as text
+         from json_each(records_write.body, '$.editorState.root.children') b
+         where text is not null
+         order by b.key
+         limit 1),
+        ''),
+    excerpt = coalesce(
+        (select substr(group_concat(text, ' '), 1, 240)
+         from (select (select group_concat(t.value, '') from json_tree(b.value) t where t.key = 'text' and t.type = 'text') as text
+               from json_each(records_write.body, '$.editorState.root.children') b
+               where json_extract(b.value, '$.type') != 'heading'
+               order by b.key)
+         where text is not null),
+        '')
+where mime_type = 'note/lexical'
+  and json_valid(body);
+
+drop view records_read;
+
+CREATE VIEW records_read AS
+SELECT rw.id,
+       rw.created_at AS updated_at,
+       rw.mime_type,
+       rw.body,
+       (SELECT MIN(first.created_at) FROM records_write first WHERE first.id = rw.id) AS created_at,
+       rw.title,
+       rw.excerpt
+FROM records_write rw
+WHERE (rw.id, rw.created_at) IN (SELECT id, MAX(created_at) FROM records_write GROUP BY id);
@@ -0,0 +1,18 @@
+alter table records_write
+    add column author text;
+
+drop view records_read;
+
+CREATE VIEW records_read AS
+SELECT rw.id,
+       rw.created_at AS updated_at,
+       rw.mime_type,
+       rw.body,
+       (SELECT MIN(first.created_at) FROM records_write first WHERE first.id = rw.id) AS created_at,
+       rw.title,
+       rw.excerpt,
+       (SELECT COUNT(*) FROM records_write revision WHERE revision.id = rw.id) AS revision_count,
+       (SELECT first.author FROM records_write first WHERE first.id = rw.id ORDER BY first.created_at LIMIT 1) AS created_by,
+       rw.author AS updated_by
+FROM records_write rw
+WHERE (rw.id, rw.created_at) IN (SELECT id, MAX(created_at) FROM records_write GROUP BY id);
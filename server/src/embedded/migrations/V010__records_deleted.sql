@@ -0,0 +1,10 @@
+create table records_deleted
+(
+    id         text    not null on conflict fail,
+    deleted_at integer not null on conflict fail,
+    constraint records_deleted_pk
+        primary key (id) on conflict replace
+);
+
+create index records_deleted_deleted_at_id_index
+    on records_deleted (deleted_at asc, id asc);
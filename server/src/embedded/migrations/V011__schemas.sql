@@ -0,0 +1,8 @@
+create table schemas
+(
+    mime_type  text    not null on conflict fail,
+    schema     text    not null on conflict fail,
+    updated_at integer not null on conflict fail,
+    constraint schemas_pk
+        primary key (mime_type) on conflict replace
+);
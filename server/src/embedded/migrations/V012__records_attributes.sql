@@ -0,0 +1,12 @@
+create table records_attributes
+(
+    record_id text not null on conflict fail,
+    name      text not null on conflict fail,
+    type      text not null on conflict fail,
+    value          not null on conflict fail,
+    constraint records_attributes_pk
+        primary key (record_id, name) on conflict replace
+);
+
+create index records_attributes_name_value_index
+    on records_attributes (name, value);
@@ -0,0 +1,10 @@
+create table collections
+(
+    id         text    not null on conflict fail,
+    name       text    not null on conflict fail,
+    query      text    not null on conflict fail,
+    created_at integer not null on conflict fail,
+    updated_at integer not null on conflict fail,
+    constraint collections_pk
+        primary key (id)
+);
@@ -0,0 +1,35 @@
+-- The editor used to save its bare Lexical state as `note`, which is neither validated, merged
+-- nor summarized. Such revisions become `note/lexical` with the state under `editorState`.
+update records_write
+set mime_type = 'note/lexical',
+    body      = json_object('editorState', json(body))
+where mime_type = 'note'
+  and json_valid(body)
+  and json_type(body, '$.root') = 'object';
+
+-- Summaries as the server derives them, see V008.
+update records_write
+set title   = coalesce(
+        (select (select group_concat(t.value, '') from json_tree(b.value) t where t.key = 'text' and t.type = 'text')
+         from json_each(records_write.body, '$.editorState.root.children') b
+         where json_extract(b.value, '$.type') = 'heading'
+         order by b.key
+         limit 1),
+        (select (select group_concat(t.value, '') from json_tree(b.value) t where t.key = 'text' and t.type = 'text') as text
+         from json_each(records_write.body, '$.editorState.root.children') b
+         where text is not null
+         order by b.key
+         limit 1),
+        ''),
+    excerpt = coalesce(
+        (select substr(group_concat(text, ' '), 1, 240)
+         from (select (select group_concat(t.value, '') from json_tree(b.value) t where t.key = 'text' and t.type = 'text') as text
+               from json_each(records_write.body, '$.editorState.root.children') b
+               where json_extract(b.value, '$.type') != 'heading'
+               order by b.key)
+         where text is not null),
+        '')
+where mime_type = 'note/lexical'
+  and title = ''
+  and excerpt = ''
+  and json_valid(body);
@@ -0,0 +1,78 @@
+-- Revisions get a number from one sequence shared by every record and tombstone, it is the
+-- ETag of a revision and orders the change feed, two saves within a second no longer collide.
+create table records_write_revision
+(
+    revision   integer primary key autoincrement,
+    id         blob    not null on conflict fail,
+    created_at integer not null on conflict fail,
+    mime_type  text    not null on conflict fail,
+    body       blob    not null on conflict fail,
+    title      text    not null default '',
+    excerpt    text    not null default '',
+    author     text
+);
+
+insert into records_write_revision (id, created_at, mime_type, body, title, excerpt, author)
+select id, created_at, mime_type, body, title, excerpt, author
+from records_write
+order by created_at, id;
+
+drop view records_read;
+
+drop table records_write;
+
+alter table records_write_revision
+    rename to records_write;
+
+create unique index records_write_id_revision_uindex
+    on records_write (id asc, revision desc);
+
+create index records_write_mime_type_created_at_index
+    on records_write (mime_type asc, created_at desc);
+
+create table records_deleted_revision
+(
+    id         text    not null on conflict fail,
+    deleted_at integer not null on conflict fail,
+    revision   integer not null on conflict fail,
+    constraint records_deleted_pk
+        primary key (id) on conflict replace
+);
+
+insert into records_deleted_revision (id, deleted_at, revision)
+select id,
+       deleted_at,
+       coalesce((select seq from sqlite_sequence where name = 'records_write'), 0) + row_number() over (order by deleted_at, id)
+from records_deleted;
+
+drop table records_deleted;
+
+alter table records_deleted_revision
+    rename to records_deleted;
+
+create unique index records_deleted_revision_uindex
+    on records_deleted (revision asc);
+
+-- Tombstones take their revision from the sequence of records_write.
+delete from sqlite_sequence
+where name = 'records_write';
+
+insert into sqlite_sequence (name, seq)
+select 'records_write', max(revision)
+from (select revision from records_write union all select revision from records_deleted)
+having count(*) > 0;
+
+CREATE VIEW records_read AS
+SELECT rw.id,
+       rw.created_at AS updated_at,
+       rw.mime_type,
+       rw.body,
+       (SELECT MIN(first.created_at) FROM records_write first WHERE first.id = rw.id) AS created_at,
+       rw.title,
+       rw.excerpt,
+       (SELECT COUNT(*) FROM records_write revision WHERE revision.id = rw.id) AS revision_count,
+       (SELECT first.author FROM records_write first WHERE first.id = rw.id ORDER BY first.revision LIMIT 1) AS created_by,
+       rw.author AS updated_by,
+       rw.revision
+FROM records_write rw
+WHERE rw.revision IN (SELECT MAX(revision) FROM records_write GROUP BY id);
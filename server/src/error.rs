@@ -0,0 +1,254 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentType, ETag, EntityTag, RETRY_AFTER};
+use r2d2_sqlite::rusqlite;
+use r2d2_sqlite::rusqlite::{ErrorCode, Row};
+use r2d2_sqlite::rusqlite::types::Type;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::record::record::{ErrPrecondition, PreconditionFailure};
+use crate::record::service::ResponseRecord;
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+/// Seconds a client should wait before retrying when the database is busy.
+const RETRY_AFTER_SECONDS: u32 = 1;
+
+/// Every way an operation of Think can fail. Over HTTP each variant is an RFC 9457
+/// problem whose `type` never changes, so clients can match on it instead of the wording.
+#[derive(Debug)]
+pub enum Error {
+    /// A record or blob which does not exist.
+    NotFound(String),
+    /// A request which cannot be acted upon, `parameter` names the offending part.
+    Validation { parameter: String, detail: String },
+    /// A save whose `If-Match` does not hold for the latest revision.
+    Conflict(Box<ErrPrecondition>),
+    /// A blob the storage refuses to take or to process.
+    Storage(StorageFailure, String),
+    /// SQLite failed, `busy` when another connection holds the lock or the pool ran out of
+    /// connections, which is worth retrying.
+    Database { busy: bool, detail: String },
+    /// Stored data no longer matches what was written.
+    Integrity(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageFailure {
+    QuotaExceeded,
+    TooLarge,
+    UnsupportedMedia,
+    /// Another blob already has the id and filename.
+    Exists,
+}
+
+impl Error {
+    pub fn validation(parameter: &str, detail: impl Into<String>) -> Self {
+        Error::Validation { parameter: parameter.to_string(), detail: detail.into() }
+    }
+
+    fn kind(&self) -> (&'static str, &'static str) {
+        match self {
+            Error::NotFound(_) => ("not-found", "Not found"),
+            Error::Validation { .. } => ("validation", "Invalid request"),
+            Error::Conflict(_) => ("conflict", "Revision conflict"),
+            Error::Storage(StorageFailure::QuotaExceeded, _) => ("quota-exceeded", "Storage quota exceeded"),
+            Error::Storage(StorageFailure::TooLarge, _) => ("too-large", "Upload too large"),
+            Error::Storage(StorageFailure::UnsupportedMedia, _) => ("unsupported-media", "Unsupported media"),
+            Error::Storage(StorageFailure::Exists, _) => ("file-exists", "File exists"),
+            Error::Database { busy: true, .. } => ("database-busy", "Database busy"),
+            Error::Database { busy: false, .. } => ("database", "Database failure"),
+            Error::Integrity(_) => ("integrity", "Integrity failure"),
+        }
+    }
+
+    fn detail(&self) -> &str {
+        match self {
+            Error::NotFound(detail)
+            | Error::Validation { detail, .. }
+            | Error::Storage(_, detail)
+            | Error::Database { detail, .. }
+            | Error::Integrity(detail) => detail,
+            Error::Conflict(precondition) => &precondition.err,
+        }
+    }
+
+    /// The problem body, `parameter` and the conflict's `id`, `current` and `conflicts` are extensions.
+    pub fn problem(&self) -> serde_json::Value {
+        let (kind, title) = self.kind();
+        let mut problem = serde_json::json!({
+            "type": format!("urn:think:problem:{}", kind),
+            "title": title,
+            "status": self.status_code().as_u16(),
+            "detail": self.detail(),
+        });
+        match self {
+            Error::Validation { parameter, .. } => {
+                problem["parameter"] = serde_json::json!(parameter);
+            }
+            Error::Conflict(precondition) => {
+                problem["id"] = serde_json::json!(precondition.id);
+                problem["current"] = serde_json::json!(precondition.current);
+                if !precondition.conflicts.is_empty() {
+                    problem["conflicts"] = serde_json::json!(precondition.conflicts);
+                }
+            }
+            _ => {}
+        }
+        problem
+    }
+}
+
+/// Body of every error response, as [`Error::problem`] builds it. Only described in the OpenAPI document.
+#[derive(Serialize, ToSchema)]
+pub struct Problem {
+    /// `urn:think:problem:{kind}`, stable across releases.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// The offending part of an invalid request.
+    pub parameter: Option<String>,
+    /// The record whose revision conflicts.
+    pub id: Option<Uuid>,
+    /// The latest revision of the conflicting record.
+    pub current: Option<ResponseRecord>,
+    /// Blocks both sides changed, with their `index`, `base`, `ours` and `theirs`.
+    pub conflicts: Option<Vec<serde_json::Value>>,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.detail())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Validation { .. } => StatusCode::BAD_REQUEST,
+            Error::Conflict(precondition) => match precondition.failure {
+                PreconditionFailure::Required => StatusCode::PRECONDITION_REQUIRED,
+                PreconditionFailure::Stale => StatusCode::CONFLICT,
+                PreconditionFailure::Unknown => StatusCode::PRECONDITION_FAILED,
+            },
+            Error::Storage(StorageFailure::QuotaExceeded, _) => StatusCode::INSUFFICIENT_STORAGE,
+            Error::Storage(StorageFailure::TooLarge, _) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Storage(StorageFailure::UnsupportedMedia, _) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::Storage(StorageFailure::Exists, _) => StatusCode::CONFLICT,
+            Error::Database { busy: true, .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Database { busy: false, .. } | Error::Integrity(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.insert_header(ContentType(PROBLEM_CONTENT_TYPE.parse().unwrap()));
+        match self {
+            Error::Conflict(precondition) => {
+                if let Some(current) = &precondition.current {
+                    response.insert_header(ETag(EntityTag::new_strong(current.revision.to_string())));
+                }
+            }
+            Error::Database { busy: true, .. } => {
+                response.insert_header((RETRY_AFTER, RETRY_AFTER_SECONDS));
+            }
+            _ => {}
+        }
+        response.body(self.problem().to_string())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        let busy = matches!(err.sqlite_error_code(), Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked));
+        Error::Database { busy, detail: err.to_string() }
+    }
+}
+
+/// Only raised when no connection became free in time.
+impl From<r2d2::Error> for Error {
+    fn from(err: r2d2::Error) -> Self {
+        Error::Database { busy: true, detail: err.to_string() }
+    }
+}
+
+impl From<ErrPrecondition> for Error {
+    fn from(err: ErrPrecondition) -> Self {
+        Error::Conflict(Box::new(err))
+    }
+}
+
+/// Tells a missing record or blob apart from a failure, like `rusqlite::OptionalExtension` does for rows.
+pub trait OptionalExt<T> {
+    fn optional(self) -> Result<Option<T>, Error>;
+}
+
+impl<T> OptionalExt<T> for Result<T, Error> {
+    fn optional(self) -> Result<Option<T>, Error> {
+        match self {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Reads a text column holding an id, a mime type or another value parsed from its text form.
+pub(crate) fn parse_column<T: FromStr>(row: &Row, index: usize) -> rusqlite::Result<T> where T::Err: Display {
+    row.get::<_, String>(index)?
+        .parse()
+        .map_err(|err: T::Err| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, err.to_string().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::ResponseError;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::RETRY_AFTER;
+    use r2d2::Pool;
+    use r2d2_sqlite::SqliteConnectionManager;
+    use uuid::Uuid;
+
+    use crate::error::Error;
+    use crate::record::service::get_record;
+    use crate::testing::init_pool;
+
+    #[test]
+    fn test_problem() {
+        let err = Error::validation("cursor", "Cursor is malformed");
+        let problem = err.problem();
+        assert_eq!(problem["type"], "urn:think:problem:validation");
+        assert_eq!(problem["status"], 400);
+        assert_eq!(problem["detail"], "Cursor is malformed");
+        assert_eq!(problem["parameter"], "cursor");
+        assert_eq!(err.error_response().headers().get("content-type").unwrap(), "application/problem+json");
+    }
+
+    #[test]
+    fn test_locked_database_is_busy() {
+        let pool = init_pool();
+        let path = pool.get().unwrap().path().unwrap().to_str().unwrap().to_string();
+        let lock = pool.get().unwrap();
+        lock.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        let impatient = Pool::new(SqliteConnectionManager::file(&path).with_init(|connection| connection.busy_timeout(Duration::ZERO))).unwrap();
+
+        let err = get_record(Uuid::new_v4(), &impatient).unwrap_err();
+
+        assert!(matches!(err, Error::Database { busy: true, .. }));
+        let response = err.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+        lock.execute_batch("ROLLBACK").unwrap();
+    }
+}
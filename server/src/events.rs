@@ -0,0 +1,98 @@
+pub mod service {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use actix_web::web::Bytes;
+    use futures::channel::mpsc::{channel, Receiver, Sender};
+    use futures::{stream, Stream, StreamExt};
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    /// Events a subscriber may fall behind by before it is dropped.
+    const SUBSCRIBER_BUFFER: usize = 64;
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+    /// Every process-wide change clients may want to refresh on, `revision` is the record's `ETag`.
+    #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum Event {
+        RecordCreated { id: Uuid, revision: i64 },
+        RecordUpdated { id: Uuid, revision: i64 },
+        RecordDeleted { id: Uuid },
+        FileUploaded { id: Uuid, filename: String },
+        FileRenamed { id: Uuid, filename: String, new_id: Uuid, new_filename: String },
+        FileDeleted { id: Uuid, filename: String },
+        AttributeSet { id: Uuid, name: String },
+        AttributeUnset { id: Uuid, name: String },
+    }
+
+    impl Event {
+        fn name(&self) -> &'static str {
+            match self {
+                Event::RecordCreated { .. } => "record_created",
+                Event::RecordUpdated { .. } => "record_updated",
+                Event::RecordDeleted { .. } => "record_deleted",
+                Event::FileUploaded { .. } => "file_uploaded",
+                Event::FileRenamed { .. } => "file_renamed",
+                Event::FileDeleted { .. } => "file_deleted",
+                Event::AttributeSet { .. } => "attribute_set",
+                Event::AttributeUnset { .. } => "attribute_unset",
+            }
+        }
+
+        /// Server-Sent Events frame, the event name lets browsers `addEventListener` per type.
+        fn frame(&self) -> Bytes {
+            Bytes::from(format!("event: {}\ndata: {}\n\n", self.name(), serde_json::to_string(self).unwrap()))
+        }
+    }
+
+    static SUBSCRIBERS: Mutex<Vec<Sender<Event>>> = Mutex::new(Vec::new());
+
+    /// Sends `event` to every subscriber, the ones which went away or cannot keep up are dropped.
+    pub fn publish(event: Event) {
+        SUBSCRIBERS.lock().unwrap().retain_mut(|subscriber| subscriber.try_send(event.clone()).is_ok());
+    }
+
+    pub fn subscribe() -> Receiver<Event> {
+        let (sender, receiver) = channel(SUBSCRIBER_BUFFER);
+        SUBSCRIBERS.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Event frames interleaved with comment lines which keep proxies from closing an idle stream.
+    pub fn stream() -> impl Stream<Item = Bytes> {
+        with_keep_alive(subscribe().map(|event| event.frame()))
+    }
+
+    pub fn with_keep_alive(frames: impl Stream<Item = Bytes>) -> impl Stream<Item = Bytes> {
+        let keep_alive = stream::unfold(actix_web::rt::time::interval(KEEP_ALIVE_INTERVAL), |mut interval| async move {
+            interval.tick().await;
+            Some((Bytes::from_static(b": keep-alive\n\n"), interval))
+        });
+        stream::select(frames, keep_alive)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use futures::StreamExt;
+        use uuid::Uuid;
+
+        use crate::events::service::{Event, publish, subscribe};
+
+        #[actix_web::test]
+        async fn test_publish() {
+            let id = Uuid::parse_str("ebf1a5b7-bc2d-4e6f-9a8b-c24d6e8f0abd").unwrap();
+            let mut receiver = subscribe();
+
+            publish(Event::RecordDeleted { id });
+
+            while let Some(event) = receiver.next().await {
+                if event == (Event::RecordDeleted { id }) {
+                    assert_eq!(event.frame(), format!("event: record_deleted\ndata: {{\"type\":\"record_deleted\",\"id\":\"{}\"}}\n\n", id));
+                    return;
+                }
+            }
+            panic!("subscriber closed before the event arrived");
+        }
+    }
+}
@@ -1,233 +1,747 @@
-use actix_web::{App, HttpResponse, HttpServer, Scope, web};
-use actix_web::http::header::{ContentDisposition, ContentType};
+use actix_web::{App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Scope, web};
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE, ContentDisposition, ContentType, ETag, EntityTag, IfMatch, LINK, X_CONTENT_TYPE_OPTIONS};
 use actix_web::middleware::Logger;
+use clap::Parser;
 use env_logger::Env;
 use futures::StreamExt;
-use r2d2::{ManageConnection, Pool};
-use r2d2_sqlite::SqliteConnectionManager;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::record::record::ErrNoId as err_no_id_for_record;
-use crate::storage::storage::ErrNoId as err_no_id_for_storage;
-use crate::record::service::{add_record, all_records, get_record, remove_record, RequestRecord};
-use crate::storage::storage::service::{RequestDeleteBlob, RequestReadBlob, RequestUploadBlob};
-
-mod record;
-mod storage;
-
-
-async fn get_record_handler(state: web::Data<StateApiRecordsScope>, path: web::Path<Uuid>) -> Result<HttpResponse, err_no_id_for_record>
+use think_core::{events, Think};
+use think_core::attribute::queries::AttributeValue;
+use think_core::error::{Error, OptionalExt, Problem, StorageFailure};
+use think_core::collection::service::{RequestCollection, RequestCollectionRecords};
+use think_core::record::service::{Precondition, RequestListRecords, RequestRecord, ResponseRecord, ResponseRecords};
+use crate::settings::{Cli, Settings, StorageBackend};
+use think_core::replication::service::{PushRow, RequestPull};
+use think_core::storage::storage::service::{RequestDeleteBlob, RequestListBlobs, RequestOrphanBlobs, RequestReadBlob, RequestRenameBlob, RequestSearchBlobs, RequestThumbnailBlob, RequestUploadBlob, ResponseReadMetaDataBlob, ResponseSearchBlobs, ThumbnailFit};
+
+mod openapi;
+mod settings;
+
+const DEFAULT_ORPHAN_GRACE_PERIOD_IN_SECONDS: i64 = 24 * 60 * 60;
+const SCHEMA_CONTENT_TYPE: &str = "application/schema+json";
+
+
+#[utoipa::path(
+    get,
+    path = "/api/records/{record}",
+    tag = "records",
+    params(("record" = Uuid, Path, description = "Id of the record")),
+    responses(
+        (status = 200, description = "Latest revision of the record", body = ResponseRecord,
+            headers(("ETag" = String, description = "The revision, for `If-Match` of the next save"), ("Link" = String, description = "`rel=\"describedby\"` to the schema of the mime type"))),
+        (status = 404, description = "No such record", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_record_handler(state: web::Data<StateApi>, path: web::Path<Uuid>) -> Result<HttpResponse, Error>
 {
     let record_id = path.into_inner();
 
-    let result_record = get_record(record_id, &state.pool);
-    match result_record {
-        Ok(v) => Ok(HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(serde_json::to_string(&v).unwrap())
-        ),
-        Err(e) => Err(e),
+    let record = state.think.records().get(record_id)?;
+    let mut response = HttpResponse::Ok();
+    if state.think.schemas().get(&record.mime_type).optional()?.is_some() {
+        response.insert_header((LINK, schema_link(&record.mime_type)));
     }
+    Ok(response
+        .content_type(ContentType::json())
+        .insert_header(ETag(EntityTag::new_strong(record.revision.to_string())))
+        .body(serde_json::to_string(&record).unwrap())
+    )
 }
 
-async fn get_records_handler(state: web::Data<StateApiRecordsScope>) -> HttpResponse
+#[utoipa::path(
+    get,
+    path = "/api/records",
+    tag = "records",
+    params(RequestListRecords),
+    responses(
+        (status = 200, description = "A page of records, limited to the requested fields", body = Vec<ResponseRecord>,
+            headers(("Link" = String, description = "`rel=\"next\"` to the next page and `rel=\"describedby\"` to the schema of each mime type on the page"))),
+        (status = 400, description = "Malformed query, cursor or filter", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_records_handler(state: web::Data<StateApi>, req: HttpRequest, query: web::Query<RequestListRecords>) -> Result<HttpResponse, Error>
 {
-    let records = all_records(&state.pool);
-    HttpResponse::Ok()
-        .insert_header(ContentType::json())
-        .json(records)
+    let page = state.think.records().list(query.into_inner())?;
+    records_page_response(&state, &req, page)
+}
+
+/// The body stays a plain array of records, the next page is announced through a `Link` header
+/// as is the schema of every mime type on the page which has one.
+fn records_page_response(state: &StateApi, req: &HttpRequest, page: ResponseRecords) -> Result<HttpResponse, Error> {
+    let mut response = HttpResponse::Ok();
+    response.insert_header(ContentType::json());
+    if let Some(cursor) = page.next_cursor {
+        let cursor = format!("cursor={}", cursor);
+        let query = req.query_string()
+            .split('&')
+            .filter(|parameter| !parameter.is_empty() && !parameter.starts_with("cursor="))
+            .chain(std::iter::once(cursor.as_str()))
+            .collect::<Vec<&str>>()
+            .join("&");
+        response.insert_header((LINK, format!("<{}?{}>; rel=\"next\"", req.path(), query)));
+    }
+    for schema in state.think.schemas().list()? {
+        if page.records.iter().any(|record| record.get("mime_type").and_then(|mime_type| mime_type.as_str()) == Some(schema.mime_type.as_str())) {
+            response.append_header((LINK, schema_link(&schema.mime_type)));
+        }
+    }
+    Ok(response.json(page.records))
 }
 
-async fn delete_record_handler(state: web::Data<StateApiRecordsScope>, path: web::Path<Uuid>) -> HttpResponse {
+#[utoipa::path(
+    delete,
+    path = "/api/records/{record}",
+    tag = "records",
+    params(("record" = Uuid, Path, description = "Id of the record")),
+    responses(
+        (status = 200, description = "Record removed"),
+        (status = 404, description = "No such record", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn delete_record_handler(state: web::Data<StateApi>, path: web::Path<Uuid>) -> Result<HttpResponse, Error> {
     let record_id = path.into_inner();
-    remove_record(record_id, &state.pool);
-    HttpResponse::Ok().finish()
+    state.think.records().remove(record_id)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Header through which clients tell who is writing, it is recorded as is and not authenticated.
+const IDENTITY_HEADER: &str = "X-Think-User";
+const IDENTITY_MAX_CHARS: usize = 200;
+
+fn identity(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(IDENTITY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty() && value.chars().count() <= IDENTITY_MAX_CHARS)
+        .map(String::from)
+}
+
+/// An `If-Match` tag which is not a revision can never match, it is kept as one no record has.
+fn precondition(req: &HttpRequest) -> Precondition {
+    match req.get_header::<IfMatch>() {
+        None => Precondition::None,
+        Some(IfMatch::Any) => Precondition::Exists,
+        Some(IfMatch::Items(tags)) => Precondition::Revisions(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .map(|tag| tag.tag().parse::<i64>().unwrap_or(i64::MIN))
+                .collect()
+        ),
+    }
 }
 
-async fn post_record_handler(state: web::Data<StateApiRecordsScope>, path: web::Path<Uuid>, body: web::Json<RequestRecord>) -> HttpResponse {
+#[utoipa::path(
+    post,
+    path = "/api/records/{record}",
+    tag = "records",
+    params(
+        ("record" = Uuid, Path, description = "Id of the record"),
+        ("If-Match" = Option<String>, Header, description = "Revision the save is based on, `*` when the record has to exist"),
+        ("X-Think-User" = Option<String>, Header, description = "Who is writing, recorded as is"),
+    ),
+    request_body = RequestRecord,
+    responses(
+        (status = 201, description = "Revision saved, the body is only sent when it was merged with a newer revision", body = ResponseRecord,
+            headers(("ETag" = String, description = "The new revision"))),
+        (status = 400, description = "Invalid record or body", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Based on an older revision and not mergeable", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Based on a revision the record never had", body = Problem, content_type = "application/problem+json"),
+        (status = 428, description = "The record exists and `If-Match` is missing", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn post_record_handler(state: web::Data<StateApi>, path: web::Path<Uuid>, req: HttpRequest, body: web::Json<RequestRecord>) -> Result<HttpResponse, Error> {
     let record_id = path.into_inner();
     let record = body.into_inner();
     if record_id != record.id {
-        return HttpResponse::BadRequest().finish();
+        return Err(Error::validation("id", format!("Body is record '{}' but the path names '{}'", record.id, record_id)));
+    }
+    let saved = state.think.records().save(record, identity(&req), precondition(&req))?;
+
+    let mut response = HttpResponse::Created();
+    response.insert_header(ETag(EntityTag::new_strong(saved.record.revision.to_string())));
+    // A merged save stored something else than what was sent, the client gets the result back.
+    if saved.merged {
+        return Ok(response.json(saved.record));
     }
-    add_record(record, &state.pool);
+    Ok(response.finish())
+}
 
-    return HttpResponse::Created().finish();
+#[utoipa::path(
+    get,
+    path = "/api/records/{record}/attachments",
+    tag = "records",
+    params(("record" = Uuid, Path, description = "Id of the record")),
+    responses(
+        (status = 200, description = "Files embedded in the latest revision", body = Vec<ResponseReadMetaDataBlob>),
+        (status = 404, description = "No such record", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_record_attachments_handler(state: web::Data<StateApi>, path: web::Path<Uuid>) -> Result<HttpResponse, Error> {
+    let record_id = path.into_inner();
+    let attachments = state.think.records().attachments(record_id)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .json(attachments))
 }
 
-async fn get_view_file_handler(state: web::Data<StateApiStorageScope>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, err_no_id_for_storage> {
+#[utoipa::path(
+    get,
+    path = "/api/file/{file}/{filename}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the file belongs to"), ("filename" = String, Path)),
+    responses(
+        (status = 200, description = "The file with its sniffed `Content-Type`, as an attachment when a browser could run it", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 404, description = "No such file", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "The stored file is corrupted", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_view_file_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, Error> {
     let (id, filename) = path.into_inner();
-    let blob = state.storage_service.read(RequestReadBlob { id, filename });
-    match blob {
-        Ok(v) => Ok(HttpResponse::Ok()
-            .insert_header(("Content-Type", v.mime_type))
-            .body(v.body)
-        ),
-        Err(err_no_id_for_storage) => Err(err_no_id_for_storage),
+    let blob = state.think.files().read(RequestReadBlob { id, filename })?;
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(("Content-Type", blob.mime_type.clone()))
+        .insert_header((X_CONTENT_TYPE_OPTIONS, "nosniff"));
+    if blob.is_risky() {
+        response.insert_header(ContentDisposition::attachment(blob.filename));
     }
+    Ok(response.body(blob.body))
 }
 
-async fn get_meta_file_handler(state: web::Data<StateApiStorageScope>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, err_no_id_for_storage> {
+#[utoipa::path(
+    get,
+    path = "/api/file/{file}/meta/{filename}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the file belongs to"), ("filename" = String, Path)),
+    responses(
+        (status = 200, description = "Properties of the file", body = ResponseReadMetaDataBlob),
+        (status = 404, description = "No such file", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_meta_file_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, Error> {
     let (id, filename) = path.into_inner();
-    let blob = state.storage_service.read_meta_data(RequestReadBlob { id, filename });
-    match blob {
-        Ok(v) => Ok(HttpResponse::Ok()
-            .insert_header(ContentType::json())
-            .body(serde_json::to_string(&v).unwrap())
-        ),
-        Err(err_no_id_for_storage) => Err(err_no_id_for_storage),
-    }
+    let blob = state.think.files().read_meta_data(RequestReadBlob { id, filename })?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .body(serde_json::to_string(&blob).unwrap())
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/file",
+    tag = "files",
+    params(RequestSearchBlobs),
+    responses(
+        (status = 200, description = "A page of matching files", body = ResponseSearchBlobs),
+        (status = 400, description = "Malformed query or cursor", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_search_files_handler(state: web::Data<StateApi>, query: web::Query<RequestSearchBlobs>) -> Result<HttpResponse, Error> {
+    let blobs = state.think.files().search(query.into_inner())?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .json(blobs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/file/{file}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the files belong to")),
+    responses(
+        (status = 200, description = "Every file of the record", body = Vec<ResponseReadMetaDataBlob>),
+    ),
+)]
+async fn get_list_files_handler(state: web::Data<StateApi>, path: web::Path<Uuid>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let blobs = state.think.files().list(RequestListBlobs { id })?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .json(blobs))
 }
 
-async fn get_download_file_handler(state: web::Data<StateApiStorageScope>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, err_no_id_for_storage> {
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ThumbnailQuery {
+    /// Width of the box in pixels.
+    w: Option<u32>,
+    /// Height of the box in pixels.
+    h: Option<u32>,
+    #[serde(default)]
+    fit: ThumbnailFit,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/file/{file}/thumb/{filename}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the file belongs to"), ("filename" = String, Path), ThumbnailQuery),
+    responses(
+        (status = 200, description = "The image scaled to the box", body = Vec<u8>, content_type = "image/*"),
+        (status = 404, description = "No such file", body = Problem, content_type = "application/problem+json"),
+        (status = 415, description = "The file is not an image", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_thumbnail_file_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>, query: web::Query<ThumbnailQuery>) -> Result<HttpResponse, Error> {
     let (id, filename) = path.into_inner();
-    let blob = state.storage_service.read(RequestReadBlob { id, filename });
-    match blob {
-        Ok(v) => Ok(HttpResponse::Ok()
-            .insert_header(("Content-Type", v.mime_type))
-            .insert_header(ContentDisposition::attachment(v.filename))
-            .body(v.body)
-        ),
-        Err(err_no_id_for_storage) => Err(err_no_id_for_storage),
-    }
+    let query = query.into_inner();
+    let blob = state.think.files().thumbnail(RequestThumbnailBlob {
+        id,
+        filename,
+        width: query.w,
+        height: query.h,
+        fit: query.fit,
+    })?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", blob.mime_type))
+        .insert_header((X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .body(blob.body)
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/file/{file}/download/{filename}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the file belongs to"), ("filename" = String, Path)),
+    responses(
+        (status = 200, description = "The file as an attachment", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 404, description = "No such file", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_download_file_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, Error> {
+    let (id, filename) = path.into_inner();
+    let blob = state.think.files().read(RequestReadBlob { id, filename })?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", blob.mime_type))
+        .insert_header((X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .insert_header(ContentDisposition::attachment(blob.filename))
+        .body(blob.body)
+    )
 }
 
-async fn delete_file_handler(state: web::Data<StateApiStorageScope>, path: web::Path<(Uuid, String)>) -> HttpResponse {
+#[utoipa::path(
+    delete,
+    path = "/api/file/{file}/{filename}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the file belongs to"), ("filename" = String, Path)),
+    responses(
+        (status = 200, description = "File removed"),
+        (status = 404, description = "No such file", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn delete_file_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, Error> {
     let (id, filename) = path.into_inner();
-    state.storage_service.delete(RequestDeleteBlob { id, filename });
-    HttpResponse::Ok().finish()
+    state.think.files().delete(RequestDeleteBlob { id, filename })?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-async fn post_file_handler(state: web::Data<StateApiStorageScope>, path: web::Path<(Uuid, String)>, mut body: web::Payload) -> HttpResponse {
+#[derive(Deserialize, Serialize, ToSchema)]
+struct PatchFileBody {
+    id: Uuid,
+    filename: String,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/file/{file}/{filename}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the file belongs to"), ("filename" = String, Path)),
+    request_body(content = PatchFileBody, description = "Where the file moves to"),
+    responses(
+        (status = 200, description = "File moved", body = ResponseReadMetaDataBlob),
+        (status = 404, description = "No such file", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "A file already exists at the target", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn patch_file_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>, body: web::Json<PatchFileBody>) -> Result<HttpResponse, Error> {
+    let (id, filename) = path.into_inner();
+    let target = body.into_inner();
+    let blob = state.think.files().rename(RequestRenameBlob {
+        id,
+        filename,
+        new_id: target.id,
+        new_filename: target.filename,
+    })?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .body(serde_json::to_string(&blob).unwrap())
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/file/{file}/{filename}",
+    tag = "files",
+    params(("file" = Uuid, Path, description = "Id of the record the file belongs to"), ("filename" = String, Path)),
+    request_body(content = Vec<u8>, description = "The file, its type is sniffed from the content", content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "File stored"),
+        (status = 413, description = "Larger than the upload limit", body = Problem, content_type = "application/problem+json"),
+        (status = 415, description = "Content the storage refuses", body = Problem, content_type = "application/problem+json"),
+        (status = 507, description = "The storage quota would be exceeded", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn post_file_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>, req: HttpRequest, mut body: web::Payload) -> Result<HttpResponse, Error> {
     let (id, filename) = path.into_inner();
+    let content_type = req.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(String::from);
 
     let mut bytes = web::BytesMut::new();
     while let Some(item) = body.next().await {
-        bytes.extend_from_slice(&item.unwrap());
+        bytes.extend_from_slice(&item.map_err(|err| Error::validation("body", err.to_string()))?);
+        // The service checks the limit as well, here it stops an oversized upload before it is buffered whole.
+        if let Some(max_upload_size) = state.think.files().max_upload_size.filter(|max_upload_size| bytes.len() > *max_upload_size) {
+            return Err(Error::Storage(StorageFailure::TooLarge, format!("Uploads are limited to {} bytes", max_upload_size)));
+        }
     }
 
-    state.storage_service.upload(RequestUploadBlob {
+    state.think.files().upload(RequestUploadBlob {
         id,
         body: bytes.to_vec(),
         path: filename,
-    });
-    HttpResponse::Created().finish()
+        content_type,
+    })?;
+    Ok(HttpResponse::Created().finish())
 }
 
+#[derive(Deserialize)]
+struct OrphansQuery {
+    grace: Option<i64>,
+}
+
+impl OrphansQuery {
+    fn older_than(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc::now() - chrono::Duration::seconds(self.grace.unwrap_or(DEFAULT_ORPHAN_GRACE_PERIOD_IN_SECONDS))
+    }
+}
+
+async fn get_orphan_files_handler(state: web::Data<StateApi>, query: web::Query<OrphansQuery>) -> Result<HttpResponse, Error> {
+    let blobs = state.think.files().orphans(RequestOrphanBlobs { older_than: query.older_than() })?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .json(blobs))
+}
+
+async fn get_usage_handler(state: web::Data<StateApi>) -> Result<HttpResponse, Error> {
+    let usage = state.think.files().usage()?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .json(usage))
+}
 
-mod embedded {
-    use refinery::embed_migrations;
+async fn get_scrub_files_handler(state: web::Data<StateApi>) -> Result<HttpResponse, Error> {
+    let report = state.think.files().scrub()?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .json(report))
+}
 
-    embed_migrations!("/home/ptr/Repositories/think/server/src/embedded/migrations");
+async fn delete_orphan_files_handler(state: web::Data<StateApi>, query: web::Query<OrphansQuery>) -> Result<HttpResponse, Error> {
+    let blobs = state.think.files().remove_orphans(RequestOrphanBlobs { older_than: query.older_than() })?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .json(blobs))
 }
 
+/// Every scope works on the same `Think`, the handlers only translate between HTTP and its API.
 #[derive(Clone)]
-struct StateApiRecordsScope {
-    pool: Pool<SqliteConnectionManager>,
+struct StateApi {
+    think: Think,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/records/{record}/attributes",
+    tag = "records",
+    params(("record" = Uuid, Path, description = "Id of the record")),
+    responses(
+        (status = 200, description = "Attributes of the record by name", body = BTreeMap<String, AttributeValue>),
+        (status = 404, description = "No such record", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn get_record_attributes_handler(state: web::Data<StateApi>, path: web::Path<Uuid>) -> Result<HttpResponse, Error> {
+    let record_id = path.into_inner();
+    Ok(HttpResponse::Ok()
+        .json(state.think.records().attributes(record_id)?))
 }
 
-fn api_records_scope(pool: &Pool<SqliteConnectionManager>) -> Scope {
-    web::scope("/api/records")
-        .app_data(web::Data::new(StateApiRecordsScope {
-            pool: pool.clone()
+#[utoipa::path(
+    put,
+    path = "/api/records/{record}/attributes/{name}",
+    tag = "records",
+    params(("record" = Uuid, Path, description = "Id of the record"), ("name" = String, Path, description = "Letters, digits, `_` and `-`")),
+    request_body = AttributeValue,
+    responses(
+        (status = 200, description = "Attribute set"),
+        (status = 400, description = "Invalid name or value", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No such record", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn put_record_attribute_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>, body: web::Json<AttributeValue>) -> Result<HttpResponse, Error> {
+    let (record_id, name) = path.into_inner();
+    state.think.records().set_attribute(record_id, &name, body.into_inner())?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/records/{record}/attributes/{name}",
+    tag = "records",
+    params(("record" = Uuid, Path, description = "Id of the record"), ("name" = String, Path)),
+    responses(
+        (status = 200, description = "Attribute unset"),
+        (status = 404, description = "No such record or attribute", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+async fn delete_record_attribute_handler(state: web::Data<StateApi>, path: web::Path<(Uuid, String)>) -> Result<HttpResponse, Error> {
+    let (record_id, name) = path.into_inner();
+    state.think.records().unset_attribute(record_id, &name)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// A scope sharing `think`, whose malformed paths, queries and bodies are answered like every other error.
+fn api_scope(path: &str, think: &Think) -> Scope {
+    web::scope(path)
+        .app_data(web::Data::new(StateApi {
+            think: think.clone()
         }))
+        .app_data(web::PathConfig::default().error_handler(|err, _| Error::NotFound(err.to_string()).into()))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| Error::validation("query", err.to_string()).into()))
+        .app_data(web::JsonConfig::default().error_handler(|err, _| Error::validation("body", err.to_string()).into()))
+}
+
+fn api_records_scope(think: &Think) -> Scope {
+    api_scope("/api/records", think)
         .route("", web::get().to(get_records_handler))
         .route("/{record}", web::get().to(get_record_handler))
         .route("/{record}", web::delete().to(delete_record_handler))
         .route("/{record}", web::post().to(post_record_handler))
+        .route("/{record}/attachments", web::get().to(get_record_attachments_handler))
+        .route("/{record}/attributes", web::get().to(get_record_attributes_handler))
+        .route("/{record}/attributes/{name}", web::put().to(put_record_attribute_handler))
+        .route("/{record}/attributes/{name}", web::delete().to(delete_record_attribute_handler))
 }
 
-struct StateApiStorageScope {
-    storage_service: storage::storage::service::Service,
-}
-
-fn api_storage_scope(pool: &Pool<SqliteConnectionManager>) -> Scope {
-    let storage_service = storage::create_service(pool);
-
-    web::scope("/api/file")
-        .app_data(web::Data::new(StateApiStorageScope {
-            storage_service,
-        }))
+fn api_storage_scope(think: &Think) -> Scope {
+    api_scope("/api/file", think)
+        .route("", web::get().to(get_search_files_handler))
+        .route("/{file}", web::get().to(get_list_files_handler))
         .route("/{file}/{filename}", web::get().to(get_view_file_handler))
         .route("/{file}/meta/{filename}", web::get().to(get_meta_file_handler))
+        .route("/{file}/thumb/{filename}", web::get().to(get_thumbnail_file_handler))
         .route("/{file}/download/{filename}", web::get().to(get_download_file_handler))
         .route("/{file}/{filename}", web::delete().to(delete_file_handler))
         .route("/{file}/{filename}", web::post().to(post_file_handler))
+        .route("/{file}/{filename}", web::patch().to(patch_file_handler))
+}
+
+/// Server-Sent Events of every change, see `events::service::Event` for the payloads.
+async fn get_events_handler() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentType(mime_guess::mime::TEXT_EVENT_STREAM))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(events::service::stream().map(Ok::<_, actix_web::Error>))
+}
+
+/// Generated on every request, it is small and only fetched when clients are built.
+async fn get_openapi_handler() -> HttpResponse {
+    HttpResponse::Ok()
+        .json(openapi::document())
+}
+
+fn api_openapi_scope() -> Scope {
+    web::scope("/api/openapi.json")
+        .route("", web::get().to(get_openapi_handler))
+}
+
+fn api_events_scope() -> Scope {
+    web::scope("/api/events")
+        .route("", web::get().to(get_events_handler))
+}
+
+async fn get_pull_handler(state: web::Data<StateApi>, query: web::Query<RequestPull>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .json(state.think.replication().pull(query.into_inner())?))
+}
+
+async fn post_push_handler(state: web::Data<StateApi>, req: HttpRequest, body: web::Json<Vec<PushRow>>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .json(state.think.replication().push(body.into_inner(), identity(&req))?))
+}
+
+async fn get_pull_stream_handler(state: web::Data<StateApi>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentType(mime_guess::mime::TEXT_EVENT_STREAM))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(state.think.replication().pull_stream().map(Ok::<_, actix_web::Error>))
+}
+
+fn api_replication_scope(think: &Think) -> Scope {
+    api_scope("/api/replication/records", think)
+        .route("/pull", web::get().to(get_pull_handler))
+        .route("/push", web::post().to(post_push_handler))
+        .route("/pull_stream", web::get().to(get_pull_stream_handler))
+}
+
+/// Where the JSON Schema of `mime_type` is served, for clients rendering a form of its records.
+fn schema_link(mime_type: &str) -> String {
+    format!("</api/schemas/{}>; rel=\"describedby\"", mime_type)
+}
+
+async fn get_schemas_handler(state: web::Data<StateApi>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .json(state.think.schemas().list()?))
+}
+
+/// The schema document itself, so `describedby` links resolve to something a validator takes.
+async fn get_schema_handler(state: web::Data<StateApi>, path: web::Path<(String, String)>) -> Result<HttpResponse, Error> {
+    let (kind, subtype) = path.into_inner();
+    let schema = state.think.schemas().get(&format!("{}/{}", kind, subtype))?;
+    Ok(HttpResponse::Ok()
+        .content_type(SCHEMA_CONTENT_TYPE)
+        .body(schema.schema.to_string()))
+}
+
+async fn put_schema_handler(state: web::Data<StateApi>, path: web::Path<(String, String)>, body: web::Json<serde_json::Value>) -> Result<HttpResponse, Error> {
+    let (kind, subtype) = path.into_inner();
+    if state.think.schemas().put(&format!("{}/{}", kind, subtype), body.into_inner())? {
+        return Ok(HttpResponse::Created().finish());
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn delete_schema_handler(state: web::Data<StateApi>, path: web::Path<(String, String)>) -> Result<HttpResponse, Error> {
+    let (kind, subtype) = path.into_inner();
+    state.think.schemas().remove(&format!("{}/{}", kind, subtype))?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Mime types have a slash, `/api/schemas/task/json` is the schema of `task/json`.
+fn api_schemas_scope(think: &Think) -> Scope {
+    api_scope("/api/schemas", think)
+        .route("", web::get().to(get_schemas_handler))
+        .route("/{type}/{subtype}", web::get().to(get_schema_handler))
+        .route("/{type}/{subtype}", web::put().to(put_schema_handler))
+        .route("/{type}/{subtype}", web::delete().to(delete_schema_handler))
+}
+
+async fn get_collections_handler(state: web::Data<StateApi>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .json(state.think.collections().list()?))
+}
+
+async fn post_collection_handler(state: web::Data<StateApi>, body: web::Json<RequestCollection>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Created()
+        .json(state.think.collections().add(body.into_inner())?))
+}
+
+async fn get_collection_handler(state: web::Data<StateApi>, path: web::Path<Uuid>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .json(state.think.collections().get(path.into_inner())?))
 }
 
+async fn put_collection_handler(state: web::Data<StateApi>, path: web::Path<Uuid>, body: web::Json<RequestCollection>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok()
+        .json(state.think.collections().change(path.into_inner(), body.into_inner())?))
+}
+
+async fn delete_collection_handler(state: web::Data<StateApi>, path: web::Path<Uuid>) -> Result<HttpResponse, Error> {
+    state.think.collections().remove(path.into_inner())?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Pages like `/api/records`, the saved query decides which records and in which order.
+async fn get_collection_records_handler(state: web::Data<StateApi>, req: HttpRequest, path: web::Path<Uuid>, query: web::Query<RequestCollectionRecords>) -> Result<HttpResponse, Error> {
+    let page = state.think.collections().records(path.into_inner(), query.into_inner())?;
+    records_page_response(&state, &req, page)
+}
+
+fn api_collections_scope(think: &Think) -> Scope {
+    api_scope("/api/collections", think)
+        .route("", web::get().to(get_collections_handler))
+        .route("", web::post().to(post_collection_handler))
+        .route("/{collection}", web::get().to(get_collection_handler))
+        .route("/{collection}", web::put().to(put_collection_handler))
+        .route("/{collection}", web::delete().to(delete_collection_handler))
+        .route("/{collection}/records", web::get().to(get_collection_records_handler))
+}
+
+fn api_storage_maintenance_scope(think: &Think) -> Scope {
+    api_scope("/api/storage", think)
+        .route("/usage", web::get().to(get_usage_handler))
+        .route("/orphans", web::get().to(get_orphan_files_handler))
+        .route("/orphans", web::delete().to(delete_orphan_files_handler))
+        .route("/scrub", web::get().to(get_scrub_files_handler))
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(Env::default());
-
-    let database_url = "/home/ptr/Repositories/think/server/tmp/data.db";
-    let manager = SqliteConnectionManager::file(database_url);
-    let mut connection = manager.connect().unwrap();
-    embedded::migrations::runner().run(&mut connection).unwrap();
+    let settings = Settings::load(Cli::parse()).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string()))?;
+    // `RUST_LOG` still takes precedence, it allows filtering per module.
+    env_logger::init_from_env(Env::default().default_filter_or(settings.log_level.as_filter()));
 
-    let pool = Pool::new(manager).unwrap();
+    let think = match settings.storage_backend {
+        StorageBackend::Sqlite => Think::open(&settings.database_path, settings.pool_size),
+    }
+        .map_err(|err| std::io::Error::other(err.to_string()))?
+        .with_quota(settings.storage_quota)
+        .with_max_upload_size(settings.max_upload_size)
+        .with_mime_types(&settings.mime_types);
 
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .wrap(Logger::default())
             .service(
-                api_records_scope(&pool)
+                api_records_scope(&think)
             ).service(
-            api_storage_scope(&pool)
-        )
+            api_storage_scope(&think)
+        ).service(
+            api_storage_maintenance_scope(&think)
+        ).service(
+            api_events_scope()
+        ).service(
+            api_replication_scope(&think)
+        ).service(
+            api_schemas_scope(&think)
+        ).service(
+            api_collections_scope(&think)
+        ).service(
+            api_openapi_scope()
+        );
+        #[cfg(feature = "swagger-ui")]
+        let app = app.service(openapi::swagger_ui());
+        app
     })
-        .bind(("127.0.0.1", 8080))?
+        .bind((settings.host.as_str(), settings.port))?
         .run()
         .await
 }
 
 #[cfg(test)]
 pub mod tests {
-    use std::fs;
-    use std::sync::Once;
-
-    use super::*;
-
-    static INIT: Once = Once::new();
-    static DATABASE_URL: &str = "/home/ptr/Repositories/think/server/tmp/data_test.db";
-
-    pub fn initialize_db() {
-        INIT.call_once(|| {
-            let database_path = std::path::Path::new(DATABASE_URL);
-            if database_path.exists() {
-                fs::remove_file(database_path).unwrap();
-            }
-            let manager = SqliteConnectionManager::file(DATABASE_URL);
-            let mut connection = manager.connect().unwrap();
-            embedded::migrations::runner().run(&mut connection).unwrap();
-            let fixtures_records_100 = fs::read_to_string("/home/ptr/Repositories/think/server/src/fixtures/records_100.sql").unwrap();
-            connection.execute_batch(fixtures_records_100.as_str()).unwrap();
-            // let fixtures_records_10000 = fs::read_to_string("/home/ptr/Repositories/think/server/src/fixtures/records_10000.sql").unwrap();
-            // connection.execute_batch(fixtures_records_10000.as_str()).unwrap();
-            connection.close().unwrap();
-        })
-    }
-
-    pub fn init_pool() -> Pool<SqliteConnectionManager> {
-        let manager = SqliteConnectionManager::file(DATABASE_URL);
-        Pool::new(manager).unwrap()
-    }
-
     #[cfg(test)]
     mod tests_api_records_scope {
         use actix_web::{App, test};
-        use actix_web::http::header::ContentType;
+        use actix_web::http::header::{ContentType, ETAG, IF_MATCH, LINK};
         use actix_web::http::StatusCode;
         use chrono::{TimeZone, Utc};
         use uuid::Uuid;
         use crate::api_records_scope;
-        use crate::record::queries::{insert_record, ReadRecord, select_record, WriteRecord};
-        use crate::record::service::{get_record, RequestRecord};
-        use crate::tests::{init_pool, initialize_db};
+        use think_core::record::queries::{insert_record, Latest, ReadRecord, select_record, WriteRecord};
+        use think_core::record::service::{add_record, get_record, Precondition, RequestRecord, ResponseRecord};
+        use think_core::record::validation::Validators;
+        use crate::IDENTITY_HEADER;
+        use think_core::Think;
+        use think_core::storage::storage::service::{RequestUploadBlob, ResponseReadMetaDataBlob};
+        use think_core::testing::init_pool;
 
         #[actix_web::test]
         async fn test_get_records_handler() {
-            initialize_db();
             let pool = init_pool();
             let inserted_record_json_body: serde_json::Value = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}").unwrap();
             insert_record(WriteRecord {
@@ -235,24 +749,27 @@ pub mod tests {
                 mime_type: String::from("note/lexical"),
                 body: inserted_record_json_body.clone(),
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
-            }, &pool);
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
             insert_record(WriteRecord {
                 id: Uuid::parse_str("ac07da60-d4dd-493e-93cb-277b2b8b4a56").unwrap(),
                 mime_type: String::from("note/lexical"),
                 body: inserted_record_json_body.clone(),
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
-            }, &pool);
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
             insert_record(WriteRecord {
                 id: Uuid::parse_str("dce1ceac-300c-473c-a329-b9f404476def").unwrap(),
                 mime_type: String::from("note/lexical"),
                 body: inserted_record_json_body.clone(),
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
-            }, &pool);
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
 
             let mut app = test::init_service(
                 App::new()
                     .service(
-                        api_records_scope(&pool)
+                        api_records_scope(&Think::new(&pool))
                     )
             ).await;
             let req = test::TestRequest::get().uri("/api/records").to_request();
@@ -261,9 +778,123 @@ pub mod tests {
             assert!(!resp.is_empty());
         }
 
+        #[actix_web::test]
+        async fn test_get_records_handler_with_pagination() {
+            let pool = init_pool();
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_records_scope(&Think::new(&pool))
+                    )
+            ).await;
+            let req = test::TestRequest::get().uri("/api/records?limit=2&sort=created_at&fields=id,updated_at").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let link = res.headers().get(LINK).unwrap().to_str().unwrap().to_string();
+            let first: Vec<serde_json::Value> = test::read_body_json(res).await;
+            assert_eq!(first.len(), 2);
+            assert!(first.iter().all(|record| record.get("body").is_none() && record.get("id").is_some()));
+
+            let next = link.trim_start_matches('<').split('>').next().unwrap().to_string();
+            assert!(next.starts_with("/api/records?limit=2&sort=created_at&fields=id,updated_at&cursor="));
+            let req = test::TestRequest::get().uri(&next).to_request();
+            let second: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(second.len(), 2);
+            assert!(second.iter().all(|record| !first.contains(record)));
+        }
+
+        #[actix_web::test]
+        async fn test_record_attributes() {
+            let pool = init_pool();
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_records_scope(&Think::new(&pool))
+                    )
+            ).await;
+            let ids = ["e26ed5b1-28f0-4449-bbc1-2e5d48d3c26a", "95a0243b-d0ef-4c0b-afe2-a1292e3a8077", "18c74eda-732f-44af-bf4a-4f923b153dc9"];
+            for (id, (due, priority)) in ids.iter().zip([("2026-10-20", 3), ("2026-12-01", 1), ("2026-10-31", 2)]) {
+                for (name, value) in [("due", serde_json::json!({"type": "date", "value": due})), ("priority", serde_json::json!({"type": "number", "value": priority}))] {
+                    let req = test::TestRequest::put().uri(format!("/api/records/{}/attributes/{}", id, name).as_str()).set_json(value).to_request();
+                    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+                }
+            }
+            let req = test::TestRequest::put().uri(format!("/api/records/{}/attributes/due", ids[0]).as_str()).set_json(serde_json::json!({"type": "date", "value": "soon"})).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+            let req = test::TestRequest::get().uri(format!("/api/records/{}/attributes", ids[0]).as_str()).to_request();
+            let attributes: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(attributes, serde_json::json!({"due": {"type": "date", "value": "2026-10-20"}, "priority": {"type": "number", "value": 3.0}}));
+
+            let req = test::TestRequest::get().uri("/api/records?fields=id&filter=due%3C2026-11-01,priority%3E%3D2&sort=attribute:priority&order=asc").to_request();
+            let records: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(records, vec![serde_json::json!({"id": ids[2]}), serde_json::json!({"id": ids[0]})]);
+
+            let req = test::TestRequest::get().uri("/api/records?fields=id&sort=attribute:priority&limit=2").to_request();
+            let res = test::call_service(&app, req).await;
+            let link = res.headers().get(LINK).unwrap().to_str().unwrap().to_string();
+            let first: Vec<serde_json::Value> = test::read_body_json(res).await;
+            assert_eq!(first, vec![serde_json::json!({"id": ids[0]}), serde_json::json!({"id": ids[2]})]);
+            let next = link.trim_start_matches('<').split('>').next().unwrap().to_string();
+            let req = test::TestRequest::get().uri(&next).to_request();
+            let second: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(second, vec![serde_json::json!({"id": ids[1]})]);
+
+            let req = test::TestRequest::delete().uri(format!("/api/records/{}/attributes/due", ids[1]).as_str()).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+            let req = test::TestRequest::get().uri("/api/records?fields=id&filter=due%3E2026-01-01").to_request();
+            let records: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(records.len(), 2);
+
+            // Text sorts after every number in SQLite, a string priority must still not match.
+            let req = test::TestRequest::put().uri(format!("/api/records/{}/attributes/priority", ids[1]).as_str()).set_json(serde_json::json!({"type": "string", "value": "urgent"})).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+            let req = test::TestRequest::get().uri("/api/records?fields=id&filter=priority%3E%3D2&sort=attribute:priority&order=asc").to_request();
+            let records: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(records, vec![serde_json::json!({"id": ids[2]}), serde_json::json!({"id": ids[0]})]);
+
+            let req = test::TestRequest::get().uri("/api/records?filter=due").to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+            let req = test::TestRequest::get().uri("/api/records?sort=priority").to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[actix_web::test]
+        async fn test_get_records_handler_with_summary_view() {
+            let pool = init_pool();
+            add_record(RequestRecord {
+                id: Uuid::parse_str("f6a8b0c2-6d7e-4f1a-8b3c-7d9e1f3a5b68").unwrap(),
+                mime_type: String::from("note/lexical"),
+                body: serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[{\"children\":[{\"text\":\"Summary view\",\"type\":\"text\",\"version\":1}],\"type\":\"heading\",\"tag\":\"h1\",\"version\":1}],\"type\":\"root\",\"version\":1}}}").unwrap(),
+            }, None, Precondition::None, &Validators::default(), &pool).unwrap();
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_records_scope(&Think::new(&pool))
+                    )
+            ).await;
+            let req = test::TestRequest::get().uri("/api/records?view=summary").to_request();
+            let res: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert!(res.iter().all(|record| record.get("body").is_none()));
+            assert!(res.iter().any(|record| record["title"] == "Summary view"));
+        }
+
+        #[actix_web::test]
+        async fn test_get_records_handler_with_unknown_field() {
+            let pool = init_pool();
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_records_scope(&Think::new(&pool))
+                    )
+            ).await;
+            let req = test::TestRequest::get().uri("/api/records?fields=id,bdy").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+
         #[actix_web::test]
         async fn test_get_record_handler() {
-            initialize_db();
             let pool = init_pool();
             let id = Uuid::parse_str("1ae64856-01d1-42b3-b4bc-19fe3e6b2f60").unwrap();
             let inserted_record_json_body = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}").unwrap();
@@ -272,14 +903,15 @@ pub mod tests {
                 mime_type: String::from("note/lexical"),
                 body: inserted_record_json_body,
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
+                author: None,
             };
 
-            insert_record(inserted_record, &pool);
+            insert_record(inserted_record, Latest::Absent, &pool).unwrap();
 
             let mut app = test::init_service(
                 App::new()
                     .service(
-                        api_records_scope(&pool)
+                        api_records_scope(&Think::new(&pool))
                     )
             ).await;
             let req = test::TestRequest::get().uri(format!("/api/records/{}", id.to_string()).as_str()).to_request();
@@ -290,7 +922,6 @@ pub mod tests {
 
         #[actix_web::test]
         async fn test_delete_record_handler() {
-            initialize_db();
             let pool = init_pool();
             let id = Uuid::parse_str("ea698120-4363-45a9-b561-59761f82b2c8").unwrap();
             let inserted_record_json_body = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}").unwrap();
@@ -299,14 +930,15 @@ pub mod tests {
                 mime_type: String::from("note/lexical"),
                 body: inserted_record_json_body,
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
+                author: None,
             };
 
-            insert_record(inserted_record, &pool);
+            insert_record(inserted_record, Latest::Absent, &pool).unwrap();
 
             let mut app = test::init_service(
                 App::new()
                     .service(
-                        api_records_scope(&pool)
+                        api_records_scope(&Think::new(&pool))
                     )
             ).await;
             let req = test::TestRequest::delete().uri(format!("/api/records/{}", id.to_string()).as_str()).to_request();
@@ -316,11 +948,14 @@ pub mod tests {
 
             let deleted_record = get_record(id, &pool);
             assert!(deleted_record.is_err());
+
+            let req = test::TestRequest::delete().uri(format!("/api/records/{}", id).as_str()).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
         }
 
         #[actix_web::test]
         async fn test_post_record_handler_update_record() {
-            initialize_db();
             let pool = init_pool();
             let id = Uuid::parse_str("fd033743-8e6b-417f-9d76-de2dba47a682").unwrap();
             let inserted_record_json_body = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}").unwrap();
@@ -329,14 +964,16 @@ pub mod tests {
                 mime_type: String::from("note/lexical"),
                 body: inserted_record_json_body,
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
+                author: None,
             };
 
-            insert_record(inserted_record, &pool);
+            insert_record(inserted_record, Latest::Absent, &pool).unwrap();
+            let revision = select_record(id, &pool).unwrap().revision;
 
             let mut app = test::init_service(
                 App::new()
                     .service(
-                        api_records_scope(&pool)
+                        api_records_scope(&Think::new(&pool))
                     )
             ).await;
 
@@ -350,6 +987,7 @@ pub mod tests {
             let req = test::TestRequest::post()
                 .uri(format!("/api/records/{}", id.to_string()).as_str())
                 .insert_header(ContentType::json())
+                .insert_header((IF_MATCH, format!("\"{}\"", revision)))
                 .set_payload(payload_request_to_update_as_str)
                 .to_request();
 
@@ -358,18 +996,119 @@ pub mod tests {
             assert_eq!(resp.status(), StatusCode::CREATED);
             let updated_record = select_record(id, &pool).unwrap();
             assert_eq!(updated_record.body, payload_request_to_update.body);
+            assert_eq!(resp.headers().get(ETAG).unwrap().to_str().unwrap(), format!("\"{}\"", updated_record.revision));
+        }
+
+        #[actix_web::test]
+        async fn test_post_record_handler_preconditions() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("dae0f4a6-ab1c-4d5e-8f7a-b13c5d7e9fac").unwrap();
+            let note = |blocks: &[&str]| serde_json::json!({"editorState": {"root": {"children": blocks.iter().map(|text| serde_json::json!({"children": [{"text": text, "type": "text", "version": 1}], "type": "paragraph", "version": 1})).collect::<Vec<serde_json::Value>>(), "type": "root", "version": 1}}});
+            insert_record(WriteRecord {
+                id,
+                mime_type: String::from("note/lexical"),
+                body: note(&["a", "b", "c"]),
+                created_at: Utc.timestamp_opt(100, 0).unwrap(),
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
+            let base = select_record(id, &pool).unwrap().revision;
+            insert_record(WriteRecord {
+                id,
+                mime_type: String::from("note/lexical"),
+                body: note(&["a", "b", "c", "theirs"]),
+                created_at: Utc.timestamp_opt(200, 0).unwrap(),
+                author: None,
+            }, Latest::Revision(base), &pool).unwrap();
+            let latest = format!("\"{}\"", select_record(id, &pool).unwrap().revision);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_records_scope(&Think::new(&pool))
+                    )
+            ).await;
+            let save = |blocks: &[&str], if_match: Option<String>| {
+                let mut req = test::TestRequest::post()
+                    .uri(format!("/api/records/{}", id).as_str())
+                    .insert_header(ContentType::json())
+                    .set_payload(serde_json::to_string(&RequestRecord {
+                        id,
+                        mime_type: String::from("note/lexical"),
+                        body: note(blocks),
+                    }).unwrap());
+                if let Some(if_match) = if_match {
+                    req = req.insert_header((IF_MATCH, if_match));
+                }
+                req.to_request()
+            };
+
+            let req = test::TestRequest::get().uri(format!("/api/records/{}", id).as_str()).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.headers().get(ETAG).unwrap().to_str().unwrap(), latest);
+
+            let res = test::call_service(&app, save(&["a", "b", "c"], None)).await;
+            assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+
+            let res = test::call_service(&app, save(&["a", "b", "c ours"], Some(format!("\"{}\"", base)))).await;
+            assert_eq!(res.status(), StatusCode::CONFLICT);
+            assert_eq!(res.headers().get(ETAG).unwrap().to_str().unwrap(), latest);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["current"]["id"], id.to_string());
+            assert_eq!(body["conflicts"][0]["index"], 2);
+
+            let res = test::call_service(&app, save(&["a", "b", "c"], Some("\"0\"".to_string()))).await;
+            assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+            let res = test::call_service(&app, save(&["a ours", "b", "c"], Some(format!("\"{}\"", base)))).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let etag = res.headers().get(ETAG).unwrap().to_str().unwrap().to_string();
+            let merged: ResponseRecord = test::read_body_json(res).await;
+            assert_eq!(merged.body, note(&["a ours", "b", "c", "theirs"]));
+
+            let res = test::call_service(&app, save(&["a ours", "b", "c", "theirs", "d"], Some(etag))).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            assert_eq!(select_record(id, &pool).unwrap().revision_count, 4);
+        }
+
+        #[actix_web::test]
+        async fn test_get_record_attachments_handler() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("46f81a3c-7a9c-4ebf-9b5d-4a6c8e0f2be7").unwrap();
+            let file_id = Uuid::parse_str("57092b4d-8bad-4fc0-8c6e-5b7d9f1a3cf8").unwrap();
+            Think::new(&pool).files().upload(RequestUploadBlob {
+                id: file_id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "cat.gif".to_string(),
+                content_type: None,
+            }).unwrap();
+            add_record(RequestRecord {
+                id,
+                mime_type: String::from("note/lexical"),
+                body: serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[{\"type\":\"image\",\"version\":1,\"src\":\"/api/file/57092b4d-8bad-4fc0-8c6e-5b7d9f1a3cf8/cat.gif\"}],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}").unwrap(),
+            }, None, Precondition::None, &Validators::default(), &pool).unwrap();
+
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_records_scope(&Think::new(&pool))
+                    )
+            ).await;
+            let req = test::TestRequest::get().uri(format!("/api/records/{}/attachments", id).as_str()).to_request();
+
+            let resp: Vec<ResponseReadMetaDataBlob> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(resp[0].id, file_id);
+            assert_eq!(resp[0].filename, "cat.gif");
         }
 
         #[actix_web::test]
         async fn test_post_record_handler_insert_record() {
-            initialize_db();
             let pool = init_pool();
             let id = Uuid::parse_str("b984d681-f2f6-4cb6-9bfd-ef32a6b8119a").unwrap();
 
             let mut app = test::init_service(
                 App::new()
                     .service(
-                        api_records_scope(&pool)
+                        api_records_scope(&Think::new(&pool))
                     )
             ).await;
 
@@ -392,116 +1131,566 @@ pub mod tests {
             let updated_record = select_record(id, &pool).unwrap();
             assert_eq!(updated_record.body, payload_request_to_update.body);
         }
-    }
-
-    #[cfg(test)]
-    mod tests_api_storage_scope {
-        use std::str::FromStr;
-        use actix_web::{App, test};
-        use actix_web::http::StatusCode;
-        use uuid::Uuid;
-        use crate::api_storage_scope;
-        use crate::storage::{create_service};
-        use crate::storage::storage::service::{RequestReadBlob, RequestUploadBlob, ResponseReadMetaDataBlob};
-        use crate::tests::{init_pool, initialize_db};
 
         #[actix_web::test]
-        async fn test_get_view_file_handler() {
-            initialize_db();
+        async fn test_post_record_handler_with_identity() {
             let pool = init_pool();
-            let storage_service = create_service(&pool);
+            let id = Uuid::parse_str("b8cad2e4-8f9a-4b3c-8d5e-9f1a3b5c7d8a").unwrap();
             let app = test::init_service(
                 App::new()
                     .service(
-                        api_storage_scope(&pool)
+                        api_records_scope(&Think::new(&pool))
                     )
             ).await;
-            storage_service.upload(RequestUploadBlob {
-                id: Uuid::from_str("260fc36a-1295-48a1-906d-93d8e8465732").unwrap(),
-                body: "TEST".as_bytes().to_vec(),
-                path: "test.txt".to_string(),
-            });
-            let req = test::TestRequest::get().uri("/api/file/260fc36a-1295-48a1-906d-93d8e8465732/test.txt").to_request();
-            let res = test::call_service(&app, req).await;
-            assert!(res.status().is_success())
+            let payload = serde_json::to_string(&RequestRecord {
+                id,
+                mime_type: String::from("note/lexical"),
+                body: serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"type\":\"root\",\"version\":1}}}").unwrap(),
+            }).unwrap();
+
+            let req = test::TestRequest::post()
+                .uri(format!("/api/records/{}", id).as_str())
+                .insert_header(ContentType::json())
+                .insert_header((IDENTITY_HEADER, " alice "))
+                .set_payload(payload)
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+
+            let req = test::TestRequest::get().uri(format!("/api/records/{}", id).as_str()).to_request();
+            let record: ResponseRecord = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(record.revision_count, 1);
+            assert_eq!(record.created_by.as_deref(), Some("alice"));
+            assert_eq!(record.updated_by.as_deref(), Some("alice"));
         }
 
         #[actix_web::test]
-        async fn test_get_download_file_handler() {
-            initialize_db();
+        async fn test_post_record_handler_malformed_body() {
             let pool = init_pool();
-            let storage_service = create_service(&pool);
+            let id = Uuid::parse_str("c41e7b2a-9d3f-4a86-b5c0-6e2f8a1d3b97").unwrap();
             let app = test::init_service(
                 App::new()
                     .service(
-                        api_storage_scope(&pool)
+                        api_records_scope(&Think::new(&pool))
                     )
             ).await;
-            storage_service.upload(RequestUploadBlob {
-                id: Uuid::from_str("c8f3a893-7d6b-4ace-b2ea-32dbb30c7ff9").unwrap(),
-                body: "TEST".as_bytes().to_vec(),
-                path: "test.txt".to_string(),
-            });
-            let req = test::TestRequest::get().uri("/api/file/c8f3a893-7d6b-4ace-b2ea-32dbb30c7ff9/download/test.txt").to_request();
-            let res = test::call_service(&app, req).await;
-            assert!(res.status().is_success())
+            let post = |mime_type: &str, body: serde_json::Value| test::TestRequest::post()
+                .uri(format!("/api/records/{}", id).as_str())
+                .set_json(RequestRecord { id, mime_type: mime_type.to_string(), body })
+                .to_request();
+
+            let res = test::call_service(&app, post("note/lexical", serde_json::json!({"editorState": {"root": {"children": [{"type": "paragraph"}], "type": "root", "version": 1}}}))).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let problem: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(problem["parameter"], "body");
+            assert_eq!(problem["detail"], "/editorState/root/children/0: version is missing");
+
+            let res = test::call_service(&app, post("text/html", serde_json::json!("<p>"))).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let problem: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(problem["parameter"], "mime_type");
+            assert!(select_record(id, &pool).is_err());
         }
+    }
+
+    #[cfg(test)]
+    mod tests_api_events_scope {
+        use actix_web::{App, test};
+        use actix_web::http::header::CONTENT_TYPE;
+        use actix_web::http::StatusCode;
+        use crate::api_events_scope;
 
         #[actix_web::test]
-        async fn test_get_meta_file_handler() {
-            initialize_db();
-            let pool = init_pool();
-            let storage_service = create_service(&pool);
+        async fn test_get_events_handler() {
             let app = test::init_service(
                 App::new()
                     .service(
-                        api_storage_scope(&pool)
+                        api_events_scope()
                     )
             ).await;
-            let id = Uuid::from_str("85d83734-2af0-41b9-9df4-b3131451e572").unwrap();
-            storage_service.upload(RequestUploadBlob {
-                id,
-                body: "TEST".as_bytes().to_vec(),
-                path: "test.txt".to_string(),
-            });
-            let req = test::TestRequest::get().uri("/api/file/85d83734-2af0-41b9-9df4-b3131451e572/meta/test.txt").to_request();
-            let res: ResponseReadMetaDataBlob = test::call_and_read_body_json(&app, req).await;
-            assert_eq!(res.id, id);
+            let req = test::TestRequest::get().uri("/api/events").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "text/event-stream");
+        }
+    }
+
+    #[cfg(test)]
+    mod tests_api_openapi_scope {
+        use actix_web::{App, test};
+        use actix_web::http::StatusCode;
+        use crate::api_openapi_scope;
+
+        #[actix_web::test]
+        async fn test_get_openapi_handler() {
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_openapi_scope()
+                    )
+            ).await;
+            let req = test::TestRequest::get().uri("/api/openapi.json").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let document: serde_json::Value = test::read_body_json(res).await;
+
+            assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+            let operations = [
+                ("/api/records", "get"),
+                ("/api/records/{record}", "get"),
+                ("/api/records/{record}", "post"),
+                ("/api/records/{record}", "delete"),
+                ("/api/records/{record}/attachments", "get"),
+                ("/api/records/{record}/attributes", "get"),
+                ("/api/records/{record}/attributes/{name}", "put"),
+                ("/api/records/{record}/attributes/{name}", "delete"),
+                ("/api/file", "get"),
+                ("/api/file/{file}", "get"),
+                ("/api/file/{file}/{filename}", "get"),
+                ("/api/file/{file}/{filename}", "post"),
+                ("/api/file/{file}/{filename}", "patch"),
+                ("/api/file/{file}/{filename}", "delete"),
+                ("/api/file/{file}/meta/{filename}", "get"),
+                ("/api/file/{file}/thumb/{filename}", "get"),
+                ("/api/file/{file}/download/{filename}", "get"),
+            ];
+            for (path, method) in operations {
+                assert!(document["paths"][path][method].is_object(), "{} {} is not documented", method, path);
+            }
+            for schema in ["RequestRecord", "ResponseRecord", "ResponseReadMetaDataBlob", "Problem"] {
+                assert!(document["components"]["schemas"][schema].is_object(), "{} is not documented", schema);
+            }
+            let schemas = document["components"]["schemas"].as_object().unwrap();
+            for reference in document.to_string().split("\"#/components/schemas/").skip(1) {
+                let name = reference.split('"').next().unwrap();
+                assert!(schemas.contains_key(name), "{} is referred to but not documented", name);
+            }
+            let not_found = &document["paths"]["/api/records/{record}"]["get"]["responses"]["404"]["content"]["application/problem+json"]["schema"];
+            assert_eq!(not_found["$ref"], "#/components/schemas/Problem");
+            assert!(document["components"]["schemas"]["ResponseRecord"]["properties"].get("revision").is_none());
+            let binary = serde_json::json!({"type": "string", "format": "binary"});
+            assert_eq!(document["paths"]["/api/file/{file}/{filename}"]["post"]["requestBody"]["content"]["application/octet-stream"]["schema"], binary);
+            for (path, content_type) in [("/api/file/{file}/{filename}", "application/octet-stream"), ("/api/file/{file}/thumb/{filename}", "image/*"), ("/api/file/{file}/download/{filename}", "application/octet-stream")] {
+                assert_eq!(document["paths"][path]["get"]["responses"]["200"]["content"][content_type]["schema"], binary, "{} is not binary", path);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests_api_replication_scope {
+        use actix_web::{App, test};
+        use actix_web::http::StatusCode;
+        use uuid::Uuid;
+        use crate::api_replication_scope;
+        use think_core::Think;
+        use think_core::replication::service::{ReplicatedRecord, ResponsePull};
+        use think_core::testing::init_pool;
+
+        #[actix_web::test]
+        async fn test_push_and_pull() {
+            let pool = init_pool();
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_replication_scope(&Think::new(&pool).with_mime_types(&[String::from("text/plain")]))
+                    )
+            ).await;
+            let id = Uuid::parse_str("b3a9d6e2-71c4-4f0a-8e5d-2c6b9f1a7e30").unwrap();
+            let document = serde_json::json!({"id": id, "mimeType": "text/plain", "body": "offline", "updatedAt": 0, "_deleted": false});
+
+            let unregistered = serde_json::json!({"id": Uuid::new_v4(), "mimeType": "text/html", "body": "<p>", "updatedAt": 0, "_deleted": false});
+            let req = test::TestRequest::post().uri("/api/replication/records/push")
+                .set_json(serde_json::json!([{"newDocumentState": document}, {"newDocumentState": unregistered}]))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+            let req = test::TestRequest::post().uri("/api/replication/records/push")
+                .set_json(serde_json::json!([{"newDocumentState": document}]))
+                .to_request();
+            let conflicts: Vec<ReplicatedRecord> = test::call_and_read_body_json(&app, req).await;
+            assert!(conflicts.is_empty());
+
+            let req = test::TestRequest::get().uri("/api/replication/records/pull?limit=1000").to_request();
+            let res: ResponsePull = test::call_and_read_body_json(&app, req).await;
+            let master = res.documents.iter().find(|document| document.id == id).unwrap().clone();
+            assert_eq!(master.body, serde_json::json!("offline"));
+            assert!(!master.deleted);
+            let checkpoint = res.checkpoint.unwrap();
+            let req = test::TestRequest::get().uri(format!("/api/replication/records/pull?revision={}", checkpoint.revision).as_str()).to_request();
+            let res: ResponsePull = test::call_and_read_body_json(&app, req).await;
+            assert!(res.documents.is_empty());
+            assert_eq!(res.checkpoint, Some(checkpoint));
+
+            // Pushed again as if the client had never seen the record.
+            let req = test::TestRequest::post().uri("/api/replication/records/push")
+                .set_json(serde_json::json!([{"newDocumentState": document}]))
+                .to_request();
+            let conflicts: Vec<ReplicatedRecord> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(conflicts, vec![master.clone()]);
+
+            let mut deleted = master.clone();
+            deleted.deleted = true;
+            let req = test::TestRequest::post().uri("/api/replication/records/push")
+                .set_json(serde_json::json!([{"newDocumentState": deleted, "assumedMasterState": master}]))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body: Vec<ReplicatedRecord> = test::read_body_json(res).await;
+            assert!(body.is_empty());
+
+            let req = test::TestRequest::get().uri(format!("/api/replication/records/pull?revision={}", master.updated_at).as_str()).to_request();
+            let res: ResponsePull = test::call_and_read_body_json(&app, req).await;
+            assert!(res.documents.iter().any(|document| document.id == id && document.deleted));
+        }
+    }
+
+    #[cfg(test)]
+    mod tests_api_schemas_scope {
+        use actix_web::{App, test};
+        use actix_web::http::header::{CONTENT_TYPE, LINK};
+        use actix_web::http::StatusCode;
+        use uuid::Uuid;
+        use crate::{api_records_scope, api_schemas_scope};
+        use think_core::Think;
+        use think_core::record::service::RequestRecord;
+        use think_core::schema::service::ResponseSchema;
+        use think_core::testing::init_pool;
+
+        #[actix_web::test]
+        async fn test_schema_validates_records() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(api_records_scope(&think))
+                    .service(api_schemas_scope(&think))
+            ).await;
+            let schema = serde_json::json!({"type": "object", "properties": {"title": {"type": "string"}, "done": {"type": "boolean"}}, "required": ["title", "done"]});
+            let id = Uuid::parse_str("e7c2a9f4-1b3d-4e6a-8c5f-9d0b2e4a6c81").unwrap();
+            let save = |body: serde_json::Value| test::TestRequest::post()
+                .uri(format!("/api/records/{}", id).as_str())
+                .set_json(RequestRecord { id, mime_type: String::from("task/json"), body })
+                .to_request();
+
+            let res = test::call_service(&app, save(serde_json::json!({"title": "Milk", "done": false}))).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+            let req = test::TestRequest::put().uri("/api/schemas/task/json").set_json(&schema).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+            let req = test::TestRequest::put().uri("/api/schemas/task/json").set_json(&schema).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+            let req = test::TestRequest::put().uri("/api/schemas/task/json").set_json(serde_json::json!({"type": 12})).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+            let req = test::TestRequest::get().uri("/api/schemas/task/json").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "application/schema+json");
+            let served: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(served, schema);
+            let req = test::TestRequest::get().uri("/api/schemas").to_request();
+            let schemas: Vec<ResponseSchema> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(schemas.iter().map(|schema| schema.mime_type.as_str()).collect::<Vec<&str>>(), vec!["task/json"]);
+
+            let res = test::call_service(&app, save(serde_json::json!({"title": "Milk", "done": "no"}))).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let problem: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(problem["parameter"], "body");
+            let res = test::call_service(&app, save(serde_json::json!({"title": "Milk", "done": false}))).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+
+            let describedby = "</api/schemas/task/json>; rel=\"describedby\"";
+            let req = test::TestRequest::get().uri(format!("/api/records/{}", id).as_str()).to_request();
+            assert_eq!(test::call_service(&app, req).await.headers().get(LINK).unwrap(), describedby);
+            let req = test::TestRequest::get().uri("/api/records").to_request();
+            assert!(test::call_service(&app, req).await.headers().get_all(LINK).any(|link| link == describedby));
+
+            let req = test::TestRequest::delete().uri("/api/schemas/task/json").to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+            let req = test::TestRequest::get().uri("/api/schemas/task/json").to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests_api_collections_scope {
+        use actix_web::{App, test};
+        use actix_web::http::header::LINK;
+        use actix_web::http::StatusCode;
+        use crate::{api_collections_scope, api_records_scope};
+        use think_core::Think;
+        use think_core::collection::service::ResponseCollection;
+        use think_core::testing::init_pool;
+
+        #[actix_web::test]
+        async fn test_collection_records() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(api_records_scope(&think))
+                    .service(api_collections_scope(&think))
+            ).await;
+            let ids = ["e26ed5b1-28f0-4449-bbc1-2e5d48d3c26a", "95a0243b-d0ef-4c0b-afe2-a1292e3a8077", "18c74eda-732f-44af-bf4a-4f923b153dc9"];
+            for (id, status) in ids.iter().zip(["open", "done", "open"]) {
+                let req = test::TestRequest::put().uri(format!("/api/records/{}/attributes/status", id).as_str()).set_json(serde_json::json!({"type": "string", "value": status})).to_request();
+                assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+            }
+
+            let req = test::TestRequest::get().uri("/api/records?fields=id&tags=-%23hashtags").to_request();
+            let records: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert!(records.is_empty());
+            let req = test::TestRequest::get().uri("/api/records?fields=id&tags=hashtags&text=playground%20debug&filter=status%3Dopen").to_request();
+            let records: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(records.len(), 2);
+
+            let req = test::TestRequest::post().uri("/api/collections").set_json(serde_json::json!({
+                "name": "Open tasks tagged hashtags",
+                "query": {"tags": "#hashtags", "filter": "status=open", "sort": "created_at", "order": "asc"},
+            })).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let collection: ResponseCollection = test::read_body_json(res).await;
+
+            let req = test::TestRequest::get().uri(format!("/api/collections/{}/records?fields=id&limit=1", collection.id).as_str()).to_request();
+            let res = test::call_service(&app, req).await;
+            let link = res.headers().get(LINK).unwrap().to_str().unwrap().to_string();
+            let first: Vec<serde_json::Value> = test::read_body_json(res).await;
+            let next = link.trim_start_matches('<').split('>').next().unwrap().to_string();
+            let req = test::TestRequest::get().uri(&next).to_request();
+            let second: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            let mut found: Vec<serde_json::Value> = first.into_iter().chain(second).collect();
+            found.sort_by_key(|record| record["id"].to_string());
+            assert_eq!(found, vec![serde_json::json!({"id": ids[2]}), serde_json::json!({"id": ids[0]})]);
+
+            let req = test::TestRequest::put().uri(format!("/api/collections/{}", collection.id).as_str()).set_json(serde_json::json!({"name": "Done", "query": {"filter": "status=done"}})).to_request();
+            let changed: ResponseCollection = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(changed.name, "Done");
+            let req = test::TestRequest::get().uri(format!("/api/collections/{}/records?fields=id", collection.id).as_str()).to_request();
+            let records: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(records, vec![serde_json::json!({"id": ids[1]})]);
+            let req = test::TestRequest::get().uri("/api/collections").to_request();
+            let collections: Vec<ResponseCollection> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(collections.len(), 1);
+
+            let req = test::TestRequest::post().uri("/api/collections").set_json(serde_json::json!({"name": "Broken", "query": {"filter": "status"}})).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+            let req = test::TestRequest::delete().uri(format!("/api/collections/{}", collection.id).as_str()).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+            let req = test::TestRequest::get().uri(format!("/api/collections/{}/records", collection.id).as_str()).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests_api_storage_scope {
+        use std::io::Cursor;
+        use std::str::FromStr;
+        use image::{DynamicImage, ImageOutputFormat, RgbImage};
+        use actix_web::{App, test};
+        use actix_web::http::StatusCode;
+        use uuid::Uuid;
+        use crate::api_storage_scope;
+        use think_core::Think;
+        use think_core::storage::storage::service::{RequestReadBlob, RequestUploadBlob, ResponseReadMetaDataBlob, ResponseSearchBlobs};
+        use actix_web::http::header::ContentType;
+        use think_core::testing::init_pool;
+
+        #[actix_web::test]
+        async fn test_get_view_file_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            think.files().upload(RequestUploadBlob {
+                id: Uuid::from_str("260fc36a-1295-48a1-906d-93d8e8465732").unwrap(),
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/260fc36a-1295-48a1-906d-93d8e8465732/test.txt").to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success())
+        }
+
+        #[actix_web::test]
+        async fn test_get_view_file_handler_when_file_is_corrupted() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("9b4d6f81-cadb-4e0f-8f1b-9d3f5b7c9e2d").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            pool.get().unwrap().execute(
+                "UPDATE storage SET body = ?1 WHERE id = ?2",
+                [hex::encode("BROKEN").as_str(), id.to_string().as_str()],
+            ).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/9b4d6f81-cadb-4e0f-8f1b-9d3f5b7c9e2d/test.txt").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        #[actix_web::test]
+        async fn test_get_view_file_handler_when_file_is_html() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            think.files().upload(RequestUploadBlob {
+                id: Uuid::from_str("df81a3c5-0314-4148-8e4a-d35f7b9c1e70").unwrap(),
+                body: "<!DOCTYPE html><html><body><script>alert(1)</script></body></html>".as_bytes().to_vec(),
+                path: "report.pdf".to_string(),
+                content_type: Some("application/pdf".to_string()),
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/df81a3c5-0314-4148-8e4a-d35f7b9c1e70/report.pdf").to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success());
+            assert_eq!(res.headers().get("Content-Type").unwrap(), "text/html");
+            assert_eq!(res.headers().get("X-Content-Type-Options").unwrap(), "nosniff");
+            assert!(res.headers().get("Content-Disposition").unwrap().to_str().unwrap().starts_with("attachment"));
+        }
+
+        #[actix_web::test]
+        async fn test_get_thumbnail_file_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let mut png = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(RgbImage::new(300, 150)).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+            think.files().upload(RequestUploadBlob {
+                id: Uuid::from_str("8a3c5e70-becf-4cf3-9f9b-8e0a2c4d6f2b").unwrap(),
+                body: png.into_inner(),
+                path: "image.png".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/8a3c5e70-becf-4cf3-9f9b-8e0a2c4d6f2b/thumb/image.png?w=60&h=60&fit=cover").to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success());
+            assert_eq!(res.headers().get("Content-Type").unwrap(), "image/png");
+            let thumbnail = image::load_from_memory(&test::read_body(res).await).unwrap();
+            assert_eq!((thumbnail.width(), thumbnail.height()), (60, 60));
+        }
+
+        #[actix_web::test]
+        async fn test_get_thumbnail_file_handler_when_file_is_not_image() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            think.files().upload(RequestUploadBlob {
+                id: Uuid::from_str("9b4d6f81-cfd0-4d04-8a0c-9f1b3d5e7a3c").unwrap(),
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/9b4d6f81-cfd0-4d04-8a0c-9f1b3d5e7a3c/thumb/test.txt").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+
+        #[actix_web::test]
+        async fn test_get_download_file_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            think.files().upload(RequestUploadBlob {
+                id: Uuid::from_str("c8f3a893-7d6b-4ace-b2ea-32dbb30c7ff9").unwrap(),
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/c8f3a893-7d6b-4ace-b2ea-32dbb30c7ff9/download/test.txt").to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success())
+        }
+
+        #[actix_web::test]
+        async fn test_get_meta_file_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("85d83734-2af0-41b9-9df4-b3131451e572").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/85d83734-2af0-41b9-9df4-b3131451e572/meta/test.txt").to_request();
+            let res: ResponseReadMetaDataBlob = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res.id, id);
         }
 
         #[actix_web::test]
         async fn test_delete_file_handler() {
-            initialize_db();
             let pool = init_pool();
-            let storage_service = create_service(&pool);
+            let think = Think::new(&pool);
             let app = test::init_service(
                 App::new()
                     .service(
-                        api_storage_scope(&pool)
+                        api_storage_scope(&think)
                     )
             ).await;
             let id = Uuid::from_str("85d83734-2af0-41b9-9df4-b3131451e572").unwrap();
-            storage_service.upload(RequestUploadBlob {
+            think.files().upload(RequestUploadBlob {
                 id,
                 body: "TEST".as_bytes().to_vec(),
                 path: "test.txt".to_string(),
-            });
+                content_type: None,
+            }).unwrap();
             let req = test::TestRequest::delete().uri("/api/file/85d83734-2af0-41b9-9df4-b3131451e572/test.txt").to_request();
             let res = test::call_service(&app, req).await;
             assert!(res.status().is_success());
-            let result = storage_service.read_meta_data(RequestReadBlob { id, filename: "test.txt".to_string() });
+            let result = think.files().read_meta_data(RequestReadBlob { id, filename: "test.txt".to_string() });
             assert!(result.is_err());
+
+            let req = test::TestRequest::delete().uri("/api/file/85d83734-2af0-41b9-9df4-b3131451e572/test.txt").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
         }
 
         #[actix_web::test]
         async fn test_post_file_handler() {
-            initialize_db();
             let pool = init_pool();
-            let storage_service = create_service(&pool);
+            let think = Think::new(&pool);
             let app = test::init_service(
                 App::new()
                     .service(
-                        api_storage_scope(&pool)
+                        api_storage_scope(&think)
                     )
             ).await;
             let id = Uuid::from_str("4d1b9378-6c34-47d1-b27c-da9ab3e6b524").unwrap();
@@ -514,9 +1703,342 @@ pub mod tests {
                     .to_request();
             let response = test::call_service(&app, request).await;
             assert!(response.status().eq(&StatusCode::CREATED));
-            let meta_data = storage_service.read_meta_data(RequestReadBlob{ id, filename: "test.txt".to_string() });
+            let meta_data = think.files().read_meta_data(RequestReadBlob{ id, filename: "test.txt".to_string() });
             assert!(meta_data.is_ok());
         }
+
+        #[actix_web::test]
+        async fn test_post_file_handler_existing() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let request = || test::TestRequest::post()
+                .uri("/api/file/6b8d0f2a-4c5e-4f7a-9b1c-3d5e7f9a1b24/test.txt")
+                .insert_header(("Content-Type", "text/plain;charset=UTF-8"))
+                .set_payload("TEST")
+                .to_request();
+            assert_eq!(test::call_service(&app, request()).await.status(), StatusCode::CREATED);
+
+            let response = test::call_service(&app, request()).await;
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+        }
+
+        #[actix_web::test]
+        async fn test_get_search_files_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            for (id, body) in [("a1c3e5f7-1b2d-4e6f-8a0b-2c4d6e8f0a13", "TEST"), ("b2d4f6a8-2c3e-4f7a-9b1c-3d5e7f9a1b24", "TESTTEST")] {
+                think.files().upload(RequestUploadBlob {
+                    id: Uuid::from_str(id).unwrap(),
+                    body: body.as_bytes().to_vec(),
+                    path: "search-handler.txt".to_string(),
+                    content_type: None,
+                }).unwrap();
+            }
+            let req = test::TestRequest::get().uri("/api/file?filename=search-handler*&mime_type=text/*&sort=size&order=asc&limit=1").to_request();
+            let res: ResponseSearchBlobs = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res.items.len(), 1);
+            assert_eq!(res.items[0].size, 4);
+
+            let req = test::TestRequest::get().uri(&format!("/api/file?filename=search-handler*&sort=size&order=asc&limit=1&cursor={}", res.next_cursor.unwrap())).to_request();
+            let res: ResponseSearchBlobs = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res.items.len(), 1);
+            assert_eq!(res.items[0].size, 8);
+        }
+
+        #[actix_web::test]
+        async fn test_get_search_files_handler_when_cursor_is_malformed() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let req = test::TestRequest::get().uri("/api/file?cursor=nothex").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[actix_web::test]
+        async fn test_post_file_handler_when_quota_exceeded() {
+            let pool = init_pool();
+            let think = Think::new(&pool).with_quota(Some(0));
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let request =
+                test::TestRequest::post()
+                    .uri("/api/file/bd6f81a3-dbec-4f10-9a3d-bf5a7c9d1f4e/test.txt")
+                    .set_payload("TEST".as_bytes().to_vec())
+                    .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+        }
+
+        #[actix_web::test]
+        async fn test_post_file_handler_when_upload_too_large() {
+            let pool = init_pool();
+            let think = Think::new(&pool).with_max_upload_size(Some(3));
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let request =
+                test::TestRequest::post()
+                    .uri("/api/file/c41e8a7b-5f2d-4b96-a3c0-7e9d1b2f6a58/test.txt")
+                    .set_payload("TEST".as_bytes().to_vec())
+                    .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        #[actix_web::test]
+        async fn test_post_file_handler_without_extension() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("e092b4d6-1425-4259-9f5b-e46a8c0d2f81").unwrap();
+            let mut png = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(RgbImage::new(2, 2)).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+            let request =
+                test::TestRequest::post()
+                    .uri("/api/file/e092b4d6-1425-4259-9f5b-e46a8c0d2f81/image")
+                    .insert_header(("Content-Type", "application/octet-stream"))
+                    .set_payload(png.into_inner())
+                    .to_request();
+            let response = test::call_service(&app, request).await;
+            assert!(response.status().eq(&StatusCode::CREATED));
+            let meta_data = think.files().read_meta_data(RequestReadBlob { id, filename: "image".to_string() }).unwrap();
+            assert_eq!(meta_data.mime_type, "image/png");
+        }
+
+        #[actix_web::test]
+        async fn test_get_list_files_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("9a5c3e7f-1b6d-4c0e-8f4a-5b7c9d1e3f60").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test2.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/file/9a5c3e7f-1b6d-4c0e-8f4a-5b7c9d1e3f60").to_request();
+            let res: Vec<ResponseReadMetaDataBlob> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res.len(), 2);
+            assert!(res.iter().all(|blob| blob.id == id));
+        }
+
+        #[actix_web::test]
+        async fn test_patch_file_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("ab6d4f80-2c7e-4d1f-9a5b-6c8d0e2f4a71").unwrap();
+            let new_id = Uuid::from_str("bc7e5a91-3d8f-4e2a-8b6c-7d9e1f3a5b82").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::patch()
+                .uri("/api/file/ab6d4f80-2c7e-4d1f-9a5b-6c8d0e2f4a71/test.txt")
+                .insert_header(ContentType::json())
+                .set_payload("{\"id\":\"bc7e5a91-3d8f-4e2a-8b6c-7d9e1f3a5b82\",\"filename\":\"moved.txt\"}")
+                .to_request();
+            let res: ResponseReadMetaDataBlob = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res.id, new_id);
+            assert_eq!(res.filename, "moved.txt");
+            assert!(think.files().read_meta_data(RequestReadBlob { id, filename: "test.txt".to_string() }).is_err());
+        }
+
+        #[actix_web::test]
+        async fn test_patch_file_handler_when_target_exist() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("cd8f6ba2-4e9a-4f3b-9c7d-8e0f2a4b6c93").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test2.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::patch()
+                .uri("/api/file/cd8f6ba2-4e9a-4f3b-9c7d-8e0f2a4b6c93/test.txt")
+                .insert_header(ContentType::json())
+                .set_payload("{\"id\":\"cd8f6ba2-4e9a-4f3b-9c7d-8e0f2a4b6c93\",\"filename\":\"test2.txt\"}")
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::CONFLICT);
+
+            // Only the file name is kept, so a path naming the same file conflicts too.
+            let req = test::TestRequest::patch()
+                .uri("/api/file/cd8f6ba2-4e9a-4f3b-9c7d-8e0f2a4b6c93/test.txt")
+                .insert_header(ContentType::json())
+                .set_payload("{\"id\":\"cd8f6ba2-4e9a-4f3b-9c7d-8e0f2a4b6c93\",\"filename\":\"dir/test2.txt\"}")
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::CONFLICT);
+            assert!(think.files().read_meta_data(RequestReadBlob { id, filename: "test.txt".to_string() }).is_ok());
+        }
     }
-}
+    #[cfg(test)]
+    mod tests_api_storage_maintenance_scope {
+        use std::str::FromStr;
+        use actix_web::{App, test};
+        use chrono::{TimeZone, Utc};
+        use uuid::Uuid;
+        use crate::api_storage_maintenance_scope;
+        use think_core::Think;
+        use think_core::storage::storage::service::{RequestReadBlob, RequestUploadBlob, ResponseReadMetaDataBlob, ResponseScrub, ResponseUsage};
+        use think_core::testing::init_pool;
+
+        #[actix_web::test]
+        async fn test_get_orphan_files_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_maintenance_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("681a3c5e-9cbe-4ad1-9d7f-6c8e0a2b4d09").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/storage/orphans?grace=-60").to_request();
+            let res: Vec<ResponseReadMetaDataBlob> = test::call_and_read_body_json(&app, req).await;
+            assert!(res.iter().any(|blob| blob.id == id));
+        }
 
+        #[actix_web::test]
+        async fn test_delete_orphan_files_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_maintenance_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("792b4d6f-adcf-4be2-8e8a-7d9f1b3c5e1a").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            // Nothing created after 1950 is old enough to be collected.
+            let grace = (Utc::now() - Utc.with_ymd_and_hms(1950, 1, 1, 0, 0, 0).unwrap()).num_seconds();
+            let req = test::TestRequest::delete().uri(format!("/api/storage/orphans?grace={}", grace).as_str()).to_request();
+            let res: Vec<ResponseReadMetaDataBlob> = test::call_and_read_body_json(&app, req).await;
+            assert!(!res.iter().any(|blob| blob.id == id));
+            assert!(think.files().read_meta_data(RequestReadBlob { id, filename: "test.txt".to_string() }).is_ok());
+        }
+
+        #[actix_web::test]
+        async fn test_get_usage_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_maintenance_scope(&think)
+                    )
+            ).await;
+            think.files().upload(RequestUploadBlob {
+                id: Uuid::from_str("ac5e7092-cadb-4e0f-8f2c-ae4f6b8c0e3d").unwrap(),
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            let req = test::TestRequest::get().uri("/api/storage/usage").to_request();
+            let res: ResponseUsage = test::call_and_read_body_json(&app, req).await;
+            assert!(res.total.files >= 1);
+            assert!(res.by_mime_type.iter().any(|usage| usage.mime_type == "text/plain"));
+        }
+
+        #[actix_web::test]
+        async fn test_get_scrub_files_handler() {
+            let pool = init_pool();
+            let think = Think::new(&pool);
+            let app = test::init_service(
+                App::new()
+                    .service(
+                        api_storage_maintenance_scope(&think)
+                    )
+            ).await;
+            let id = Uuid::from_str("8a3c5e70-b9ca-4dfe-9e0a-8c2e4a6b8d1c").unwrap();
+            think.files().upload(RequestUploadBlob {
+                id,
+                body: "TEST".as_bytes().to_vec(),
+                path: "test.txt".to_string(),
+                content_type: None,
+            }).unwrap();
+            pool.get().unwrap().execute(
+                "UPDATE storage SET body = ?1 WHERE id = ?2",
+                [hex::encode("BROKEN").as_str(), id.to_string().as_str()],
+            ).unwrap();
+            let req = test::TestRequest::get().uri("/api/storage/scrub").to_request();
+            let res: ResponseScrub = test::call_and_read_body_json(&app, req).await;
+            assert!(res.corrupted.iter().any(|blob| blob.id == id));
+        }
+    }
+}
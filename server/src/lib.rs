@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use actix_web::web::Bytes;
+use futures::Stream;
+use r2d2::{ManageConnection, Pool};
+use r2d2_sqlite::SqliteConnectionManager;
+use uuid::Uuid;
+
+use crate::attachment::service::record_attachments;
+use crate::attribute::queries::AttributeValue;
+use crate::attribute::service::{record_attributes, set_attribute, unset_attribute};
+use crate::collection::service::{add_collection, change_collection, collection_records, get_collection, list_collections, remove_collection, RequestCollection, RequestCollectionRecords, ResponseCollection};
+use crate::error::Error;
+use crate::record::service::{add_record, get_record, list_records, Precondition, record_stats, remove_record, RequestListRecords, RequestRecord, ResponseRecord, ResponseRecordStats, ResponseRecords, SavedRecord};
+use crate::record::validation::Validators;
+use crate::replication::service::{pull, pull_stream, push, PushRow, ReplicatedRecord, RequestPull, ResponsePull};
+use crate::schema::service::{get_schema, list_schemas, put_schema, remove_schema, ResponseSchema};
+use crate::storage::storage::service::{ResponseReadMetaDataBlob, Service as StorageService};
+
+pub mod attachment;
+pub mod attribute;
+pub mod collection;
+pub mod error;
+pub mod events;
+pub mod record;
+pub mod replication;
+pub mod schema;
+pub mod storage;
+
+pub mod embedded {
+    use refinery::embed_migrations;
+
+    embed_migrations!("src/embedded/migrations");
+}
+
+/// Entry point for embedding Think, it owns the connection pool every service works on.
+/// Clones share the pool.
+#[derive(Clone)]
+pub struct Think {
+    pool: Pool<SqliteConnectionManager>,
+    files: StorageService,
+    validators: Validators,
+}
+
+impl Think {
+    /// Opens the database at `path`, creating it when missing, and applies pending migrations.
+    pub fn open(path: impl AsRef<Path>, pool_size: u32) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let failed = |action: &str, err: &dyn std::fmt::Display| Error::Database { busy: false, detail: format!("Cannot {} '{}': {}", action, path.display(), err) };
+        let manager = SqliteConnectionManager::file(path);
+        let mut connection = manager.connect().map_err(|err| failed("open", &err))?;
+        embedded::migrations::runner().run(&mut connection).map_err(|err| failed("migrate", &err))?;
+        let pool = Pool::builder().max_size(pool_size).build(manager).map_err(|err| failed("open", &err))?;
+        Ok(Self::new(&pool))
+    }
+
+    /// Works on a pool whose database is already migrated.
+    pub fn new(pool: &Pool<SqliteConnectionManager>) -> Self {
+        Self { pool: pool.clone(), files: StorageService::new(pool), validators: Validators::default() }
+    }
+
+    pub fn with_quota(mut self, quota: Option<usize>) -> Self {
+        self.files = self.files.with_quota(quota);
+        self
+    }
+
+    pub fn with_max_upload_size(mut self, max_upload_size: Option<usize>) -> Self {
+        self.files = self.files.with_max_upload_size(max_upload_size);
+        self
+    }
+
+    /// Accepts records of `mime_types` besides `note/lexical`, their bodies are any JSON within the limits.
+    pub fn with_mime_types(mut self, mime_types: &[String]) -> Self {
+        self.validators = self.validators.with_mime_types(mime_types);
+        self
+    }
+
+    pub fn pool(&self) -> &Pool<SqliteConnectionManager> {
+        &self.pool
+    }
+
+    pub fn records(&self) -> Records<'_> {
+        Records { pool: &self.pool, validators: &self.validators }
+    }
+
+    pub fn files(&self) -> &StorageService {
+        &self.files
+    }
+
+    pub fn collections(&self) -> Collections<'_> {
+        Collections { pool: &self.pool }
+    }
+
+    pub fn schemas(&self) -> Schemas<'_> {
+        Schemas { pool: &self.pool }
+    }
+
+    pub fn replication(&self) -> Replication<'_> {
+        Replication { pool: &self.pool, validators: &self.validators }
+    }
+}
+
+pub struct Records<'a> {
+    pool: &'a Pool<SqliteConnectionManager>,
+    validators: &'a Validators,
+}
+
+impl Records<'_> {
+    pub fn list(&self, request: RequestListRecords) -> Result<ResponseRecords, Error> {
+        list_records(request, self.pool)
+    }
+
+    pub fn get(&self, record_id: Uuid) -> Result<ResponseRecord, Error> {
+        get_record(record_id, self.pool)
+    }
+
+    /// See [`add_record`] for how `precondition` is checked and stale saves are merged.
+    pub fn save(&self, record: RequestRecord, author: Option<String>, precondition: Precondition) -> Result<SavedRecord, Error> {
+        add_record(record, author, precondition, self.validators, self.pool)
+    }
+
+    pub fn remove(&self, record_id: Uuid) -> Result<(), Error> {
+        remove_record(record_id, self.pool)
+    }
+
+    /// Files embedded in the latest revision of the record.
+    pub fn attachments(&self, record_id: Uuid) -> Result<Vec<ResponseReadMetaDataBlob>, Error> {
+        record_attachments(record_id, self.pool)
+    }
+
+    pub fn attributes(&self, record_id: Uuid) -> Result<BTreeMap<String, AttributeValue>, Error> {
+        record_attributes(record_id, self.pool)
+    }
+
+    pub fn set_attribute(&self, record_id: Uuid, name: &str, value: AttributeValue) -> Result<(), Error> {
+        set_attribute(record_id, name, value, self.pool)
+    }
+
+    pub fn unset_attribute(&self, record_id: Uuid, name: &str) -> Result<(), Error> {
+        unset_attribute(record_id, name, self.pool)
+    }
+
+    pub fn stats(&self) -> Result<ResponseRecordStats, Error> {
+        record_stats(self.pool)
+    }
+}
+
+pub struct Collections<'a> {
+    pool: &'a Pool<SqliteConnectionManager>,
+}
+
+impl Collections<'_> {
+    pub fn list(&self) -> Result<Vec<ResponseCollection>, Error> {
+        list_collections(self.pool)
+    }
+
+    pub fn get(&self, collection_id: Uuid) -> Result<ResponseCollection, Error> {
+        get_collection(collection_id, self.pool)
+    }
+
+    pub fn add(&self, collection: RequestCollection) -> Result<ResponseCollection, Error> {
+        add_collection(collection, self.pool)
+    }
+
+    pub fn change(&self, collection_id: Uuid, collection: RequestCollection) -> Result<ResponseCollection, Error> {
+        change_collection(collection_id, collection, self.pool)
+    }
+
+    pub fn remove(&self, collection_id: Uuid) -> Result<(), Error> {
+        remove_collection(collection_id, self.pool)
+    }
+
+    /// Records matching the saved query of the collection right now.
+    pub fn records(&self, collection_id: Uuid, page: RequestCollectionRecords) -> Result<ResponseRecords, Error> {
+        collection_records(collection_id, page, self.pool)
+    }
+}
+
+pub struct Schemas<'a> {
+    pool: &'a Pool<SqliteConnectionManager>,
+}
+
+impl Schemas<'_> {
+    pub fn list(&self) -> Result<Vec<ResponseSchema>, Error> {
+        list_schemas(self.pool)
+    }
+
+    pub fn get(&self, mime_type: &str) -> Result<ResponseSchema, Error> {
+        get_schema(mime_type, self.pool)
+    }
+
+    /// Returns whether `mime_type` had no schema before.
+    pub fn put(&self, mime_type: &str, schema: serde_json::Value) -> Result<bool, Error> {
+        put_schema(mime_type, schema, self.pool)
+    }
+
+    pub fn remove(&self, mime_type: &str) -> Result<(), Error> {
+        remove_schema(mime_type, self.pool)
+    }
+}
+
+pub struct Replication<'a> {
+    pool: &'a Pool<SqliteConnectionManager>,
+    validators: &'a Validators,
+}
+
+impl Replication<'_> {
+    pub fn pull(&self, request: RequestPull) -> Result<ResponsePull, Error> {
+        pull(request, self.pool)
+    }
+
+    /// Returns the master state of every document which could not be written.
+    pub fn push(&self, rows: Vec<PushRow>, author: Option<String>) -> Result<Vec<ReplicatedRecord>, Error> {
+        push(rows, author, self.validators, self.pool)
+    }
+
+    pub fn pull_stream(&self) -> impl Stream<Item = Bytes> {
+        pull_stream(self.pool)
+    }
+}
+
+/// Databases for tests, the binaries' tests get it through the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use std::ops::Deref;
+
+    use r2d2::{ManageConnection, Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use tempfile::TempDir;
+
+    use crate::embedded;
+
+    /// A pool on a database of its own, which is removed with its directory on drop.
+    pub struct TestPool {
+        pool: Pool<SqliteConnectionManager>,
+        _directory: TempDir,
+    }
+
+    impl Deref for TestPool {
+        type Target = Pool<SqliteConnectionManager>;
+
+        fn deref(&self) -> &Self::Target {
+            &self.pool
+        }
+    }
+
+    /// Every test gets its own database, migrated and loaded with the fixtures, so tests
+    /// cannot see each other's writes.
+    pub fn init_pool() -> TestPool {
+        let directory = TempDir::with_prefix("think-tests-").unwrap();
+        let manager = SqliteConnectionManager::file(directory.path().join("think.db"));
+        let mut connection = manager.connect().unwrap();
+        embedded::migrations::runner().run(&mut connection).unwrap();
+        connection.execute_batch(include_str!("fixtures/records_100.sql")).unwrap();
+        connection.close().unwrap();
+        TestPool { pool: Pool::new(manager).unwrap(), _directory: directory }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    use crate::{embedded, Think};
+    use crate::record::service::{Precondition, RequestRecord};
+
+    #[test]
+    fn test_migrate_note_records() {
+        let mut connection = r2d2_sqlite::rusqlite::Connection::open_in_memory().unwrap();
+        embedded::migrations::runner().set_target(refinery::Target::Version(13)).run(&mut connection).unwrap();
+        connection.execute_batch(r#"INSERT INTO records_write (id, mime_type, body, created_at) VALUES ('5d0c6e8a-2f4b-4c1d-9e7a-3b8f6d2c4a10', 'note', '{"root":{"children":[{"children":[{"text":"Groceries","type":"text","version":1}],"type":"heading","version":1}],"type":"root","version":1}}', 1)"#).unwrap();
+
+        embedded::migrations::runner().run(&mut connection).unwrap();
+
+        let (mime_type, body, title): (String, String, String) = connection
+            .query_row("SELECT mime_type, body, title FROM records_write", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        assert_eq!(mime_type, "note/lexical");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["editorState"]["root"]["type"], "root");
+        assert_eq!(title, "Groceries");
+    }
+
+    #[test]
+    fn test_migrate_record_revisions() {
+        let mut connection = r2d2_sqlite::rusqlite::Connection::open_in_memory().unwrap();
+        embedded::migrations::runner().set_target(refinery::Target::Version(14)).run(&mut connection).unwrap();
+        connection.execute_batch(r#"
+            INSERT INTO records_write (id, mime_type, body, created_at) VALUES ('8a3e5c71-4b2d-4f9e-a6c8-1d7b3e9f5a20', 'text/plain', '"second"', 20);
+            INSERT INTO records_write (id, mime_type, body, created_at) VALUES ('8a3e5c71-4b2d-4f9e-a6c8-1d7b3e9f5a20', 'text/plain', '"first"', 10);
+            INSERT INTO records_deleted (id, deleted_at) VALUES ('0f4b6d82-5c3e-4a1f-b7d9-2e8c4f0a6b31', 15);
+        "#).unwrap();
+
+        embedded::migrations::runner().run(&mut connection).unwrap();
+
+        let (revision, body): (i64, String) = connection
+            .query_row("SELECT revision, body FROM records_read", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!((revision, body.as_str()), (2, r#""second""#));
+        let tombstone: i64 = connection.query_row("SELECT revision FROM records_deleted", [], |row| row.get(0)).unwrap();
+        assert_eq!(tombstone, 3);
+        connection.execute("INSERT INTO records_write (id, mime_type, body, created_at) VALUES ('8a3e5c71-4b2d-4f9e-a6c8-1d7b3e9f5a20', 'text/plain', '\"third\"', 20)", []).unwrap();
+        assert_eq!(connection.last_insert_rowid(), 4);
+    }
+
+    #[test]
+    fn test_think_open() {
+        let directory = TempDir::new().unwrap();
+        let think = Think::open(directory.path().join("think.db"), 2).unwrap().with_mime_types(&[String::from("text/plain")]);
+        let id = Uuid::parse_str("d2f7a9c1-3b5e-4e8a-9f16-8c4b7a2e5d03").unwrap();
+        let saved = think.records().save(RequestRecord {
+            id,
+            mime_type: String::from("text/plain"),
+            body: serde_json::json!("embedded"),
+        }, None, Precondition::None).unwrap();
+        assert_eq!(think.records().get(id).unwrap().revision, saved.record.revision);
+        assert_eq!(think.records().stats().unwrap().records, 1);
+
+        think.records().remove(id).unwrap();
+        assert!(think.records().get(id).is_err());
+        assert_eq!(think.records().stats().unwrap().deleted, 1);
+    }
+}
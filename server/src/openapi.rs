@@ -0,0 +1,87 @@
+use utoipa::{Modify, OpenApi};
+use utoipa::openapi::RefOr;
+use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, Schema, SchemaFormat, Type};
+
+use think_core::record::service::{RecordView, SortOrder};
+use think_core::storage::storage::service::{BlobSort, SortOrder as BlobSortOrder, ThumbnailFit};
+
+/// OpenAPI 3 document of the records and files routes, collected from the `utoipa::path`
+/// attribute of each handler. Clients are generated from it, see `/api/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Think",
+        description = "Errors are RFC 9457 problems whose `type` never changes, clients match on it instead of the wording.",
+    ),
+    paths(
+        crate::get_records_handler,
+        crate::get_record_handler,
+        crate::post_record_handler,
+        crate::delete_record_handler,
+        crate::get_record_attachments_handler,
+        crate::get_record_attributes_handler,
+        crate::put_record_attribute_handler,
+        crate::delete_record_attribute_handler,
+        crate::get_search_files_handler,
+        crate::get_list_files_handler,
+        crate::get_view_file_handler,
+        crate::get_meta_file_handler,
+        crate::get_thumbnail_file_handler,
+        crate::get_download_file_handler,
+        crate::post_file_handler,
+        crate::patch_file_handler,
+        crate::delete_file_handler,
+    ),
+    // Query parameters only refer to the schemas of their enums.
+    components(schemas(SortOrder, RecordView, BlobSort, BlobSortOrder, ThumbnailFit)),
+    modifiers(&BinaryBodies),
+    tags(
+        (name = "records", description = "JSON documents of a mime type, every save appends a revision."),
+        (name = "files", description = "Blobs stored under the id of the record embedding them."),
+    ),
+)]
+pub struct ApiDoc;
+
+/// File contents are declared as `Vec<u8>`, which utoipa describes as an array of integers,
+/// over the wire they are the bytes as is.
+struct BinaryBodies;
+
+impl Modify for BinaryBodies {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let binary: RefOr<Schema> = ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::Binary)))
+            .into();
+        for item in openapi.paths.paths.values_mut() {
+            let operations = [&mut item.get, &mut item.put, &mut item.post, &mut item.delete, &mut item.patch];
+            for operation in operations.into_iter().flatten() {
+                let requests = operation.request_body.iter_mut().flat_map(|body| body.content.iter_mut());
+                let responses = operation.responses.responses.values_mut()
+                    .filter_map(|response| match response {
+                        RefOr::T(response) => Some(response.content.iter_mut()),
+                        RefOr::Ref(_) => None,
+                    })
+                    .flatten();
+                for (content_type, content) in requests.chain(responses) {
+                    if content_type == "application/octet-stream" || content_type.starts_with("image/") {
+                        content.schema = Some(binary.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Think has no license yet, the one taken from the manifest would be nameless.
+pub fn document() -> utoipa::openapi::OpenApi {
+    let mut document = ApiDoc::openapi();
+    document.info.license = None;
+    document
+}
+
+/// Swagger UI at `/api/docs/` reading the document from `/api/openapi.json`.
+#[cfg(feature = "swagger-ui")]
+pub fn swagger_ui() -> utoipa_swagger_ui::SwaggerUi {
+    utoipa_swagger_ui::SwaggerUi::new("/api/docs/{_:.*}")
+        .config(utoipa_swagger_ui::Config::from("/api/openapi.json"))
+}
@@ -1,126 +1,1013 @@
+#[allow(clippy::module_inception)]
 pub mod record {
     use serde::{Serialize};
     use uuid::Uuid;
 
+    use crate::record::merge::BlockConflict;
+    use crate::record::service::ResponseRecord;
 
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum PreconditionFailure {
+        /// The record exists but the save did not say which revision it is based on.
+        Required,
+        /// The save is based on an older revision of the record.
+        Stale,
+        /// The save is based on a revision the record never had, or expected a missing record to exist.
+        Unknown,
+    }
+
+    /// Carries the latest revision so the client can rebase its edit without another round trip.
     #[derive(Debug, Serialize)]
-    pub struct ErrNoId {
+    pub struct ErrPrecondition {
         pub id: Uuid,
         pub err: String,
+        pub current: Option<Box<ResponseRecord>>,
+        /// Blocks both sides changed when a stale save could not be merged.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub conflicts: Vec<BlockConflict>,
+        #[serde(skip)]
+        pub failure: PreconditionFailure,
     }
 }
 
 
-pub mod http {
-    use actix_web::{HttpResponse, ResponseError};
-    use actix_web::body::BoxBody;
-    use actix_web::http::StatusCode;
-
-    use crate::record::record::ErrNoId;
-
-    impl ResponseError for ErrNoId {
-        fn status_code(&self) -> StatusCode {
-            StatusCode::NOT_FOUND
-        }
-
-        fn error_response(&self) -> HttpResponse<BoxBody> {
-            let body = serde_json::to_string(&self).unwrap();
-            let res = HttpResponse::new(self.status_code());
-            res.set_body(BoxBody::new(body))
-        }
-    }
-
-    // Implement Display for ErrNoId
-    impl std::fmt::Display for ErrNoId {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{:?}", self)
-        }
-    }
-
-}
-
 pub mod service {
     use chrono::{DateTime, Utc};
     use r2d2::{Pool};
     use r2d2_sqlite::SqliteConnectionManager;
     use uuid::Uuid;
     use serde::{Deserialize, Serialize};
+    use utoipa::{IntoParams, ToSchema};
 
-    use crate::record::queries::{delete_record, insert_record, select_record, select_records, WriteRecord};
-    use crate::record::record::{ErrNoId};
+    use crate::attachment::service::{link_attachments, unlink_attachments};
+    use crate::attribute::queries::delete_record_attributes;
+    use crate::attribute::service::parse_filter;
+    use crate::record::queries::{delete_record, insert_record, Latest, ReadRecord, select_record, select_records, RecordSearch, RecordSortColumn, RecordSortKey, select_revision_body, select_stats, WriteRecord};
+    use crate::record::merge::merge;
+    use crate::events::service::{Event, publish};
+    use crate::error::{Error, OptionalExt};
+    use crate::record::record::{ErrPrecondition, PreconditionFailure};
+    use crate::record::validation::Validators;
+    use crate::schema::queries::select_schema;
 
-    #[derive(Deserialize, Serialize)]
+    const DEFAULT_LIST_FIELDS: [&str; 10] = ["id", "mime_type", "title", "excerpt", "body", "created_at", "updated_at", "revision_count", "created_by", "updated_by"];
+    const SUMMARY_LIST_FIELDS: [&str; 9] = ["id", "mime_type", "title", "excerpt", "created_at", "updated_at", "revision_count", "created_by", "updated_by"];
+    const MAX_LIST_LIMIT: usize = 1000;
+    const ATTRIBUTE_SORT_PREFIX: &str = "attribute:";
+
+    #[derive(Deserialize, Serialize, ToSchema)]
     pub struct RequestRecord {
         pub id: Uuid,
         pub mime_type: String,
         pub body: serde_json::Value,
     }
 
-    #[derive(Deserialize, Serialize)]
+    #[derive(Deserialize, Serialize, Debug, ToSchema)]
     pub struct ResponseRecord {
+        /// Identifies the latest revision, it is sent as the `ETag` rather than in the body.
+        #[serde(skip)]
+        pub revision: i64,
         pub id: Uuid,
         pub mime_type: String,
+        pub title: String,
+        pub excerpt: String,
         pub body: serde_json::Value,
+        pub created_at: DateTime<Utc>,
         pub updated_at: DateTime<Utc>,
+        pub revision_count: usize,
+        /// Identity sent by the client which wrote the first revision, if any.
+        pub created_by: Option<String>,
+        /// Identity sent by the client which wrote the latest revision, if any.
+        pub updated_by: Option<String>,
     }
 
-    pub fn all_records(pool: &Pool<SqliteConnectionManager>) -> Vec<ResponseRecord> {
-        select_records(pool).into_iter().map(|read_record| ResponseRecord {
-            id: read_record.id,
-            mime_type: read_record.mime_type,
-            body: read_record.body,
-            updated_at: read_record.updated_at,
-        }).collect()
-    }
-
-    pub fn get_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseRecord, ErrNoId> {
-        let read_record = select_record(record_id, &pool);
-        if read_record.is_ok() {
-            let record = read_record.unwrap();
-            return Ok(ResponseRecord {
+    impl ResponseRecord {
+        fn from_read_record(record: ReadRecord) -> Self {
+            Self {
+                revision: record.revision,
                 id: record.id,
                 mime_type: record.mime_type,
+                title: record.title,
+                excerpt: record.excerpt,
                 body: record.body,
+                created_at: record.created_at,
                 updated_at: record.updated_at,
-            });
+                revision_count: record.revision_count,
+                created_by: record.created_by,
+                updated_by: record.updated_by,
+            }
+        }
+    }
+
+    /// `updated_at`, `created_at`, `title` or `attribute:{name}`.
+    #[derive(Deserialize, Serialize, Clone, Default, PartialEq, Debug)]
+    #[serde(try_from = "String", into = "String")]
+    pub enum RecordSort {
+        #[default]
+        UpdatedAt,
+        CreatedAt,
+        Title,
+        /// Only records which have the attribute are listed.
+        Attribute(String),
+    }
+
+    impl TryFrom<String> for RecordSort {
+        type Error = String;
+
+        fn try_from(sort: String) -> Result<Self, Self::Error> {
+            match sort.as_str() {
+                "updated_at" => Ok(RecordSort::UpdatedAt),
+                "created_at" => Ok(RecordSort::CreatedAt),
+                "title" => Ok(RecordSort::Title),
+                _ => match sort.strip_prefix(ATTRIBUTE_SORT_PREFIX) {
+                    Some(name) if !name.is_empty() => Ok(RecordSort::Attribute(name.to_string())),
+                    _ => Err(format!("Unknown sort '{}', expected updated_at, created_at, title or {}{{name}}", sort, ATTRIBUTE_SORT_PREFIX)),
+                },
+            }
         }
-        Err(read_record.err().unwrap())
     }
 
-    pub fn add_record(record: RequestRecord, pool: &Pool<SqliteConnectionManager>) {
-        let current = Utc::now();
-        insert_record(
+    impl From<RecordSort> for String {
+        fn from(sort: RecordSort) -> Self {
+            match sort {
+                RecordSort::UpdatedAt => String::from("updated_at"),
+                RecordSort::CreatedAt => String::from("created_at"),
+                RecordSort::Title => String::from("title"),
+                RecordSort::Attribute(name) => format!("{}{}", ATTRIBUTE_SORT_PREFIX, name),
+            }
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug, ToSchema)]
+    #[serde(rename_all = "lowercase")]
+    pub enum SortOrder {
+        Asc,
+        #[default]
+        Desc,
+    }
+
+    /// `summary` is every property but `body`, enough to render a list of notes.
+    #[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug, ToSchema)]
+    #[serde(rename_all = "lowercase")]
+    pub enum RecordView {
+        #[default]
+        Full,
+        Summary,
+    }
+
+    /// `fields` is a comma separated subset of the record properties and takes precedence over `view`,
+    /// leaving out `body` keeps the bodies out of the query. Without `limit` every record is returned in one page.
+    /// `filter` holds comma separated attribute predicates, see [`parse_filter`]. `tags` and `text`
+    /// are explained at [`parse_tags`] and [`parse_terms`].
+    #[derive(Deserialize, Serialize, Default, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct RequestListRecords {
+        pub limit: Option<usize>,
+        pub cursor: Option<String>,
+        /// `updated_at`, `created_at`, `title` or `attribute:{name}`.
+        #[serde(default)]
+        #[param(value_type = String)]
+        pub sort: RecordSort,
+        #[serde(default)]
+        pub order: SortOrder,
+        #[serde(default)]
+        pub view: RecordView,
+        pub fields: Option<String>,
+        pub filter: Option<String>,
+        pub tags: Option<String>,
+        pub text: Option<String>,
+    }
+
+    /// Tags separated by commas or spaces, `#` is optional. Records have to carry every tag, as a
+    /// hashtag in their body, and none of those starting with `-`. Returns the wanted and the
+    /// excluded tags, lowercase.
+    pub fn parse_tags(tags: &str) -> Result<(Vec<String>, Vec<String>), Error> {
+        let mut wanted = Vec::new();
+        let mut excluded = Vec::new();
+        for tag in tags.split([',', ' ']).filter(|tag| !tag.is_empty()) {
+            let (list, tag) = match tag.strip_prefix('-') {
+                Some(tag) => (&mut excluded, tag),
+                None => (&mut wanted, tag),
+            };
+            let tag = tag.strip_prefix('#').unwrap_or(tag);
+            if tag.is_empty() || tag.contains('#') {
+                return Err(Error::validation("tags", format!("'{}' is not a tag", tag)));
+            }
+            list.push(tag.to_ascii_lowercase());
+        }
+        Ok((wanted, excluded))
+    }
+
+    /// Terms separated by whitespace, each has to occur in the text of a record, ignoring ASCII case.
+    pub fn parse_terms(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    pub struct ResponseRecords {
+        pub records: Vec<serde_json::Map<String, serde_json::Value>>,
+        pub next_cursor: Option<String>,
+    }
+
+    /// The cursor is the sort key and id of the last record of a page, opaque to clients.
+    fn encode_cursor(key: &RecordSortKey, id: Uuid) -> String {
+        hex::encode(serde_json::to_string(&(key, id)).unwrap())
+    }
+
+    fn decode_cursor(cursor: &str) -> Option<(RecordSortKey, Uuid)> {
+        let decoded = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+        serde_json::from_str(&decoded).ok()
+    }
+
+    pub fn list_records(request: RequestListRecords, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseRecords, Error> {
+        let fields: Vec<String> = match request.fields.as_deref() {
+            Some(fields) => fields.split(',').map(|field| field.trim().to_string()).filter(|field| !field.is_empty()).collect(),
+            None => match request.view {
+                RecordView::Full => DEFAULT_LIST_FIELDS.iter().map(|field| field.to_string()).collect(),
+                RecordView::Summary => SUMMARY_LIST_FIELDS.iter().map(|field| field.to_string()).collect(),
+            },
+        };
+        if let Some(unknown) = fields.iter().find(|field| !DEFAULT_LIST_FIELDS.contains(&field.as_str())) {
+            return Err(Error::validation("fields", format!("Unknown field '{}', expected any of {}", unknown, DEFAULT_LIST_FIELDS.join(", "))));
+        }
+        let after = match request.cursor.as_deref() {
+            Some(cursor) => Some(decode_cursor(cursor).ok_or_else(|| Error::validation("cursor", "Cursor is malformed, pass the cursor of a previous page unchanged"))?),
+            None => None,
+        };
+        let limit = request.limit.map(|limit| limit.clamp(1, MAX_LIST_LIMIT));
+        let sort = match request.sort {
+            RecordSort::UpdatedAt => RecordSortColumn::UpdatedAt,
+            RecordSort::CreatedAt => RecordSortColumn::CreatedAt,
+            RecordSort::Title => RecordSortColumn::Title,
+            RecordSort::Attribute(name) => RecordSortColumn::Attribute(name),
+        };
+        let filters = match request.filter.as_deref() {
+            Some(filter) => parse_filter(filter)?,
+            None => Vec::new(),
+        };
+        let (tags, excluded_tags) = match request.tags.as_deref() {
+            Some(tags) => parse_tags(tags)?,
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let rows = select_records(RecordSearch {
+            sort,
+            descending: request.order == SortOrder::Desc,
+            after,
+            limit,
+            with_body: fields.iter().any(|field| field == "body"),
+            filters,
+            tags,
+            excluded_tags,
+            terms: request.text.as_deref().map(parse_terms).unwrap_or_default(),
+        }, pool)?;
+
+        let next_cursor = match (rows.last(), limit) {
+            (Some((last, key)), Some(limit)) if rows.len() == limit => Some(encode_cursor(key, last.id)),
+            _ => None,
+        };
+        let records = rows.into_iter().map(|(read_record, _)| {
+            let record = serde_json::to_value(ResponseRecord::from_read_record(read_record)).unwrap();
+            match record {
+                serde_json::Value::Object(mut map) => {
+                    map.retain(|key, _| fields.contains(key));
+                    map
+                }
+                _ => unreachable!("a record always serializes to an object"),
+            }
+        }).collect();
+
+        Ok(ResponseRecords { records, next_cursor })
+    }
+
+    pub fn get_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseRecord, Error> {
+        select_record(record_id, pool).map(ResponseRecord::from_read_record)
+    }
+
+    /// Which revision the client based its save on, taken from `If-Match`.
+    pub enum Precondition {
+        /// Without `If-Match` only a record which does not exist yet can be saved.
+        None,
+        /// `If-Match: *`, the record has to exist.
+        Exists,
+        Revisions(Vec<i64>),
+    }
+
+    pub struct SavedRecord {
+        pub record: ResponseRecord,
+        /// The save was based on an older revision and got merged with the latest one.
+        pub merged: bool,
+    }
+
+    /// Checks `body` with the validator of `mime_type` and the JSON Schema stored for it.
+    pub fn validate_body(mime_type: &str, body: &serde_json::Value, validators: &Validators, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let schema = select_schema(mime_type, pool)?;
+        validators.validate(mime_type, body, schema.as_ref().map(|schema| &schema.schema))
+    }
+
+    /// Appends a revision when `precondition` holds for the latest one, which is checked again
+    /// by the insert itself so concurrent saves cannot both pass. A save based on an older
+    /// revision is merged with the latest one when both changed different blocks. The body
+    /// has to pass [`validate_body`] first.
+    pub fn add_record(mut record: RequestRecord, author: Option<String>, precondition: Precondition, validators: &Validators, pool: &Pool<SqliteConnectionManager>) -> Result<SavedRecord, Error> {
+        validate_body(&record.mime_type, &record.body, validators, pool)?;
+        let current = select_record(record.id, pool).optional()?;
+        let latest = current.as_ref().map(|current| current.revision);
+        // The newest revision the client named, the common ancestor of its edit and the latest revision.
+        let base = match &precondition {
+            Precondition::Revisions(revisions) => {
+                let mut revisions: Vec<i64> = revisions.iter().copied().filter(|revision| Some(*revision) != latest).collect();
+                revisions.sort_unstable_by(|a, b| b.cmp(a));
+                revisions.into_iter()
+                    .map(|revision| select_revision_body(record.id, revision, pool))
+                    .find_map(|body| body.transpose())
+                    .transpose()?
+            }
+            _ => None,
+        };
+        let mut failure = match (&precondition, latest) {
+            (Precondition::None, None) => None,
+            (Precondition::None, Some(_)) => Some(PreconditionFailure::Required),
+            (Precondition::Exists, Some(_)) => None,
+            (Precondition::Exists, None) => Some(PreconditionFailure::Unknown),
+            (Precondition::Revisions(revisions), Some(latest)) if revisions.contains(&latest) => None,
+            (Precondition::Revisions(_), Some(_)) if base.is_some() => Some(PreconditionFailure::Stale),
+            (Precondition::Revisions(_), _) => Some(PreconditionFailure::Unknown),
+        };
+        let mut conflicts = Vec::new();
+        let mut merged = false;
+        if let (Some(PreconditionFailure::Stale), Some(base), Some(current)) = (failure, &base, &current) {
+            if current.mime_type == record.mime_type {
+                match merge(&record.mime_type, base, &record.body, &current.body) {
+                    Ok(body) => {
+                        record.body = body;
+                        merged = true;
+                        failure = None;
+                    }
+                    Err(block_conflicts) => conflicts = block_conflicts,
+                }
+            }
+        }
+        if let Some(failure) = failure {
+            return Err(ErrPrecondition {
+                id: record.id,
+                err: match failure {
+                    PreconditionFailure::Required => format!("Record '{}' exists, send If-Match with its ETag", record.id),
+                    PreconditionFailure::Stale => format!("Record '{}' changed since the revision in If-Match", record.id),
+                    PreconditionFailure::Unknown => format!("Record '{}' has no revision matching If-Match", record.id),
+                },
+                current: current.map(|current| Box::new(ResponseRecord::from_read_record(current))),
+                conflicts,
+                failure,
+            }.into());
+        }
+
+        let id = record.id;
+        let body = record.body.clone();
+        let inserted = insert_record(
             WriteRecord {
                 id: record.id,
                 mime_type: record.mime_type,
                 body: record.body,
-                created_at: current,
+                created_at: Utc::now(),
+                author,
+            }
+            , match latest {
+                Some(latest) => Latest::Revision(latest),
+                None => Latest::Absent,
+            }, pool)?;
+        if !inserted {
+            return Err(ErrPrecondition {
+                id,
+                err: format!("Record '{}' changed while saving", id),
+                current: select_record(id, pool).optional()?.map(|current| Box::new(ResponseRecord::from_read_record(current))),
+                conflicts: Vec::new(),
+                failure: PreconditionFailure::Stale,
+            }.into());
+        }
+        link_attachments(id, &body, pool)?;
+        let saved = select_record(id, pool).map(ResponseRecord::from_read_record)?;
+        publish(match latest {
+            Some(_) => Event::RecordUpdated { id, revision: saved.revision },
+            None => Event::RecordCreated { id, revision: saved.revision },
+        });
+        Ok(SavedRecord {
+            record: saved,
+            merged,
+        })
+    }
+
+    pub fn remove_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        if !delete_record(record_id, pool)? {
+            return Err(Error::NotFound(format!("Record '{}' not found", record_id)));
+        }
+        unlink_attachments(record_id, pool)?;
+        delete_record_attributes(record_id, pool)?;
+        publish(Event::RecordDeleted { id: record_id });
+        Ok(())
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct ResponseRecordStats {
+        pub records: usize,
+        pub revisions: usize,
+        /// Tombstones kept for replicas which have not synced the deletion yet.
+        pub deleted: usize,
+    }
+
+    pub fn record_stats(pool: &Pool<SqliteConnectionManager>) -> Result<ResponseRecordStats, Error> {
+        let (records, revisions, deleted) = select_stats(pool)?;
+        Ok(ResponseRecordStats { records, revisions, deleted })
+    }
+}
+
+mod summary {
+    use serde_json::Value;
+
+    const LEXICAL_MIME_TYPE: &str = "note/lexical";
+    const TITLE_MAX_CHARS: usize = 120;
+    const EXCERPT_MAX_CHARS: usize = 240;
+
+    pub struct Summary {
+        pub title: String,
+        pub excerpt: String,
+    }
+
+    /// The first heading of a Lexical note, or its first line, is the title; the remaining lines
+    /// make up the excerpt. Other mime types have no summary.
+    pub fn summarize(mime_type: &str, body: &Value) -> Summary {
+        let blocks = match body.pointer("/editorState/root/children").and_then(|children| children.as_array()) {
+            Some(blocks) if mime_type == LEXICAL_MIME_TYPE => blocks,
+            _ => return Summary { title: String::new(), excerpt: String::new() },
+        };
+
+        let mut lines: Vec<(bool, String)> = Vec::new();
+        for block in blocks {
+            let is_heading = block.get("type").and_then(|t| t.as_str()) == Some("heading");
+            let mut text = String::new();
+            collect_text(block, &mut text);
+            for line in text.lines() {
+                let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+                if !line.is_empty() {
+                    lines.push((is_heading, line));
+                }
             }
-            , &pool)
+        }
+
+        let title_index = lines.iter().position(|(is_heading, _)| *is_heading).unwrap_or(0);
+        let title = lines.get(title_index).map(|(_, line)| truncate(line, TITLE_MAX_CHARS)).unwrap_or_default();
+        let excerpt = lines.iter()
+            .enumerate()
+            .filter(|(index, _)| *index != title_index)
+            .map(|(_, (_, line))| line.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        Summary { title, excerpt: truncate(&excerpt, EXCERPT_MAX_CHARS) }
     }
 
-    pub fn remove_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) {
-        delete_record(record_id, &pool)
+    fn collect_text(node: &Value, text: &mut String) {
+        match node.get("type").and_then(|t| t.as_str()) {
+            Some("text") => text.push_str(node.get("text").and_then(|t| t.as_str()).unwrap_or_default()),
+            Some("linebreak") => text.push('\n'),
+            _ => {}
+        }
+        if let Some(children) = node.get("children").and_then(|children| children.as_array()) {
+            for child in children {
+                collect_text(child, text);
+                // List items and other nested blocks read as separate lines.
+                if child.get("type").and_then(|t| t.as_str()) == Some("listitem") {
+                    text.push('\n');
+                }
+            }
+        }
+    }
+
+    fn truncate(text: &str, max_chars: usize) -> String {
+        if text.chars().count() <= max_chars {
+            return text.to_string();
+        }
+        let mut truncated: String = text.chars().take(max_chars - 1).collect();
+        truncated.truncate(truncated.trim_end().len());
+        truncated.push('…');
+        truncated
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::record::summary::summarize;
+
+        #[test]
+        fn test_summarize_with_heading() {
+            let body: serde_json::Value = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[{\"children\":[{\"text\":\"Intro\",\"type\":\"text\",\"version\":1}],\"type\":\"paragraph\",\"version\":1},{\"children\":[{\"text\":\"Welcome to the \",\"type\":\"text\",\"version\":1},{\"text\":\"playground\",\"type\":\"text\",\"version\":1}],\"type\":\"heading\",\"tag\":\"h1\",\"version\":1},{\"children\":[{\"text\":\"First line\",\"type\":\"text\",\"version\":1},{\"type\":\"linebreak\",\"version\":1},{\"text\":\"second   line\",\"type\":\"text\",\"version\":1}],\"type\":\"paragraph\",\"version\":1}],\"type\":\"root\",\"version\":1}}}").unwrap();
+
+            let summary = summarize("note/lexical", &body);
+
+            assert_eq!(summary.title, "Welcome to the playground");
+            assert_eq!(summary.excerpt, "Intro First line second line");
+        }
+
+        #[test]
+        fn test_summarize_without_heading() {
+            let body: serde_json::Value = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[{\"children\":[],\"type\":\"paragraph\",\"version\":1},{\"children\":[{\"text\":\"Groceries\",\"type\":\"text\",\"version\":1},{\"type\":\"linebreak\",\"version\":1},{\"text\":\"milk\",\"type\":\"text\",\"version\":1}],\"type\":\"paragraph\",\"version\":1}],\"type\":\"root\",\"version\":1}}}").unwrap();
+
+            let summary = summarize("note/lexical", &body);
+
+            assert_eq!(summary.title, "Groceries");
+            assert_eq!(summary.excerpt, "milk");
+        }
+
+        #[test]
+        fn test_summarize_truncates() {
+            let text = "word ".repeat(100);
+            let body = serde_json::json!({"editorState": {"root": {"children": [{"children": [{"text": text, "type": "text"}], "type": "heading"}], "type": "root"}}});
+
+            let summary = summarize("note/lexical", &body);
+
+            assert_eq!(summary.title.chars().count(), 120);
+            assert!(summary.title.ends_with("word…"));
+        }
+
+        #[test]
+        fn test_summarize_when_mime_type_is_not_lexical() {
+            let summary = summarize("application/json", &serde_json::json!({"editorState": {}}));
+            assert!(summary.title.is_empty() && summary.excerpt.is_empty());
+        }
+    }
+}
+
+mod merge {
+    use serde::Serialize;
+    use serde_json::Value;
+    use utoipa::ToSchema;
+
+    const LEXICAL_MIME_TYPE: &str = "note/lexical";
+    const BLOCKS_POINTER: &str = "/editorState/root/children";
+
+    /// A run of top-level blocks both sides changed, `index` is where it sits in the base revision.
+    #[derive(Debug, Serialize, PartialEq, ToSchema)]
+    pub struct BlockConflict {
+        pub index: usize,
+        pub base: Vec<Value>,
+        pub ours: Vec<Value>,
+        pub theirs: Vec<Value>,
+    }
+
+    /// Three-way merge of two Lexical bodies derived from `base`, block by block over the
+    /// top-level children of the root. Everything besides the blocks is taken from `ours`.
+    /// Other mime types cannot be merged and conflict as a whole.
+    pub fn merge(mime_type: &str, base: &Value, ours: &Value, theirs: &Value) -> Result<Value, Vec<BlockConflict>> {
+        let blocks = |body: &Value| body.pointer(BLOCKS_POINTER).and_then(|blocks| blocks.as_array()).cloned();
+        let (base_blocks, our_blocks, their_blocks) = match (blocks(base), blocks(ours), blocks(theirs)) {
+            (Some(base_blocks), Some(our_blocks), Some(their_blocks)) if mime_type == LEXICAL_MIME_TYPE => (base_blocks, our_blocks, their_blocks),
+            _ => return Err(vec![BlockConflict {
+                index: 0,
+                base: vec![base.clone()],
+                ours: vec![ours.clone()],
+                theirs: vec![theirs.clone()],
+            }]),
+        };
+
+        let blocks = merge_blocks(&base_blocks, &our_blocks, &their_blocks)?;
+        let mut merged = ours.clone();
+        *merged.pointer_mut(BLOCKS_POINTER).unwrap() = Value::Array(blocks);
+        Ok(merged)
+    }
+
+    /// diff3: blocks left unchanged by both sides anchor the merge, the runs between anchors
+    /// take whichever side changed them and conflict when both did differently.
+    fn merge_blocks(base: &[Value], ours: &[Value], theirs: &[Value]) -> Result<Vec<Value>, Vec<BlockConflict>> {
+        let our_matches = longest_common_subsequence(base, ours);
+        let their_matches = longest_common_subsequence(base, theirs);
+
+        let mut merged: Vec<Value> = Vec::new();
+        let mut conflicts: Vec<BlockConflict> = Vec::new();
+        let (mut b, mut o, mut t) = (0, 0, 0);
+        loop {
+            let anchor = (b..base.len()).find_map(|index| match (our_matches[index], their_matches[index]) {
+                (Some(our_index), Some(their_index)) if our_index >= o && their_index >= t => Some((index, our_index, their_index)),
+                _ => None,
+            });
+            let (base_end, our_end, their_end) = anchor.unwrap_or((base.len(), ours.len(), theirs.len()));
+
+            let (base_run, our_run, their_run) = (&base[b..base_end], &ours[o..our_end], &theirs[t..their_end]);
+            if our_run == base_run || our_run == their_run {
+                merged.extend_from_slice(their_run);
+            } else if their_run == base_run {
+                merged.extend_from_slice(our_run);
+            } else {
+                conflicts.push(BlockConflict {
+                    index: b,
+                    base: base_run.to_vec(),
+                    ours: our_run.to_vec(),
+                    theirs: their_run.to_vec(),
+                });
+            }
+
+            match anchor {
+                Some(_) => {
+                    merged.push(base[base_end].clone());
+                    (b, o, t) = (base_end + 1, our_end + 1, their_end + 1);
+                }
+                None => break,
+            }
+        }
+
+        if conflicts.is_empty() { Ok(merged) } else { Err(conflicts) }
+    }
+
+    /// For each element of `base`, the index of the element it is matched with in `other`.
+    fn longest_common_subsequence(base: &[Value], other: &[Value]) -> Vec<Option<usize>> {
+        let mut lengths = vec![vec![0usize; other.len() + 1]; base.len() + 1];
+        for i in (0..base.len()).rev() {
+            for j in (0..other.len()).rev() {
+                lengths[i][j] = if base[i] == other[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+
+        let mut matches = vec![None; base.len()];
+        let (mut i, mut j) = (0, 0);
+        while i < base.len() && j < other.len() {
+            if base[i] == other[j] {
+                matches[i] = Some(j);
+                i += 1;
+                j += 1;
+            } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        matches
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::{json, Value};
+
+        use crate::record::merge::merge;
+
+        fn note(blocks: &[&str]) -> Value {
+            json!({"editorState": {"root": {"children": blocks.iter().map(|text| json!({"children": [{"text": text, "type": "text"}], "type": "paragraph"})).collect::<Vec<Value>>(), "type": "root"}}})
+        }
+
+        #[test]
+        fn test_merge_when_edits_touch_different_blocks() {
+            let base = note(&["a", "b", "c"]);
+            let ours = note(&["a", "b edited", "c"]);
+            let theirs = note(&["inserted", "a", "b", "c", "appended"]);
+
+            let merged = merge("note/lexical", &base, &ours, &theirs).unwrap();
+
+            assert_eq!(merged, note(&["inserted", "a", "b edited", "c", "appended"]));
+        }
+
+        #[test]
+        fn test_merge_when_both_removed_the_same_block() {
+            let base = note(&["a", "b", "c"]);
+            let ours = note(&["a", "c"]);
+            let theirs = note(&["a", "c", "d"]);
+
+            assert_eq!(merge("note/lexical", &base, &ours, &theirs).unwrap(), note(&["a", "c", "d"]));
+        }
+
+        #[test]
+        fn test_merge_when_edits_touch_the_same_block() {
+            let base = note(&["a", "b", "c"]);
+            let ours = note(&["a", "ours", "c"]);
+            let theirs = note(&["a", "theirs", "c"]);
+
+            let conflicts = merge("note/lexical", &base, &ours, &theirs).unwrap_err();
+
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].index, 1);
+            assert_eq!(conflicts[0].base, vec![note(&["b"])["editorState"]["root"]["children"][0].clone()]);
+            assert_eq!(conflicts[0].ours.len(), 1);
+            assert_eq!(conflicts[0].theirs.len(), 1);
+        }
+
+        #[test]
+        fn test_merge_when_mime_type_is_not_lexical() {
+            let body = json!({"value": 1});
+            assert!(merge("application/json", &body, &body, &body).is_err());
+        }
+    }
+}
+
+pub mod validation {
+    use std::collections::HashMap;
+
+    use jsonschema::JSONSchema;
+    use serde_json::Value;
+
+    use crate::error::Error;
+
+    const LEXICAL_MIME_TYPE: &str = "note/lexical";
+    /// Every node Lexical and the editor's plugins serialize at this version.
+    const LEXICAL_VERSION: u64 = 1;
+    const LEXICAL_NODE_TYPES: &[&str] = &[
+        "root", "paragraph", "text", "linebreak", "tab", "heading", "quote", "list", "listitem",
+        "code", "code-highlight", "table", "tablerow", "tablecell", "tablesheet", "hashtag", "link",
+        "autolink", "overflow", "mark", "sticky", "image", "mention", "emoji", "equation",
+        "autocomplete", "keyword", "horizontalrule", "youtube", "collapsible-container",
+        "collapsible-content", "collapsible-title", "record",
+    ];
+    /// Largest body as serialized JSON, whatever its mime type.
+    const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+    /// Deepest nesting of arrays and objects in a body.
+    const MAX_DEPTH: usize = 64;
+
+    /// Checks a body of one mime type, `Err` tells what is malformed.
+    pub type Validator = fn(&Value) -> Result<(), String>;
+
+    /// The mime types records may have, each with the check its bodies must pass. Only
+    /// `note/lexical` is known by default, others are registered through the settings or by
+    /// storing a JSON Schema for them.
+    #[derive(Clone)]
+    pub struct Validators {
+        by_mime_type: HashMap<String, Validator>,
+    }
+
+    impl Default for Validators {
+        fn default() -> Self {
+            Self { by_mime_type: HashMap::new() }.register(LEXICAL_MIME_TYPE, validate_lexical)
+        }
+    }
+
+    impl Validators {
+        pub fn register(mut self, mime_type: &str, validator: Validator) -> Self {
+            self.by_mime_type.insert(mime_type.to_string(), validator);
+            self
+        }
+
+        /// Accepts bodies of `mime_types` within the size and depth limits, mime types with a
+        /// validator of their own keep it.
+        pub fn with_mime_types(mut self, mime_types: &[String]) -> Self {
+            for mime_type in mime_types {
+                self.by_mime_type.entry(mime_type.clone()).or_insert(|_| Ok(()));
+            }
+            self
+        }
+
+        /// `schema` is the JSON Schema stored for `mime_type`, it applies on top of the validator.
+        pub fn validate(&self, mime_type: &str, body: &Value, schema: Option<&Value>) -> Result<(), Error> {
+            let validator = self.by_mime_type.get(mime_type);
+            if validator.is_none() && schema.is_none() {
+                return Err(Error::validation("mime_type", format!("Mime type '{}' is not registered", mime_type)));
+            }
+            let size = serde_json::to_vec(body).map_or(usize::MAX, |bytes| bytes.len());
+            if size > MAX_BODY_SIZE {
+                return Err(Error::validation("body", format!("Body of {} bytes exceeds the limit of {} bytes", size, MAX_BODY_SIZE)));
+            }
+            if depth(body) > MAX_DEPTH {
+                return Err(Error::validation("body", format!("Body is nested deeper than {} levels", MAX_DEPTH)));
+            }
+            if let Some(validator) = validator {
+                validator(body).map_err(|detail| Error::validation("body", detail))?;
+            }
+            if let Some(schema) = schema {
+                let compiled = compile_schema(schema).map_err(|detail| Error::Integrity(format!("Stored schema of '{}' is invalid: {}", mime_type, detail)))?;
+                let first_error = compiled.validate(body).err().and_then(|mut errors| errors.next().map(|err| format!("{}: {}", err.instance_path, err)));
+                if let Some(detail) = first_error {
+                    return Err(Error::validation("body", detail));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Rejects documents which are not a JSON Schema, `Err` tells why.
+    pub fn compile_schema(schema: &Value) -> Result<JSONSchema, String> {
+        JSONSchema::compile(schema).map_err(|err| format!("{}: {}", err.schema_path, err))
+    }
+
+    fn depth(value: &Value) -> usize {
+        match value {
+            Value::Array(values) => 1 + values.iter().map(depth).max().unwrap_or(0),
+            Value::Object(values) => 1 + values.values().map(depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// The editor cannot load a state whose `editorState.root` is not a tree of nodes it knows.
+    fn validate_lexical(body: &Value) -> Result<(), String> {
+        let root = body.pointer("/editorState/root").ok_or("Missing /editorState/root")?;
+        if root.get("type").and_then(|t| t.as_str()) != Some("root") {
+            return Err(String::from("/editorState/root: type must be 'root'"));
+        }
+        validate_node(root, "/editorState/root")
+    }
+
+    fn validate_node(node: &Value, pointer: &str) -> Result<(), String> {
+        let node_type = node.get("type").and_then(|t| t.as_str()).ok_or_else(|| format!("{}: type is missing", pointer))?;
+        if !LEXICAL_NODE_TYPES.contains(&node_type) {
+            return Err(format!("{}: unknown node type '{}'", pointer, node_type));
+        }
+        match node.get("version").and_then(|version| version.as_u64()) {
+            Some(version) if (1..=LEXICAL_VERSION).contains(&version) => {}
+            Some(version) => return Err(format!("{}: unsupported version {} of '{}'", pointer, version, node_type)),
+            None => return Err(format!("{}: version is missing", pointer)),
+        }
+        if node_type == "text" && !node.get("text").is_some_and(|text| text.is_string()) {
+            return Err(format!("{}: text node without text", pointer));
+        }
+        let children = match node.get("children") {
+            None if node_type == "root" => return Err(format!("{}: children are missing", pointer)),
+            None => return Ok(()),
+            Some(children) => children.as_array().ok_or_else(|| format!("{}/children: must be an array", pointer))?,
+        };
+        for (index, child) in children.iter().enumerate() {
+            let pointer = format!("{}/children/{}", pointer, index);
+            if child.get("type").and_then(|t| t.as_str()) == Some("root") {
+                return Err(format!("{}: root can only be the top node", pointer));
+            }
+            validate_node(child, &pointer)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use crate::error::Error;
+        use crate::record::validation::Validators;
+
+        fn note(children: serde_json::Value) -> serde_json::Value {
+            json!({"editorState": {"root": {"children": children, "type": "root", "version": 1}}})
+        }
+
+        fn rejected(mime_type: &str, body: &serde_json::Value) -> (String, String) {
+            match Validators::default().validate(mime_type, body, None).unwrap_err() {
+                Error::Validation { parameter, detail } => (parameter, detail),
+                err => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        #[test]
+        fn test_validate_lexical() {
+            let body = note(json!([{"children": [{"text": "Hello", "type": "text", "version": 1}, {"type": "linebreak", "version": 1}], "type": "paragraph", "version": 1}]));
+            assert!(Validators::default().validate("note/lexical", &body, None).is_ok());
+        }
+
+        #[test]
+        fn test_validate_lexical_malformed() {
+            assert_eq!(rejected("note/lexical", &json!({"editorState": {}})).1, "Missing /editorState/root");
+            assert_eq!(rejected("note/lexical", &note(json!({}))).1, "/editorState/root/children: must be an array");
+            let (parameter, detail) = rejected("note/lexical", &note(json!([{"children": [{"type": "widget", "version": 1}], "type": "paragraph", "version": 1}])));
+            assert_eq!(parameter, "body");
+            assert_eq!(detail, "/editorState/root/children/0/children/0: unknown node type 'widget'");
+            assert!(rejected("note/lexical", &note(json!([{"type": "paragraph", "version": 7}]))).1.contains("unsupported version 7"));
+            assert!(rejected("note/lexical", &note(json!([{"type": "paragraph"}]))).1.contains("version is missing"));
+            assert!(rejected("note/lexical", &note(json!([{"type": "text", "version": 1}]))).1.contains("text node without text"));
+        }
+
+        #[test]
+        fn test_validate_limits() {
+            let mut nested = json!({"type": "paragraph", "version": 1});
+            for _ in 0..40 {
+                nested = json!({"children": [nested], "type": "paragraph", "version": 1});
+            }
+            assert!(rejected("note/lexical", &note(json!([nested]))).1.contains("nested deeper"));
+            let text = "a".repeat(5 * 1024 * 1024);
+            assert!(rejected("note/lexical", &note(json!([{"text": text, "type": "text", "version": 1}]))).1.contains("exceeds the limit"));
+        }
+
+        #[test]
+        fn test_validate_mime_types() {
+            assert_eq!(rejected("text/plain", &json!("plain")).0, "mime_type");
+            let validators = Validators::default().with_mime_types(&[String::from("text/plain"), String::from("note/lexical")]);
+            assert!(validators.validate("text/plain", &json!("plain"), None).is_ok());
+            assert!(validators.validate("note/lexical", &json!("plain"), None).is_err());
+        }
+
+        #[test]
+        fn test_validate_schema() {
+            let schema = json!({"type": "object", "properties": {"done": {"type": "boolean"}}, "required": ["title"]});
+            let validators = Validators::default();
+            assert!(validators.validate("task/json", &json!({"title": "Milk", "done": false}), Some(&schema)).is_ok());
+            let err = validators.validate("task/json", &json!({"title": "Milk", "done": "no"}), Some(&schema)).unwrap_err();
+            assert!(matches!(err, Error::Validation { parameter, detail } if parameter == "body" && detail.starts_with("/done: ")));
+            assert!(validators.validate("task/json", &json!({"done": true}), Some(&schema)).is_err());
+        }
     }
 }
 
 pub mod queries {
-    use std::str::FromStr;
     use serde::{Deserialize, Serialize};
 
     use chrono::{DateTime, TimeZone, Utc};
     use r2d2::{Pool};
     use r2d2_sqlite::SqliteConnectionManager;
+    use r2d2_sqlite::rusqlite::{OptionalExtension, params_from_iter, Row, ToSql};
     use uuid::Uuid;
 
-    use crate::record::record::{ErrNoId};
+    use crate::attribute::queries::{attribute_expression, AttributePredicate};
+    use crate::error::{Error, parse_column};
+    use crate::record::summary::summarize;
+
+    pub enum RecordSortColumn {
+        UpdatedAt,
+        CreatedAt,
+        Title,
+        /// Value of the attribute with this name.
+        Attribute(String),
+    }
+
+    /// Value of the sort column of a record, as stored.
+    #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+    #[serde(untagged)]
+    pub enum RecordSortKey {
+        Integer(i64),
+        Real(f64),
+        Text(String),
+    }
+
+    impl ToSql for RecordSortKey {
+        fn to_sql(&self) -> r2d2_sqlite::rusqlite::Result<r2d2_sqlite::rusqlite::types::ToSqlOutput<'_>> {
+            match self {
+                RecordSortKey::Integer(key) => key.to_sql(),
+                RecordSortKey::Real(key) => key.to_sql(),
+                RecordSortKey::Text(key) => key.to_sql(),
+            }
+        }
+    }
+
+    pub struct RecordSearch {
+        pub sort: RecordSortColumn,
+        pub descending: bool,
+        /// Sort key and id of the last record already returned.
+        pub after: Option<(RecordSortKey, Uuid)>,
+        pub limit: Option<usize>,
+        /// Without it `body` is read as `null`.
+        pub with_body: bool,
+        /// Every predicate has to hold.
+        pub filters: Vec<AttributePredicate>,
+        /// Lowercase hashtags without `#` the body has to contain.
+        pub tags: Vec<String>,
+        pub excluded_tags: Vec<String>,
+        /// Each has to occur in a text node of the body, ignoring ASCII case.
+        pub terms: Vec<String>,
+    }
+
+    impl Default for RecordSearch {
+        fn default() -> Self {
+            Self {
+                sort: RecordSortColumn::UpdatedAt,
+                descending: true,
+                after: None,
+                limit: None,
+                with_body: true,
+                filters: Vec::new(),
+                tags: Vec::new(),
+                excluded_tags: Vec::new(),
+                terms: Vec::new(),
+            }
+        }
+    }
+
+    /// Hashtag nodes of `records_read rr` whose lowercase text is the parameter at `index`.
+    fn hashtag_query(index: usize) -> String {
+        format!("SELECT 1 FROM json_tree(rr.body) jt WHERE jt.type = 'object' AND json_extract(jt.value, '$.type') = 'hashtag' AND lower(json_extract(jt.value, '$.text')) = ?{}", index)
+    }
 
     #[derive(Deserialize, Serialize)]
     pub struct ReadRecord {
+        /// Number of the latest revision, unique across records and increasing with every save.
+        #[serde(skip)]
+        pub revision: i64,
         pub id: Uuid,
         pub mime_type: String,
         pub body: serde_json::Value,
         pub updated_at: DateTime<Utc>,
+        pub title: String,
+        pub excerpt: String,
+        pub created_at: DateTime<Utc>,
+        pub revision_count: usize,
+        pub created_by: Option<String>,
+        pub updated_by: Option<String>,
+    }
+
+    const READ_COLUMNS: &str = "rr.id, rr.mime_type, rr.body, rr.updated_at, rr.title, rr.excerpt, rr.created_at, rr.revision_count, rr.created_by, rr.updated_by, rr.revision";
+
+    fn read_record(row: &Row) -> r2d2_sqlite::rusqlite::Result<ReadRecord> {
+        Ok(ReadRecord {
+            revision: row.get::<_, i64>(10)?,
+            id: parse_column(row, 0)?,
+            mime_type: row.get::<_, String>(1)?,
+            body: row.get::<_, serde_json::Value>(2)?,
+            updated_at: Utc.timestamp_opt(row.get::<_, i64>(3)?, 0).unwrap(),
+            title: row.get::<_, String>(4)?,
+            excerpt: row.get::<_, String>(5)?,
+            created_at: Utc.timestamp_opt(row.get::<_, i64>(6)?, 0).unwrap(),
+            revision_count: row.get::<_, usize>(7)?,
+            created_by: row.get::<_, Option<String>>(8)?,
+            updated_by: row.get::<_, Option<String>>(9)?,
+        })
     }
 
     #[derive(Deserialize, Serialize)]
@@ -129,66 +1016,168 @@ pub mod queries {
         pub mime_type: String,
         pub body: serde_json::Value,
         pub created_at: DateTime<Utc>,
+        pub author: Option<String>,
     }
 
-    pub fn select_records(pool: &Pool<SqliteConnectionManager>) -> Vec<ReadRecord> {
-        let connection = pool.get().unwrap();
-        let mut stmt = connection.prepare("SELECT rr.id, rr.mime_type, rr.body, rr.updated_at FROM records_read rr ORDER BY updated_at DESC").unwrap();
+    /// Keyset pagination over `(sort column, id)`, stable since the id is unique in `records_read`.
+    pub fn select_records(search: RecordSearch, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<(ReadRecord, RecordSortKey)>, Error> {
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+        let mut conditions: Vec<String> = Vec::new();
+        let expression = match search.sort {
+            RecordSortColumn::UpdatedAt => String::from("rr.updated_at"),
+            RecordSortColumn::CreatedAt => String::from("rr.created_at"),
+            RecordSortColumn::Title => String::from("rr.title"),
+            RecordSortColumn::Attribute(name) => {
+                params.push(Box::new(name));
+                let expression = attribute_expression(params.len());
+                // A missing key would compare as NULL and end the keyset pagination.
+                conditions.push(format!("{} IS NOT NULL", expression));
+                expression
+            }
+        };
+        for predicate in search.filters {
+            conditions.push(predicate.condition(params.len() + 1));
+            let type_name = predicate.value.type_name();
+            params.push(Box::new(predicate.name));
+            params.push(Box::new(predicate.value));
+            params.push(Box::new(type_name));
+        }
+        for (tags, operator) in [(search.tags, "EXISTS"), (search.excluded_tags, "NOT EXISTS")] {
+            for tag in tags {
+                params.push(Box::new(format!("#{}", tag)));
+                conditions.push(format!("{} ({})", operator, hashtag_query(params.len())));
+            }
+        }
+        for term in search.terms {
+            let escaped = term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            params.push(Box::new(format!("%{}%", escaped)));
+            conditions.push(format!("EXISTS (SELECT 1 FROM json_tree(rr.body) jt WHERE jt.key = 'text' AND jt.type = 'text' AND jt.value LIKE ?{} ESCAPE '\\')", params.len()));
+        }
+        let (comparison, direction) = if search.descending { ("<", "DESC") } else { (">", "ASC") };
+        if let Some((key, id)) = search.after {
+            params.push(Box::new(key));
+            params.push(Box::new(id.to_string()));
+            conditions.push(format!("({}, rr.id) {} (?{}, ?{})", expression, comparison, params.len() - 1, params.len()));
+        }
+        let limit = match search.limit {
+            Some(limit) => {
+                params.push(Box::new(limit as i64));
+                format!("LIMIT ?{}", params.len())
+            }
+            None => String::new(),
+        };
+        let sql = format!(
+            "SELECT {}, {} FROM records_read rr {} ORDER BY {} {}, rr.id {} {}",
+            if search.with_body { READ_COLUMNS.to_string() } else { READ_COLUMNS.replace("rr.body", "'null'") },
+            expression,
+            if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) },
+            expression, direction, direction,
+            limit,
+        );
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare(sql.as_str())?;
 
-        let result_of_records = stmt.query_map([], |row| Ok(ReadRecord {
-            id: Uuid::from_str(&row.get_unwrap::<_, String>(0)).unwrap(),
-            mime_type: row.get_unwrap::<_, String>(1),
-            body: row.get_unwrap::<_, serde_json::Value>(2),
-            updated_at: Utc.timestamp_millis_opt(row.get_unwrap::<_, i64>(3)).unwrap(),
-        }));
+        let result_of_records = stmt.query_map(params_from_iter(params.iter()), |row| Ok((read_record(row)?, sort_key(row, 11)?)))?;
 
-        let mut records: Vec<ReadRecord> = Vec::new();
+        let mut records: Vec<(ReadRecord, RecordSortKey)> = Vec::new();
 
-        for result_of_record in result_of_records.unwrap() {
-            records.push(result_of_record.unwrap());
+        for result_of_record in result_of_records {
+            records.push(result_of_record?);
         }
 
-        records
+        Ok(records)
     }
 
-    pub fn select_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<ReadRecord, ErrNoId> {
-        let connection = pool.get().unwrap();
-        let mut stmt = connection.prepare("SELECT rr.id, rr.mime_type, rr.body, rr.updated_at FROM records_read rr WHERE id = ?1 LIMIT 1").unwrap();
+    fn sort_key(row: &Row, index: usize) -> r2d2_sqlite::rusqlite::Result<RecordSortKey> {
+        Ok(match row.get::<_, r2d2_sqlite::rusqlite::types::Value>(index)? {
+            r2d2_sqlite::rusqlite::types::Value::Integer(key) => RecordSortKey::Integer(key),
+            r2d2_sqlite::rusqlite::types::Value::Real(key) => RecordSortKey::Real(key),
+            r2d2_sqlite::rusqlite::types::Value::Text(key) => RecordSortKey::Text(key),
+            _ => RecordSortKey::Text(String::new()),
+        })
+    }
+
+    pub fn select_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<ReadRecord, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare(format!("SELECT {} FROM records_read rr WHERE id = ?1 LIMIT 1", READ_COLUMNS).as_str())?;
+
+        stmt.query_row([record_id.to_string().as_str()], read_record)
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("Record '{}' not found", record_id)))
+    }
 
-        let result_of_record = stmt.query_row([record_id.to_string().as_str()], |row| Ok(ReadRecord {
-            id: Uuid::from_str(&row.get_unwrap::<_, String>(0)).unwrap(),
-            mime_type: row.get_unwrap::<_, String>(1),
-            body: row.get_unwrap::<_, serde_json::Value>(2),
-            updated_at: Utc.timestamp_millis_opt(row.get_unwrap::<_, i64>(3)).unwrap(),
-        }));
+    /// The revision a write expects to follow.
+    pub enum Latest {
+        Absent,
+        Revision(i64),
+    }
 
-        match result_of_record {
-            Ok(v) => Ok(v),
-            Err(r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows) => Err(ErrNoId {
-                id: record_id,
-                err: String::from(format!("Record '{}' not found", record_id)),
-            }),
-            Err(e) => panic!("Error: {}", e)
+    /// Title and excerpt are derived from the body here, so every revision carries its own summary.
+    /// Returns false, without writing, when the latest revision is not the expected one.
+    pub fn insert_record(record: WriteRecord, latest: Latest, pool: &Pool<SqliteConnectionManager>) -> Result<bool, Error> {
+        let summary = summarize(&record.mime_type, &record.body);
+        let connection = pool.get()?;
+        let condition = match latest {
+            Latest::Absent => "NOT EXISTS (SELECT 1 FROM records_write WHERE id = ?1)",
+            Latest::Revision(_) => "(SELECT MAX(revision) FROM records_write WHERE id = ?1) = ?8",
+        };
+        let mut stmt = connection.prepare(format!("INSERT INTO records_write (id, mime_type, body, created_at, title, excerpt, author) SELECT ?1,?2,?3,?4,?5,?6,?7 WHERE {}", condition).as_str())?;
+        let record_id = record.id.to_string();
+        let mut params: Vec<Box<dyn ToSql>> = vec![
+            Box::new(record_id.clone()),
+            Box::new(record.mime_type),
+            Box::new(record.body.to_string()),
+            Box::new(record.created_at.timestamp()),
+            Box::new(summary.title),
+            Box::new(summary.excerpt),
+            Box::new(record.author),
+        ];
+        if let Latest::Revision(revision) = latest {
+            params.push(Box::new(revision));
+        }
+        let inserted = stmt.execute(params_from_iter(params.iter()))? == 1;
+        if inserted {
+            connection.execute("DELETE FROM records_deleted WHERE id = ?1", [record_id.as_str()])?;
         }
+        Ok(inserted)
     }
 
-    pub fn insert_record(record: WriteRecord, pool: &Pool<SqliteConnectionManager>) {
-        let connection = pool.get().unwrap();
-        let mut stmt = connection.prepare("INSERT INTO records_write (id, mime_type, body, created_at) VALUES (?1,?2,?3,?4)").unwrap();
-        stmt.execute([
-            record.id.to_string().as_str(),
-            record.mime_type.as_str(),
-            &record.body.to_string(),
-            &record.created_at.timestamp().to_string(),
-        ]).unwrap();
+    pub fn select_revision_body(record_id: Uuid, revision: i64, pool: &Pool<SqliteConnectionManager>) -> Result<Option<serde_json::Value>, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("SELECT body FROM records_write WHERE id = ?1 AND revision = ?2")?;
+        Ok(stmt.query_row(r2d2_sqlite::rusqlite::params![record_id.to_string(), revision], |row| row.get::<_, serde_json::Value>(0)).optional()?)
+    }
+
+    /// Leaves a tombstone behind, numbered after the last revision, so replicas learn about the deletion.
+    /// False when there was no such record.
+    pub fn delete_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<bool, Error> {
+        let mut connection = pool.get()?;
+        let transaction = connection.transaction()?;
+        let deleted = transaction.execute("DELETE FROM records_write WHERE id = ?1", [record_id.to_string().as_str()])? > 0;
+        if deleted {
+            // Tombstones share the sequence numbering the revisions of records_write.
+            let revision = transaction.query_row(
+                "UPDATE sqlite_sequence SET seq = seq + 1 WHERE name = 'records_write' RETURNING seq",
+                [],
+                |row| row.get::<_, i64>(0),
+            )?;
+            transaction.execute(
+                "INSERT INTO records_deleted (id, deleted_at, revision) VALUES (?1, ?2, ?3)",
+                r2d2_sqlite::rusqlite::params![record_id.to_string(), Utc::now().timestamp(), revision],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(deleted)
     }
 
-    pub fn delete_record(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) {
-        let connection = pool.get().unwrap();
-        let mut stmt = connection.prepare("DELETE FROM records_write WHERE id = ?1").unwrap();
-        stmt.execute([
-            record_id.to_string().as_str(),
-        ]).unwrap();
+    /// Counts of records, of all their revisions and of tombstones.
+    pub fn select_stats(pool: &Pool<SqliteConnectionManager>) -> Result<(usize, usize, usize), Error> {
+        let connection = pool.get()?;
+        Ok(connection.query_row(
+            "SELECT (SELECT COUNT(DISTINCT id) FROM records_write), (SELECT COUNT(*) FROM records_write), (SELECT COUNT(*) FROM records_deleted)",
+            [],
+            |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?, row.get::<_, usize>(2)?)),
+        )?)
     }
 
     #[cfg(test)]
@@ -196,12 +1185,30 @@ pub mod queries {
         use chrono::{TimeZone, Utc};
         use uuid::Uuid;
 
-        use crate::record::queries::{delete_record, insert_record, select_record, select_records, WriteRecord};
-        use crate::tests::{init_pool, initialize_db};
+        use crate::error::Error;
+        use crate::events::service::{Event, subscribe};
+        use crate::record::queries::{delete_record, insert_record, Latest, RecordSearch, RecordSortColumn, RecordSortKey, select_record, select_records, WriteRecord};
+        use crate::record::service::{add_record, Precondition, remove_record, RequestRecord};
+        use crate::record::validation::Validators;
+        use crate::testing::init_pool;
+
+        #[test]
+        fn test_select_record_timestamps() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("4e6a8c0d-2f3b-4d5e-9a7c-1b3d5f7a9c20").unwrap();
+            let body = serde_json::json!({"editorState": {"root": {"children": [], "type": "root", "version": 1}}});
+            add_record(RequestRecord { id, mime_type: String::from("note/lexical"), body: body.clone() }, None, Precondition::None, &Validators::default(), &pool).unwrap();
+            let revision = select_record(id, &pool).unwrap().revision;
+            add_record(RequestRecord { id, mime_type: String::from("note/lexical"), body }, None, Precondition::Revisions(vec![revision]), &Validators::default(), &pool).unwrap();
+
+            let record = select_record(id, &pool).unwrap();
+            for timestamp in [record.created_at, record.updated_at] {
+                assert!((Utc::now() - timestamp).num_seconds().abs() < 60, "{} is not now", timestamp);
+            }
+        }
 
         #[test]
         fn test_insert_record() {
-            initialize_db();
             let pool = init_pool();
             let id = Uuid::parse_str("0cdc6d0f-bbbc-455d-914d-61f211fcece2").unwrap();
             let json_str_body = "{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}";
@@ -212,9 +1219,10 @@ pub mod queries {
                 mime_type: String::from("note/lexical"),
                 body: json_body,
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
+                author: None,
             };
 
-            insert_record(requested_record, &pool);
+            insert_record(requested_record, Latest::Absent, &pool).unwrap();
 
             let result = select_record(id, &pool);
             assert!(result.is_ok());
@@ -224,7 +1232,6 @@ pub mod queries {
 
         #[test]
         fn test_select_records() {
-            initialize_db();
             let pool = init_pool();
 
             let inserted_json_str_body = "{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1083367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}";
@@ -236,27 +1243,101 @@ pub mod queries {
                 mime_type: String::from("note/lexical"),
                 body: inserted_json_body.clone(),
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
-            }, &pool);
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
             insert_record(WriteRecord {
                 id: Uuid::parse_str("5b0fc422-8d76-47c9-895b-6e5f057b27ff").unwrap(),
                 mime_type: String::from("note/lexical"),
                 body: inserted_json_body.clone(),
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
-            }, &pool);
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
             insert_record(WriteRecord {
                 id: Uuid::parse_str("386e609e-d3df-438e-9451-1af8928b5da3").unwrap(),
                 mime_type: String::from("note/lexical"),
                 body: inserted_json_body.clone(),
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
-            }, &pool);
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
 
-            let records = select_records(&pool);
+            let records = select_records(RecordSearch::default(), &pool).unwrap();
             assert!(records.len() >= 3);
         }
 
+        #[test]
+        fn test_select_records_after_cursor() {
+            let pool = init_pool();
+            let search = |after: Option<(RecordSortKey, Uuid)>| select_records(RecordSearch {
+                sort: RecordSortColumn::Title,
+                descending: false,
+                after,
+                limit: Some(2),
+                with_body: false,
+                ..Default::default()
+            }, &pool).unwrap();
+
+            let first = search(None);
+            assert_eq!(first.len(), 2);
+            assert!(first.iter().all(|(record, _)| record.body.is_null()));
+
+            let (last, key) = &first[1];
+            let second = search(Some((key.clone(), last.id)));
+            assert_eq!(second.len(), 2);
+            assert!(second.iter().all(|(record, _)| first.iter().all(|(seen, _)| seen.id != record.id)));
+            assert!(matches!((&first[1].1, &second[0].1), (RecordSortKey::Text(a), RecordSortKey::Text(b)) if a <= b));
+        }
+
+        #[test]
+        fn test_select_record_revisions() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("a7b9c1d3-7e8f-4a2b-9c4d-8e0f2a4b6c79").unwrap();
+            let body: serde_json::Value = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"type\":\"root\",\"version\":1}}}").unwrap();
+
+            for (created_at, author) in [(1, Some("alice")), (2, None), (3, Some("bob"))] {
+                let latest = select_record(id, &pool).map(|record| Latest::Revision(record.revision)).unwrap_or(Latest::Absent);
+                insert_record(WriteRecord {
+                    id,
+                    mime_type: String::from("note/lexical"),
+                    body: body.clone(),
+                    created_at: Utc.timestamp_opt(created_at, 0).unwrap(),
+                    author: author.map(String::from),
+                }, latest, &pool).unwrap();
+            }
+
+            let record = select_record(id, &pool).unwrap();
+            assert_eq!(record.revision_count, 3);
+            assert_eq!(record.created_by.as_deref(), Some("alice"));
+            assert_eq!(record.updated_by.as_deref(), Some("bob"));
+            assert!(record.created_at < record.updated_at);
+        }
+
+        #[test]
+        fn test_insert_record_when_latest_changed() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("c9dbe3f5-9a0b-4c4d-9e6f-a02b4c6d8e9b").unwrap();
+            let body: serde_json::Value = serde_json::from_str("{\"editorState\":{\"root\":{\"children\":[],\"type\":\"root\",\"version\":1}}}").unwrap();
+            // Every save lands within the same second, only the revision tells them apart.
+            let write = |latest: Latest| insert_record(WriteRecord {
+                id,
+                mime_type: String::from("note/lexical"),
+                body: body.clone(),
+                created_at: Utc.timestamp_opt(10, 0).unwrap(),
+                author: None,
+            }, latest, &pool).unwrap();
+
+            assert!(write(Latest::Absent));
+            let first = select_record(id, &pool).unwrap().revision;
+            assert!(!write(Latest::Absent));
+            assert!(write(Latest::Revision(first)));
+            let second = select_record(id, &pool).unwrap().revision;
+            assert!(second > first);
+            assert!(!write(Latest::Revision(first)));
+            assert_eq!(select_record(id, &pool).unwrap().revision, second);
+            assert_eq!(select_record(id, &pool).unwrap().revision_count, 2);
+        }
+
         #[test]
         fn test_select_record() {
-            initialize_db();
             let pool = init_pool();
             let id = Uuid::parse_str("08766606-db6a-4ceb-8400-f4d2d542ab07").unwrap();
 
@@ -268,9 +1349,10 @@ pub mod queries {
                 mime_type: String::from("note/lexical"),
                 body: inserted_json_body,
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
+                author: None,
             };
 
-            insert_record(inserted_record, &pool);
+            insert_record(inserted_record, Latest::Absent, &pool).unwrap();
 
 
             let result = select_record(id, &pool);
@@ -281,7 +1363,6 @@ pub mod queries {
 
         #[test]
         fn test_delete_record() {
-            initialize_db();
             let pool = init_pool();
             let id = Uuid::parse_str("c26f6038-a956-459c-8856-710e7dfc0867").unwrap();
             let json_str_body = "{\"editorState\":{\"root\":{\"children\":[],\"direction\":\"ltr\",\"format\":\"\",\"indent\":0,\"type\":\"root\",\"version\":1}},\"lastSaved\":1683367373153,\"source\":\"Playground\",\"version\":\"0.10.0\"}";
@@ -292,11 +1373,27 @@ pub mod queries {
                 mime_type: String::from("note/lexical"),
                 body: json_body,
                 created_at: Utc.timestamp_millis_opt(1).unwrap(),
-            }, &pool);
-            delete_record(id, &pool);
+                author: None,
+            }, Latest::Absent, &pool).unwrap();
+            assert!(delete_record(id, &pool).unwrap());
 
             let requested_record = select_record(id, &pool);
             assert!(requested_record.is_err());
+            assert!(!delete_record(id, &pool).unwrap());
+        }
+
+        #[test]
+        fn test_remove_missing_record() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("5e7a9c1b-3d4f-4a6b-8c0d-2e4f6a8b0c13").unwrap();
+            let mut receiver = subscribe();
+
+            assert!(matches!(remove_record(id, &pool), Err(Error::NotFound(_))));
+
+            // Other tests publish to the same subscribers, only events about this record count.
+            while let Ok(event) = receiver.try_recv() {
+                assert_ne!(event, Event::RecordDeleted { id });
+            }
         }
     }
 }
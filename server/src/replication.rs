@@ -0,0 +1,247 @@
+pub mod service {
+    use actix_web::web::Bytes;
+    use futures::{stream, Stream, StreamExt};
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    use crate::error::Error;
+    use crate::events::service::{Event, subscribe, with_keep_alive};
+    use crate::record::service::{add_record, Precondition, remove_record, RequestRecord, validate_body};
+    use crate::record::validation::Validators;
+    use crate::replication::queries::{select_changes, select_document};
+
+    const DEFAULT_PULL_LIMIT: usize = 100;
+    const MAX_PULL_LIMIT: usize = 1000;
+
+    /// A record as RxDB sees it, `updatedAt` is the revision number which is also the record's `ETag`.
+    #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ReplicatedRecord {
+        pub id: Uuid,
+        pub mime_type: String,
+        pub body: serde_json::Value,
+        pub updated_at: i64,
+        #[serde(rename = "_deleted", default)]
+        pub deleted: bool,
+    }
+
+    /// Position in the change feed, the revision of the last change pulled. Every save and
+    /// deletion takes the next number of one sequence, so no later change can sort before it.
+    #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+    pub struct Checkpoint {
+        pub revision: i64,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct RequestPull {
+        pub revision: Option<i64>,
+        pub limit: Option<usize>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct ResponsePull {
+        pub documents: Vec<ReplicatedRecord>,
+        /// Unchanged when there was nothing new, `null` only for an empty database pulled from the start.
+        pub checkpoint: Option<Checkpoint>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PushRow {
+        pub new_document_state: ReplicatedRecord,
+        /// The state the client's change was based on, absent for documents it created.
+        pub assumed_master_state: Option<ReplicatedRecord>,
+    }
+
+    /// Records and deletions after the checkpoint, oldest first.
+    pub fn pull(request: RequestPull, pool: &Pool<SqliteConnectionManager>) -> Result<ResponsePull, Error> {
+        let checkpoint = request.revision.map(|revision| Checkpoint { revision });
+        let limit = request.limit.unwrap_or(DEFAULT_PULL_LIMIT).clamp(1, MAX_PULL_LIMIT);
+        let documents = select_changes(request.revision, limit, pool)?;
+        let checkpoint = documents.last()
+            .map(|document| Checkpoint { revision: document.updated_at })
+            .or(checkpoint);
+        Ok(ResponsePull {
+            documents,
+            checkpoint,
+        })
+    }
+
+    /// Applies every row whose assumed master state is still the latest one, the current master
+    /// state of every other row is returned for the client to resolve. Nothing is written when
+    /// one of the documents fails validation.
+    pub fn push(rows: Vec<PushRow>, author: Option<String>, validators: &Validators, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<ReplicatedRecord>, Error> {
+        for document in rows.iter().map(|row| &row.new_document_state).filter(|document| !document.deleted) {
+            validate_body(&document.mime_type, &document.body, validators, pool)?;
+        }
+        let mut conflicts = Vec::new();
+        for row in rows {
+            let document = row.new_document_state;
+            let master = select_document(document.id, pool)?;
+            let assumed = match (&row.assumed_master_state, &master) {
+                (_, None) => true,
+                (None, Some(_)) => false,
+                (Some(assumed), Some(master)) => assumed.updated_at == master.updated_at && assumed.deleted == master.deleted,
+            };
+            if !assumed {
+                conflicts.extend(master);
+                continue;
+            }
+            let latest = master.filter(|master| !master.deleted).map(|master| master.updated_at);
+            if document.deleted {
+                if latest.is_some() {
+                    remove_record(document.id, pool)?;
+                }
+                continue;
+            }
+            let saved = add_record(
+                RequestRecord {
+                    id: document.id,
+                    mime_type: document.mime_type,
+                    body: document.body,
+                }, author.clone(), match latest {
+                    Some(latest) => Precondition::Revisions(vec![latest]),
+                    None => Precondition::None,
+                }, validators, pool);
+            match saved {
+                // Only a concurrent save can get in between, the client has to see its result.
+                Err(Error::Conflict(_)) => conflicts.extend(select_document(document.id, pool)?),
+                Err(err) => return Err(err),
+                Ok(_) => {}
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// RxDB's `pull.stream$`, it starts with `RESYNC` so a client reconnecting after missing
+    /// events pulls from its checkpoint, every record change follows as a single document batch.
+    pub fn pull_stream(pool: &Pool<SqliteConnectionManager>) -> impl Stream<Item = Bytes> {
+        let pool = pool.clone();
+        let changes = subscribe().filter_map(move |event| {
+            let pool = pool.clone();
+            async move {
+                let id = match event {
+                    Event::RecordCreated { id, .. } | Event::RecordUpdated { id, .. } | Event::RecordDeleted { id } => id,
+                    _ => return None,
+                };
+                // A change which cannot be read now is caught up with by the next pull.
+                let document = select_document(id, &pool).ok().flatten()?;
+                let checkpoint = Checkpoint { revision: document.updated_at };
+                Some(frame(&ResponsePull { documents: vec![document], checkpoint: Some(checkpoint) }))
+            }
+        });
+        with_keep_alive(stream::once(async { frame(&"RESYNC") }).chain(changes))
+    }
+
+    fn frame(data: &impl Serialize) -> Bytes {
+        Bytes::from(format!("data: {}\n\n", serde_json::to_string(data).unwrap()))
+    }
+}
+
+pub mod queries {
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use r2d2_sqlite::rusqlite::{OptionalExtension, params_from_iter, Row, ToSql};
+    use uuid::Uuid;
+
+    use crate::error::{Error, parse_column};
+    use crate::replication::service::ReplicatedRecord;
+
+    /// Latest revisions and tombstones, a record is in at most one of them.
+    const CHANGES: &str = "SELECT id, mime_type, body, revision, 0 AS deleted FROM records_read \
+        UNION ALL SELECT id, '', 'null', revision, 1 FROM records_deleted";
+
+    fn read_document(row: &Row) -> r2d2_sqlite::rusqlite::Result<ReplicatedRecord> {
+        Ok(ReplicatedRecord {
+            id: parse_column(row, 0)?,
+            mime_type: row.get::<_, String>(1)?,
+            body: row.get::<_, serde_json::Value>(2)?,
+            updated_at: row.get::<_, i64>(3)?,
+            deleted: row.get::<_, bool>(4)?,
+        })
+    }
+
+    /// Changes with a revision after `after`, in the order they were made.
+    pub fn select_changes(after: Option<i64>, limit: usize, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<ReplicatedRecord>, Error> {
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+        let mut condition = String::new();
+        if let Some(revision) = after {
+            params.push(Box::new(revision));
+            condition = String::from("WHERE revision > ?1");
+        }
+        params.push(Box::new(limit));
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare(format!("SELECT * FROM ({}) {} ORDER BY revision LIMIT ?{}", CHANGES, condition, params.len()).as_str())?;
+        let rows = stmt.query_map(params_from_iter(params.iter()), read_document)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// The master state of one record, its tombstone when it was deleted.
+    pub fn select_document(record_id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<Option<ReplicatedRecord>, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare(format!("SELECT * FROM ({}) WHERE id = ?1 LIMIT 1", CHANGES).as_str())?;
+        Ok(stmt.query_row([record_id.to_string().as_str()], read_document).optional()?)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use uuid::Uuid;
+
+        use crate::record::queries::{delete_record, insert_record, Latest, select_record, WriteRecord};
+        use crate::replication::queries::{select_changes, select_document};
+        use crate::testing::init_pool;
+
+        #[test]
+        fn test_select_changes_with_tombstone() {
+            let pool = init_pool();
+            let id = Uuid::parse_str("5e0c7a43-2d1b-4f8e-9c6a-7b3d2e1f0a94").unwrap();
+            assert!(insert_record(WriteRecord {
+                id,
+                mime_type: String::from("text/plain"),
+                body: serde_json::json!("replicated"),
+                created_at: chrono::Utc::now(),
+                author: None,
+            }, Latest::Absent, &pool).unwrap());
+            let revision = select_record(id, &pool).unwrap().revision;
+
+            let document = select_document(id, &pool).unwrap().unwrap();
+            assert_eq!(document.updated_at, revision);
+            assert!(!document.deleted);
+            assert!(select_changes(Some(revision - 1), 1000, &pool).unwrap().iter().any(|change| change.id == id && !change.deleted));
+
+            delete_record(id, &pool).unwrap();
+
+            let tombstone = select_document(id, &pool).unwrap().unwrap();
+            assert!(tombstone.deleted);
+            assert!(tombstone.updated_at > revision);
+            let changes = select_changes(Some(revision), 1000, &pool).unwrap();
+            assert!(changes.iter().any(|change| change.id == id && change.deleted));
+            assert!(select_changes(Some(tombstone.updated_at), 1000, &pool).unwrap().iter().all(|change| change.id != id));
+        }
+
+        #[test]
+        fn test_select_changes_within_a_second() {
+            let pool = init_pool();
+            let created_at = chrono::Utc::now();
+            let later = Uuid::parse_str("0b1c2d3e-4f5a-4b6c-8d7e-9f0a1b2c3d4e").unwrap();
+            let earlier = Uuid::parse_str("f1e2d3c4-b5a6-4978-8a9b-0c1d2e3f4a5b").unwrap();
+            let write = |id: Uuid| assert!(insert_record(WriteRecord {
+                id,
+                mime_type: String::from("text/plain"),
+                body: serde_json::json!("replicated"),
+                created_at,
+                author: None,
+            }, Latest::Absent, &pool).unwrap());
+
+            write(earlier);
+            let checkpoint = select_record(earlier, &pool).unwrap().revision;
+            // Saved within the same second and sorting before the checkpoint's id.
+            write(later);
+
+            let changes = select_changes(Some(checkpoint), 1000, &pool).unwrap();
+            assert_eq!(changes.iter().map(|change| change.id).collect::<Vec<Uuid>>(), vec![later]);
+        }
+    }
+}
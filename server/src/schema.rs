@@ -0,0 +1,150 @@
+pub mod service {
+    use chrono::{DateTime, Utc};
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+
+    use crate::error::Error;
+    use crate::record::validation::compile_schema;
+    use crate::schema::queries::{delete_schema, select_schema, select_schemas, upsert_schema, Schema};
+
+    /// Characters RFC 6838 allows in the type and subtype of a mime type.
+    const MIME_TYPE_SYMBOLS: &str = "!#$&-^_.+";
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct ResponseSchema {
+        pub mime_type: String,
+        pub schema: Value,
+        pub updated_at: DateTime<Utc>,
+    }
+
+    fn validate_mime_type(mime_type: &str) -> Result<(), Error> {
+        let is_token = |token: &str| !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || MIME_TYPE_SYMBOLS.contains(c));
+        match mime_type.split_once('/') {
+            Some((kind, subtype)) if is_token(kind) && is_token(subtype) => Ok(()),
+            _ => Err(Error::validation("mime_type", format!("'{}' is not a mime type of the form type/subtype", mime_type))),
+        }
+    }
+
+    /// Registers the JSON Schema bodies of `mime_type` are validated against from now on, replacing
+    /// the previous one. Bodies saved before are not checked again. Returns whether it is new.
+    pub fn put_schema(mime_type: &str, schema: Value, pool: &Pool<SqliteConnectionManager>) -> Result<bool, Error> {
+        validate_mime_type(mime_type)?;
+        compile_schema(&schema).map_err(|detail| Error::validation("body", detail))?;
+        let existed = select_schema(mime_type, pool)?.is_some();
+        upsert_schema(Schema {
+            mime_type: mime_type.to_string(),
+            schema,
+            updated_at: Utc::now(),
+        }, pool)?;
+        Ok(!existed)
+    }
+
+    pub fn get_schema(mime_type: &str, pool: &Pool<SqliteConnectionManager>) -> Result<ResponseSchema, Error> {
+        select_schema(mime_type, pool)?
+            .map(ResponseSchema::from)
+            .ok_or_else(|| Error::NotFound(format!("Schema of '{}' not found", mime_type)))
+    }
+
+    pub fn list_schemas(pool: &Pool<SqliteConnectionManager>) -> Result<Vec<ResponseSchema>, Error> {
+        Ok(select_schemas(pool)?.into_iter().map(ResponseSchema::from).collect())
+    }
+
+    /// Records of `mime_type` stay, new ones are accepted only when it is registered otherwise.
+    pub fn remove_schema(mime_type: &str, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        if !delete_schema(mime_type, pool)? {
+            return Err(Error::NotFound(format!("Schema of '{}' not found", mime_type)));
+        }
+        Ok(())
+    }
+
+    impl From<Schema> for ResponseSchema {
+        fn from(schema: Schema) -> Self {
+            Self { mime_type: schema.mime_type, schema: schema.schema, updated_at: schema.updated_at }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use crate::error::Error;
+        use crate::schema::service::{get_schema, list_schemas, put_schema, remove_schema};
+        use crate::testing::init_pool;
+
+        #[test]
+        fn test_put_schema() {
+            let pool = init_pool();
+            let schema = json!({"type": "object", "required": ["title"]});
+
+            assert!(put_schema("task/json", schema.clone(), &pool).unwrap());
+            assert!(!put_schema("task/json", json!({"type": "object"}), &pool).unwrap());
+            assert_eq!(get_schema("task/json", &pool).unwrap().schema, json!({"type": "object"}));
+            assert_eq!(list_schemas(&pool).unwrap().len(), 1);
+
+            remove_schema("task/json", &pool).unwrap();
+            assert!(matches!(get_schema("task/json", &pool), Err(Error::NotFound(_))));
+            assert!(matches!(remove_schema("task/json", &pool), Err(Error::NotFound(_))));
+        }
+
+        #[test]
+        fn test_put_schema_invalid() {
+            let pool = init_pool();
+            assert!(matches!(put_schema("task", json!({}), &pool), Err(Error::Validation { parameter, .. }) if parameter == "mime_type"));
+            assert!(matches!(put_schema("task/json", json!({"type": "record"}), &pool), Err(Error::Validation { parameter, .. }) if parameter == "body"));
+            assert!(list_schemas(&pool).unwrap().is_empty());
+        }
+    }
+}
+
+pub mod queries {
+    use chrono::{DateTime, TimeZone, Utc};
+    use r2d2::{Pool};
+    use r2d2_sqlite::SqliteConnectionManager;
+    use r2d2_sqlite::rusqlite::{OptionalExtension, params, Row};
+    use serde_json::Value;
+
+    use crate::error::Error;
+
+    pub struct Schema {
+        pub mime_type: String,
+        pub schema: Value,
+        pub updated_at: DateTime<Utc>,
+    }
+
+    fn read_schema(row: &Row) -> r2d2_sqlite::rusqlite::Result<Schema> {
+        Ok(Schema {
+            mime_type: row.get(0)?,
+            schema: row.get(1)?,
+            updated_at: Utc.timestamp_opt(row.get(2)?, 0).unwrap(),
+        })
+    }
+
+    pub fn upsert_schema(schema: Schema, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("INSERT INTO schemas (mime_type, schema, updated_at) VALUES (?1, ?2, ?3)")?;
+        stmt.execute(params![schema.mime_type, schema.schema.to_string(), schema.updated_at.timestamp()])?;
+        Ok(())
+    }
+
+    pub fn select_schema(mime_type: &str, pool: &Pool<SqliteConnectionManager>) -> Result<Option<Schema>, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("SELECT mime_type, schema, updated_at FROM schemas WHERE mime_type = ?1")?;
+        Ok(stmt.query_row([mime_type], read_schema).optional()?)
+    }
+
+    pub fn select_schemas(pool: &Pool<SqliteConnectionManager>) -> Result<Vec<Schema>, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("SELECT mime_type, schema, updated_at FROM schemas ORDER BY mime_type")?;
+        let rows = stmt.query_map([], read_schema)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns whether there was a schema to delete.
+    pub fn delete_schema(mime_type: &str, pool: &Pool<SqliteConnectionManager>) -> Result<bool, Error> {
+        let connection = pool.get()?;
+        let mut stmt = connection.prepare("DELETE FROM schemas WHERE mime_type = ?1")?;
+        Ok(stmt.execute([mime_type])? > 0)
+    }
+}
@@ -0,0 +1,208 @@
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use config::{Config, ConfigError, Environment, File};
+use serde::{Deserialize, Serialize};
+
+const ENVIRONMENT_PREFIX: &str = "THINK";
+/// Looked up as `think.toml`, `think.yaml` or `think.json` in the working directory.
+const DEFAULT_CONFIG_FILE: &str = "think";
+
+/// Flags override the config file and `THINK_*` environment variables, which override the defaults.
+#[derive(Parser, Default, Debug)]
+#[command(about = "Think server")]
+pub struct Cli {
+    /// Config file in TOML, YAML or JSON, picked by its extension.
+    #[arg(long, env = "THINK_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long)]
+    pub database_path: Option<String>,
+    #[arg(long)]
+    pub host: Option<String>,
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Connections kept open to the database.
+    #[arg(long)]
+    pub pool_size: Option<u32>,
+    /// Largest accepted upload in bytes.
+    #[arg(long)]
+    pub max_upload_size: Option<u64>,
+    /// Bytes all stored blobs may take together.
+    #[arg(long)]
+    pub storage_quota: Option<u64>,
+    #[arg(long)]
+    pub storage_backend: Option<String>,
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Accepts records of this mime type besides `note/lexical`, can be repeated.
+    #[arg(long = "mime-type")]
+    pub mime_types: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Blobs are stored in the database next to the records.
+    Sqlite,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn as_filter(&self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub database_path: String,
+    pub host: String,
+    pub port: u16,
+    pub pool_size: u32,
+    /// `None` accepts uploads of any size.
+    pub max_upload_size: Option<usize>,
+    /// `None` leaves the storage unlimited.
+    pub storage_quota: Option<usize>,
+    pub storage_backend: StorageBackend,
+    pub log_level: LogLevel,
+    /// Mime types records may have besides `note/lexical`, their bodies are not checked beyond
+    /// size and depth. `THINK_MIME_TYPES` takes them comma separated.
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ErrSettings {
+    pub err: String,
+}
+
+impl std::fmt::Display for ErrSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid settings: {}", self.err)
+    }
+}
+
+impl From<ConfigError> for ErrSettings {
+    fn from(err: ConfigError) -> Self {
+        ErrSettings { err: err.to_string() }
+    }
+}
+
+impl Settings {
+    pub fn load(cli: Cli) -> Result<Self, ErrSettings> {
+        Self::load_from(cli, Environment::with_prefix(ENVIRONMENT_PREFIX).try_parsing(true))
+    }
+
+    fn load_from(cli: Cli, environment: Environment) -> Result<Self, ErrSettings> {
+        let file = match &cli.config {
+            Some(path) => File::from(path.as_path()).required(true),
+            None => File::with_name(DEFAULT_CONFIG_FILE).required(false),
+        };
+        let settings: Settings = Config::builder()
+            .set_default("database_path", "tmp/data.db")?
+            .set_default("host", "127.0.0.1")?
+            .set_default("port", 8080)?
+            .set_default("pool_size", 10)?
+            .set_default("storage_backend", "sqlite")?
+            .set_default("log_level", "info")?
+            .add_source(file)
+            .add_source(environment.list_separator(",").with_list_parse_key("mime_types"))
+            .set_override_option("database_path", cli.database_path)?
+            .set_override_option("host", cli.host)?
+            .set_override_option("port", cli.port)?
+            .set_override_option("pool_size", cli.pool_size)?
+            .set_override_option("max_upload_size", cli.max_upload_size)?
+            .set_override_option("storage_quota", cli.storage_quota)?
+            .set_override_option("storage_backend", cli.storage_backend)?
+            .set_override_option("log_level", cli.log_level)?
+            .set_override_option("mime_types", (!cli.mime_types.is_empty()).then_some(cli.mime_types))?
+            .build()?
+            .try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), ErrSettings> {
+        let invalid = |err: String| Err(ErrSettings { err });
+        if self.database_path.trim().is_empty() {
+            return invalid(String::from("database_path must not be empty"));
+        }
+        if Path::new(&self.database_path).is_dir() {
+            return invalid(format!("database_path '{}' is a directory", self.database_path));
+        }
+        if self.host.trim().is_empty() {
+            return invalid(String::from("host must not be empty"));
+        }
+        if self.pool_size == 0 {
+            return invalid(String::from("pool_size must be at least 1"));
+        }
+        if self.max_upload_size == Some(0) {
+            return invalid(String::from("max_upload_size must be at least 1 byte, leave it out for no limit"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Environment, Map};
+
+    use crate::settings::{Cli, LogLevel, Settings, StorageBackend};
+
+    fn environment(variables: &[(&str, &str)]) -> Environment {
+        let source: Map<String, String> = variables.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        Environment::with_prefix("THINK").try_parsing(true).source(Some(source))
+    }
+
+    #[test]
+    fn test_load_defaults() {
+        let settings = Settings::load_from(Cli::default(), environment(&[])).unwrap();
+        assert_eq!(settings.database_path, "tmp/data.db");
+        assert_eq!((settings.host.as_str(), settings.port), ("127.0.0.1", 8080));
+        assert_eq!(settings.storage_backend, StorageBackend::Sqlite);
+        assert_eq!(settings.log_level, LogLevel::Info);
+        assert_eq!(settings.storage_quota, None);
+        assert!(settings.mime_types.is_empty());
+    }
+
+    #[test]
+    fn test_load_flags_override_environment() {
+        let settings = Settings::load_from(Cli {
+            port: Some(9090),
+            log_level: Some(String::from("debug")),
+            ..Cli::default()
+        }, environment(&[("THINK_PORT", "8081"), ("THINK_STORAGE_QUOTA", "4096"), ("THINK_LOG_LEVEL", "warn"), ("THINK_MIME_TYPES", "text/plain,text/markdown")])).unwrap();
+        assert_eq!(settings.port, 9090);
+        assert_eq!(settings.mime_types, vec!["text/plain", "text/markdown"]);
+        assert_eq!(settings.log_level, LogLevel::Debug);
+        assert_eq!(settings.storage_quota, Some(4096));
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let err = Settings::load_from(Cli::default(), environment(&[("THINK_STORAGE_BACKEND", "s3")])).unwrap_err();
+        assert!(err.err.contains("s3"));
+        let err = Settings::load_from(Cli { pool_size: Some(0), ..Cli::default() }, environment(&[])).unwrap_err();
+        assert!(err.err.contains("pool_size"));
+        let err = Settings::load_from(Cli { database_path: Some(String::from("/")), ..Cli::default() }, environment(&[])).unwrap_err();
+        assert!(err.err.contains("database_path"));
+    }
+}
@@ -2,13 +2,8 @@ use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use crate::storage::storage::service::Service;
 
+#[allow(clippy::module_inception)]
 pub mod storage {
-    use serde::{Serialize};
-    use actix_web::http::StatusCode;
-    use actix_web::{HttpResponse, ResponseError};
-    use actix_web::body::BoxBody;
-    use uuid::Uuid;
-
     pub mod service {
         use std::path::Path;
         use serde::{Deserialize, Serialize};
@@ -17,8 +12,11 @@ pub mod storage {
         use mime_guess::MimeGuess;
         use r2d2::{Pool};
         use r2d2_sqlite::SqliteConnectionManager;
-        use crate::storage::storage::query::{CompressionStrategy, delete, insert, DbRow, select, select_without_body, DbRowWithoutBody};
-        use crate::storage::storage::ErrNoId;
+        use utoipa::{IntoParams, ToSchema};
+        use crate::storage::storage::query::{CompressionStrategy, delete, insert, DbRow, select, select_without_body, DbRowWithoutBody, select_all_without_body, update_id_and_filename, select_orphans_without_body, DerivedRow, select_derived, insert_derived, delete_unreferenced_derived, for_each, UsageRow, select_usage_total, select_usage_by_mime_type, select_usage_by_record, select_usage_unreferenced, BlobSearch, BlobSortColumn, select_search_without_body};
+        use crate::error::{Error, StorageFailure};
+        use crate::storage::storage::{metadata, sniff, thumbnail};
+        use crate::events::service::{Event, publish};
         use blake2::{Blake2b512, Digest};
 
         #[derive(Deserialize, Serialize)]
@@ -33,11 +31,87 @@ pub mod storage {
             pub filename: String,
         }
 
+        #[derive(Deserialize, Serialize)]
+        pub struct RequestListBlobs {
+            pub id: Uuid,
+        }
+
+        #[derive(Deserialize, Serialize)]
+        pub struct RequestRenameBlob {
+            pub id: Uuid,
+            pub filename: String,
+            pub new_id: Uuid,
+            pub new_filename: String,
+        }
+
+        #[derive(Deserialize, Serialize)]
+        pub struct RequestOrphanBlobs {
+            pub older_than: DateTime<Utc>,
+        }
+
+        #[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug, ToSchema)]
+        #[serde(rename_all = "snake_case")]
+        pub enum BlobSort {
+            #[default]
+            CreatedAt,
+            Size,
+        }
+
+        #[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug, ToSchema)]
+        #[serde(rename_all = "lowercase")]
+        #[schema(as = BlobSortOrder)]
+        pub enum SortOrder {
+            Asc,
+            #[default]
+            Desc,
+        }
+
+        /// `mime_type` matches exactly or by type with `image/*`, `filename` is a case sensitive glob
+        /// (`*`, `?`, `[...]`) and the size bounds are inclusive and apply to the stored size.
+        #[derive(Deserialize, Serialize, Default, IntoParams)]
+        #[into_params(parameter_in = Query)]
+        pub struct RequestSearchBlobs {
+            pub mime_type: Option<String>,
+            pub filename: Option<String>,
+            pub min_size: Option<usize>,
+            pub max_size: Option<usize>,
+            pub created_after: Option<DateTime<Utc>>,
+            pub created_before: Option<DateTime<Utc>>,
+            #[serde(default)]
+            pub sort: BlobSort,
+            #[serde(default)]
+            pub order: SortOrder,
+            pub limit: Option<usize>,
+            pub cursor: Option<String>,
+        }
+
+        #[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug, ToSchema)]
+        #[serde(rename_all = "lowercase")]
+        pub enum ThumbnailFit {
+            /// Scale down preserving aspect ratio so the image fits inside the box.
+            #[default]
+            Contain,
+            /// Scale preserving aspect ratio and crop so the image fills the whole box.
+            Cover,
+            /// Scale to exactly the box, ignoring aspect ratio.
+            Fill,
+        }
+
+        #[derive(Deserialize, Serialize)]
+        pub struct RequestThumbnailBlob {
+            pub id: Uuid,
+            pub filename: String,
+            pub width: Option<u32>,
+            pub height: Option<u32>,
+            pub fit: ThumbnailFit,
+        }
+
         #[derive(Deserialize, Serialize)]
         pub struct RequestUploadBlob {
             pub id: Uuid,
             pub body: Vec<u8>,
             pub path: String,
+            pub content_type: Option<String>,
         }
 
         #[derive(Deserialize, Serialize)]
@@ -51,6 +125,11 @@ pub mod storage {
         }
 
         impl ResponseReadBlob {
+            /// Content a browser would execute when rendered inline, it has to be served as a download.
+            pub fn is_risky(&self) -> bool {
+                sniff::is_risky(&self.mime_type)
+            }
+
             fn from_row(row: DbRow) -> Self {
                 Self {
                     id: row.id,
@@ -70,53 +149,328 @@ pub mod storage {
                     mime_type: row.mime_type.to_string(),
                     size: row.size_before_compress,
                     created_at: row.created_at,
+                    filename: row.filename,
+                    metadata: row.metadata,
+                }
+            }
+        }
+
+        #[derive(Deserialize, Serialize, Default)]
+        pub struct ResponseUsageBucket {
+            pub files: usize,
+            pub size_before_compress: usize,
+            pub size_after_compress: usize,
+        }
+
+        impl ResponseUsageBucket {
+            fn from_row(row: &UsageRow) -> Self {
+                Self {
+                    files: row.files,
+                    size_before_compress: row.size_before_compress,
+                    size_after_compress: row.size_after_compress,
                 }
             }
         }
 
         #[derive(Deserialize, Serialize)]
+        pub struct ResponseUsageByMimeType {
+            pub mime_type: String,
+            #[serde(flatten)]
+            pub usage: ResponseUsageBucket,
+        }
+
+        #[derive(Deserialize, Serialize)]
+        pub struct ResponseUsageByRecord {
+            pub record_id: Uuid,
+            #[serde(flatten)]
+            pub usage: ResponseUsageBucket,
+        }
+
+        /// A blob embedded in several records is counted once for each of them in `by_record`.
+        #[derive(Deserialize, Serialize)]
+        pub struct ResponseUsage {
+            pub quota: Option<usize>,
+            pub total: ResponseUsageBucket,
+            pub unreferenced: ResponseUsageBucket,
+            pub by_mime_type: Vec<ResponseUsageByMimeType>,
+            pub by_record: Vec<ResponseUsageByRecord>,
+        }
+
+        /// `next_cursor` is only set when there may be more blobs after the last item.
+        #[derive(Deserialize, Serialize, ToSchema)]
+        pub struct ResponseSearchBlobs {
+            pub items: Vec<ResponseReadMetaDataBlob>,
+            pub next_cursor: Option<String>,
+        }
+
+        #[derive(Deserialize, Serialize)]
+        pub struct ResponseScrub {
+            pub checked: usize,
+            pub corrupted: Vec<ResponseReadMetaDataBlob>,
+        }
+
+        #[derive(Deserialize, Serialize, ToSchema)]
         pub struct ResponseReadMetaDataBlob {
             pub id: Uuid,
             pub mime_type: String,
             pub size: usize,
             pub created_at: DateTime<Utc>,
+            pub filename: String,
+            pub metadata: BlobMetaData,
+        }
+
+        /// Properties extracted from the content at upload time. Everything is optional,
+        /// only what could be cheaply determined for the given type is filled in.
+        #[derive(Deserialize, Serialize, Clone, Default, PartialEq, Debug, ToSchema)]
+        pub struct BlobMetaData {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub width: Option<u32>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub height: Option<u32>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub taken_at: Option<String>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub camera_make: Option<String>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub camera_model: Option<String>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub page_count: Option<u32>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub title: Option<String>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub duration: Option<f64>,
         }
 
         pub struct Service {
             pub pool: Pool<SqliteConnectionManager>,
+            /// Upper bound in bytes for the stored (compressed) size of all blobs, `None` is unlimited.
+            pub quota: Option<usize>,
+            /// Upper bound in bytes for a single uploaded blob, `None` is unlimited.
+            pub max_upload_size: Option<usize>,
+        }
+
+        const DEFAULT_SEARCH_LIMIT: usize = 50;
+        const MAX_SEARCH_LIMIT: usize = 500;
+
+        /// The cursor is the sort key, id and filename of the last item of a page, opaque to clients.
+        fn encode_cursor(key: &i64, id: Uuid, filename: &str) -> String {
+            hex::encode(format!("{}:{}:{}", key, id, filename))
+        }
+
+        fn decode_cursor(cursor: &str) -> Option<(i64, Uuid, String)> {
+            let decoded = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+            let mut parts = decoded.splitn(3, ':');
+            let key = parts.next()?.parse::<i64>().ok()?;
+            let id = Uuid::parse_str(parts.next()?).ok()?;
+            let filename = parts.next()?.to_string();
+            Some((key, id, filename))
+        }
+
+        fn hash(body: &[u8]) -> String {
+            let mut hasher = Blake2b512::new();
+            hasher.update(body);
+            hex::encode(hasher.finalize())
+        }
+
+        /// Re-hashes the stored body and compares it with the hash taken at upload.
+        fn verify(row: &DbRow) -> Result<(), Error> {
+            let err = match row.compression_strategy {
+                CompressionStrategy::Uncompressed if hash(&row.body) == row.hash_before_compress => return Ok(()),
+                CompressionStrategy::Uncompressed => format!("Blob '{}/{}' does not match its hash", row.id, row.filename),
+                CompressionStrategy::Lz4 => format!("Blob '{}/{}' uses unsupported compression '{}'", row.id, row.filename, row.compression_strategy),
+            };
+            Err(Error::Integrity(err))
+        }
+
+        /// The last component of `path`, which has to name a file.
+        fn file_name(parameter: &str, path: &str) -> Result<String, Error> {
+            Path::new(path).file_name()
+                .and_then(|name| name.to_str())
+                .map(String::from)
+                .ok_or_else(|| Error::validation(parameter, format!("'{}' does not name a file", path)))
         }
 
         impl Service {
             pub fn new(pool: &Pool<SqliteConnectionManager>) -> Self {
-                Self { pool: pool.clone() }
+                Self { pool: pool.clone(), quota: None, max_upload_size: None }
+            }
+            pub fn with_quota(mut self, quota: Option<usize>) -> Self {
+                self.quota = quota;
+                self
+            }
+            pub fn with_max_upload_size(mut self, max_upload_size: Option<usize>) -> Self {
+                self.max_upload_size = max_upload_size;
+                self
+            }
+            pub fn read(&self, request: RequestReadBlob) -> Result<ResponseReadBlob, Error> {
+                let row = select(request.id, request.filename, &self.pool)?;
+                verify(&row)?;
+                Ok(ResponseReadBlob::from_row(row))
+            }
+            /// Walks the whole storage and reports every blob whose body no longer matches its hash.
+            pub fn scrub(&self) -> Result<ResponseScrub, Error> {
+                let mut checked = 0;
+                let mut corrupted: Vec<ResponseReadMetaDataBlob> = Vec::new();
+                for_each(&self.pool, |row| {
+                    checked += 1;
+                    if verify(&row).is_err() {
+                        corrupted.push(ResponseReadMetaDataBlob {
+                            id: row.id,
+                            mime_type: row.mime_type.to_string(),
+                            size: row.size_before_compress,
+                            created_at: row.created_at,
+                            filename: row.filename,
+                            metadata: row.metadata,
+                        });
+                    }
+                })?;
+                Ok(ResponseScrub { checked, corrupted })
+            }
+            pub fn read_meta_data(&self, request: RequestReadBlob) -> Result<ResponseReadMetaDataBlob, Error> {
+                select_without_body(request.id, request.filename, &self.pool).map(ResponseReadMetaDataBlob::from_row)
+            }
+            pub fn list(&self, request: RequestListBlobs) -> Result<Vec<ResponseReadMetaDataBlob>, Error> {
+                Ok(select_all_without_body(request.id, &self.pool)?
+                    .into_iter()
+                    .map(ResponseReadMetaDataBlob::from_row)
+                    .collect())
+            }
+            pub fn delete(&self, request: RequestDeleteBlob) -> Result<(), Error> {
+                if !delete(request.id, request.filename.clone(), &self.pool)? {
+                    return Err(Error::NotFound(format!("Blob '{}/{}' not found", request.id, request.filename)));
+                }
+                publish(Event::FileDeleted { id: request.id, filename: request.filename });
+                Ok(())
+            }
+            pub fn search(&self, request: RequestSearchBlobs) -> Result<ResponseSearchBlobs, Error> {
+                let limit = request.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+                let after = match request.cursor.as_deref() {
+                    Some(cursor) => Some(decode_cursor(cursor).ok_or_else(|| Error::validation("cursor", "Cursor is malformed, pass the next_cursor of a previous page unchanged"))?),
+                    None => None,
+                };
+                let column = match request.sort {
+                    BlobSort::CreatedAt => BlobSortColumn::CreatedAt,
+                    BlobSort::Size => BlobSortColumn::Size,
+                };
+                let rows = select_search_without_body(BlobSearch {
+                    mime_type: request.mime_type,
+                    filename: request.filename,
+                    min_size: request.min_size,
+                    max_size: request.max_size,
+                    created_after: request.created_after,
+                    created_before: request.created_before,
+                    sort: column,
+                    descending: request.order == SortOrder::Desc,
+                    after,
+                    limit,
+                }, &self.pool)?;
+                let next_cursor = match rows.last() {
+                    Some(last) if rows.len() == limit => Some(encode_cursor(&column.key(last), last.id, &last.filename)),
+                    _ => None,
+                };
+                Ok(ResponseSearchBlobs {
+                    items: rows.into_iter().map(ResponseReadMetaDataBlob::from_row).collect(),
+                    next_cursor,
+                })
+            }
+            pub fn orphans(&self, request: RequestOrphanBlobs) -> Result<Vec<ResponseReadMetaDataBlob>, Error> {
+                Ok(select_orphans_without_body(request.older_than, &self.pool)?
+                    .into_iter()
+                    .map(ResponseReadMetaDataBlob::from_row)
+                    .collect())
+            }
+            pub fn remove_orphans(&self, request: RequestOrphanBlobs) -> Result<Vec<ResponseReadMetaDataBlob>, Error> {
+                let orphans = self.orphans(request)?;
+                for orphan in &orphans {
+                    delete(orphan.id, orphan.filename.clone(), &self.pool)?;
+                    publish(Event::FileDeleted { id: orphan.id, filename: orphan.filename.clone() });
+                }
+                delete_unreferenced_derived(&self.pool)?;
+                Ok(orphans)
             }
-            pub fn read(&self, request: RequestReadBlob) -> Result<ResponseReadBlob, ErrNoId> {
-                match select(request.id, request.filename, &self.pool) {
-                    Ok(v) => Ok(ResponseReadBlob::from_row(v)),
-                    Err(err_no_id) => Err(err_no_id)
+            /// Resized variant of an `image/*` blob. Variants are cached by the source hash,
+            /// so identical images stored under different ids share their thumbnails.
+            pub fn thumbnail(&self, request: RequestThumbnailBlob) -> Result<ResponseReadBlob, Error> {
+                let source = select_without_body(request.id, request.filename.clone(), &self.pool)?;
+                if source.mime_type.type_() != mime_guess::mime::IMAGE {
+                    return Err(Error::Storage(StorageFailure::UnsupportedMedia, format!("Blob '{}' of type '{}' is not an image", request.id, source.mime_type)));
                 }
+                let (width, height) = thumbnail::dimensions(request.width, request.height);
+                let variant = thumbnail::variant(width, height, request.fit);
+
+                let derived = match select_derived(source.hash_before_compress.clone(), variant.clone(), &self.pool)? {
+                    Some(v) => v,
+                    None => {
+                        let original = select(request.id, request.filename, &self.pool)?;
+                        verify(&original)?;
+                        let (body, mime_type) = thumbnail::render(&original.body, width, height, request.fit).map_err(|err| Error::Storage(StorageFailure::UnsupportedMedia, err))?;
+                        let row = DerivedRow {
+                            source_hash: source.hash_before_compress,
+                            variant,
+                            mime_type,
+                            size: body.len(),
+                            body,
+                            created_at: Utc::now(),
+                        };
+                        insert_derived(&row, &self.pool)?;
+                        row
+                    }
+                };
+
+                Ok(ResponseReadBlob {
+                    id: source.id,
+                    mime_type: derived.mime_type.to_string(),
+                    size: derived.size,
+                    body: derived.body,
+                    created_at: derived.created_at,
+                    filename: source.filename,
+                })
             }
-            pub fn read_meta_data(&self, request: RequestReadBlob) -> Result<ResponseReadMetaDataBlob, ErrNoId> {
-                match select_without_body(request.id, request.filename, &self.pool) {
-                    Ok(v) => Ok(ResponseReadMetaDataBlob::from_row(v)),
-                    Err(err_no_id) => Err(err_no_id)
+            pub fn rename(&self, request: RequestRenameBlob) -> Result<ResponseReadMetaDataBlob, Error> {
+                let new_filename = file_name("filename", &request.new_filename)?;
+                let updated = update_id_and_filename(request.id, request.filename.clone(), request.new_id, new_filename.clone(), &self.pool)?;
+                if updated == 0 {
+                    return Err(Error::NotFound(format!("Blob '{}/{}' not found", request.id, request.filename)));
                 }
+                publish(Event::FileRenamed { id: request.id, filename: request.filename, new_id: request.new_id, new_filename: new_filename.clone() });
+                self.read_meta_data(RequestReadBlob { id: request.new_id, filename: new_filename })
             }
-            pub fn delete(&self, request: RequestDeleteBlob) {
-                delete(request.id, request.filename, &self.pool)
+            pub fn usage(&self) -> Result<ResponseUsage, Error> {
+                Ok(ResponseUsage {
+                    quota: self.quota,
+                    total: ResponseUsageBucket::from_row(&select_usage_total(&self.pool)?),
+                    unreferenced: ResponseUsageBucket::from_row(&select_usage_unreferenced(&self.pool)?),
+                    by_mime_type: select_usage_by_mime_type(&self.pool)?.iter().map(|row| ResponseUsageByMimeType {
+                        mime_type: row.key.clone(),
+                        usage: ResponseUsageBucket::from_row(row),
+                    }).collect(),
+                    by_record: select_usage_by_record(&self.pool)?.iter().map(|row| Ok(ResponseUsageByRecord {
+                        record_id: Uuid::parse_str(&row.key).map_err(|err| Error::Integrity(format!("Attachment of record '{}': {}", row.key, err)))?,
+                        usage: ResponseUsageBucket::from_row(row),
+                    })).collect::<Result<_, Error>>()?,
+                })
             }
-            pub fn upload(&self, request: RequestUploadBlob) {
-                let mut hasher = Blake2b512::new();
+            pub fn upload(&self, request: RequestUploadBlob) -> Result<(), Error> {
                 let body = request.body;
-                hasher.update(&body);
-                let output = hasher.finalize();
-                let hash = hex::encode(output);
-                let size = &body.len() * std::mem::size_of::<u8>();
-                let filename = Path::new(&request.path).file_name().unwrap().to_str().unwrap().to_string();
+                let hash = hash(&body);
+                let size = body.len();
+                if let Some(max_upload_size) = self.max_upload_size.filter(|max_upload_size| size > *max_upload_size) {
+                    return Err(Error::Storage(StorageFailure::TooLarge, format!("Uploads are limited to {} bytes", max_upload_size)));
+                }
+                if let Some(quota) = self.quota {
+                    let used = select_usage_total(&self.pool)?.size_after_compress;
+                    if used + size > quota {
+                        return Err(Error::Storage(StorageFailure::QuotaExceeded, format!("Storing {} bytes would exceed the quota of {} bytes, {} bytes are already used", size, quota, used)));
+                    }
+                }
+                let filename = file_name("filename", &request.path)?;
+                let mime_type = sniff::detect(MimeGuess::from_path(&request.path).first(), request.content_type.as_deref(), &body);
+                let metadata = metadata::extract(&mime_type, &body);
+                let uploaded_filename = filename.clone();
 
                 let row = DbRow {
                     id: request.id,
-                    mime_type: MimeGuess::from_path(&request.path).first_or_octet_stream(),
+                    mime_type,
                     body,
                     size_after_compress: size,
                     size_before_compress: size,
@@ -124,15 +478,18 @@ pub mod storage {
                     compression_strategy: CompressionStrategy::Uncompressed,
                     created_at: Utc::now(),
                     filename,
+                    metadata,
                 };
 
-                insert(row, &self.pool)
+                insert(row, &self.pool)?;
+                publish(Event::FileUploaded { id: request.id, filename: uploaded_filename });
+                Ok(())
             }
         }
 
         impl Clone for Service {
             fn clone(&self) -> Self {
-                Service::new(&self.pool)
+                Service::new(&self.pool).with_quota(self.quota).with_max_upload_size(self.max_upload_size)
             }
         }
 
@@ -142,8 +499,17 @@ pub mod storage {
             use r2d2::Pool;
             use r2d2_sqlite::SqliteConnectionManager;
             use uuid::Uuid;
-            use crate::storage::storage::service::{RequestDeleteBlob, RequestReadBlob, RequestUploadBlob, Service};
-            use crate::tests::{init_pool, initialize_db};
+            use chrono::{TimeZone, Utc};
+            use crate::storage::storage::query::{insert, CompressionStrategy, DbRow};
+            use std::io::Cursor;
+            use blake2::{Blake2b512, Digest};
+            use image::{DynamicImage, ImageOutputFormat, RgbImage};
+            use crate::storage::storage::query::select_derived;
+            use crate::error::{Error, StorageFailure};
+            use crate::attachment::queries::{Attachment, replace_record_attachments};
+            use crate::events::service::{Event, subscribe};
+            use crate::storage::storage::service::{BlobMetaData, RequestDeleteBlob, RequestListBlobs, RequestOrphanBlobs, RequestReadBlob, RequestRenameBlob, RequestSearchBlobs, BlobSort, RequestThumbnailBlob, RequestUploadBlob, Service, ThumbnailFit};
+            use crate::testing::init_pool;
 
             fn init_service(pool: &Pool<SqliteConnectionManager>) -> Service {
                 Service::new(&pool)
@@ -151,7 +517,6 @@ pub mod storage {
 
             #[test]
             fn test_service_new() {
-                initialize_db();
                 let pool = init_pool();
                 Service::new(&pool);
                 assert!(true);
@@ -159,7 +524,6 @@ pub mod storage {
 
             #[test]
             fn test_service_upload() {
-                initialize_db();
                 let pool = init_pool();
                 let service = init_service(&pool);
                 let id = Uuid::from_str("84f48191-5daa-427b-af2f-5f7c523e9745").unwrap();
@@ -167,12 +531,12 @@ pub mod storage {
                     id,
                     body: "TEST".as_bytes().to_vec(),
                     path: "/tmp/file.txt".to_string(),
-                });
+                    content_type: None,
+                }).unwrap();
             }
 
             #[test]
             fn test_service_read() {
-                initialize_db();
                 let pool = init_pool();
                 let service = init_service(&pool);
                 let id = Uuid::from_str("23885056-5dc3-4e17-9d25-1ad6ce459dca").unwrap();
@@ -181,14 +545,14 @@ pub mod storage {
                     id,
                     body: "TEST".as_bytes().to_vec(),
                     path: "/tmp/file.txt".to_string(),
-                });
+                    content_type: None,
+                }).unwrap();
                 let response = service.read(RequestReadBlob { id, filename: filename.to_string() });
                 assert!(response.is_ok());
             }
 
             #[test]
             fn test_service_read_when_exist_but_filename_is_difference_then_saved() {
-                initialize_db();
                 let pool = init_pool();
                 let service = init_service(&pool);
                 let id = Uuid::from_str("85b6809a-f2c2-4c52-84be-1d772413d3ae").unwrap();
@@ -196,7 +560,8 @@ pub mod storage {
                     id,
                     body: "TEST".as_bytes().to_vec(),
                     path: "/tmp/file.txt".to_string(),
-                });
+                    content_type: None,
+                }).unwrap();
                 let filename = "file1.txt";
                 let response = service.read(RequestReadBlob { id, filename: filename.to_string() });
                 assert!(response.is_err());
@@ -204,7 +569,6 @@ pub mod storage {
 
             #[test]
             fn test_service_read_when_exist_but_id_is_difference_then_saved() {
-                initialize_db();
                 let pool = init_pool();
                 let service = init_service(&pool);
                 let id1 = Uuid::from_str("c9484687-1b16-41ff-9eac-455cb173b783").unwrap();
@@ -212,16 +576,62 @@ pub mod storage {
                     id: id1,
                     body: "TEST".as_bytes().to_vec(),
                     path: "/tmp/file.txt".to_string(),
-                });
+                    content_type: None,
+                }).unwrap();
                 let id2 = Uuid::from_str("80d29c34-e174-48c5-b060-eaf878f66725").unwrap();
                 let filename = "file.txt";
                 let response = service.read(RequestReadBlob { id: id2, filename: filename.to_string() });
                 assert!(response.is_err());
             }
 
+            fn corrupt(id: Uuid, filename: &str, pool: &Pool<SqliteConnectionManager>) {
+                pool.get().unwrap().execute(
+                    "UPDATE storage SET body = ?1 WHERE id = ?2 AND filename = ?3",
+                    [hex::encode("TEST!").as_str(), id.to_string().as_str(), filename],
+                ).unwrap();
+            }
+
+            #[test]
+            fn test_service_read_when_body_is_corrupted() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("f1a3c5e7-2536-436a-8a6c-f57b9d1e3a92").unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                }).unwrap();
+                corrupt(id, "file.txt", &pool);
+                let response = service.read(RequestReadBlob { id, filename: "file.txt".to_string() });
+                assert!(matches!(response, Err(Error::Integrity(_))));
+            }
+
+            #[test]
+            fn test_service_scrub() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let healthy_id = Uuid::from_str("02b4d6f8-3647-447b-9b7d-068c0e2f4ba3").unwrap();
+                let corrupted_id = Uuid::from_str("13c5e709-4758-458c-8c8e-179d1f3a5cb4").unwrap();
+                for id in [healthy_id, corrupted_id] {
+                    service.upload(RequestUploadBlob {
+                        id,
+                        body: "TEST".as_bytes().to_vec(),
+                        path: "/tmp/file.txt".to_string(),
+                        content_type: None,
+                    }).unwrap();
+                }
+                corrupt(corrupted_id, "file.txt", &pool);
+
+                let response = service.scrub().unwrap();
+
+                assert!(response.checked >= 2);
+                assert!(response.corrupted.iter().any(|blob| blob.id == corrupted_id));
+                assert!(!response.corrupted.iter().any(|blob| blob.id == healthy_id));
+            }
+
             #[test]
             fn test_service_delete() {
-                initialize_db();
                 let pool = init_pool();
                 let service = init_service(&pool);
                 let id = Uuid::from_str("ffbb2557-1d7a-44ec-b949-cd2a886551e1").unwrap();
@@ -229,228 +639,1334 @@ pub mod storage {
                     id,
                     body: "TEST".as_bytes().to_vec(),
                     path: "/tmp/file.txt".to_string(),
-                });
-                service.delete(RequestDeleteBlob { id, filename: "file.txt".to_string() });
+                    content_type: None,
+                }).unwrap();
+                service.delete(RequestDeleteBlob { id, filename: "file.txt".to_string() }).unwrap();
             }
-        }
-    }
 
+            #[test]
+            fn test_service_upload_too_large() {
+                let pool = init_pool();
+                let service = init_service(&pool).with_max_upload_size(Some(3));
+                let id = Uuid::from_str("1b3d5f7a-9c0e-4a2b-8d4f-6a8c0e2b4d79").unwrap();
 
-    #[derive(Debug, Serialize)]
-    pub struct ErrNoId {
-        pub id: Uuid,
-        pub err: String,
-    }
+                let response = service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                });
 
-    impl ResponseError for ErrNoId {
-        fn status_code(&self) -> StatusCode {
-            StatusCode::NOT_FOUND
-        }
+                assert!(matches!(response, Err(Error::Storage(StorageFailure::TooLarge, _))));
+            }
 
-        fn error_response(&self) -> HttpResponse<BoxBody> {
-            let body = serde_json::to_string(&self).unwrap();
-            let res = HttpResponse::new(self.status_code());
-            res.set_body(BoxBody::new(body))
-        }
-    }
+            #[test]
+            fn test_service_upload_existing() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("9c1e3a5b-7d8f-4a0b-8c2d-6e8f0a2b4c57").unwrap();
+                let request = || RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                };
+                service.upload(request()).unwrap();
 
-    // Implement Display for ErrNoId
-    impl std::fmt::Display for ErrNoId {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{:?}", self)
-        }
-    }
+                let response = service.upload(request());
 
-    mod query {
-        use std::fmt;
-        use std::fmt::{Formatter};
-        use std::str::FromStr;
-        use chrono::{DateTime, TimeZone, Utc};
-        use mime_guess::Mime;
-        use r2d2::{Pool};
-        use r2d2_sqlite::SqliteConnectionManager;
-        use uuid::Uuid;
-        use crate::storage::storage::ErrNoId;
+                assert!(matches!(response, Err(Error::Storage(StorageFailure::Exists, _))));
+            }
 
-        pub enum CompressionStrategy {
-            Lz4,
-            Uncompressed,
-        }
+            #[test]
+            fn test_service_delete_missing() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("7a9c1e3b-5d6f-4b8a-9c0e-4f6a8b0c2d35").unwrap();
+                let mut receiver = subscribe();
 
-        impl fmt::Display for CompressionStrategy {
-            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-                match self {
-                    CompressionStrategy::Lz4 => write!(f, "lz4"),
-                    CompressionStrategy::Uncompressed => write!(f, "uncompressed"),
+                let response = service.delete(RequestDeleteBlob { id, filename: "file.txt".to_string() });
+
+                assert!(matches!(response, Err(Error::NotFound(_))));
+                // Other tests publish to the same subscribers, only events about this blob count.
+                while let Ok(event) = receiver.try_recv() {
+                    assert_ne!(event, Event::FileDeleted { id, filename: "file.txt".to_string() });
                 }
             }
-        }
-
-        impl std::str::FromStr for CompressionStrategy {
-            type Err = ();
 
-            fn from_str(input: &str) -> Result<Self, Self::Err> {
-                match input {
-                    "lz4" => Ok(CompressionStrategy::Lz4),
-                    "uncompressed" => Ok(CompressionStrategy::Uncompressed),
-                    _ => Err(())
-                }
+            #[test]
+            fn test_service_list() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("5c1e9a3b-7d2f-4e6a-8b0c-1d3e5f7a9b2c").unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                }).unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/image.png".to_string(),
+                    content_type: None,
+                }).unwrap();
+                let response = service.list(RequestListBlobs { id }).unwrap();
+                assert_eq!(response.len(), 2);
+                assert_eq!(response[0].filename, "file.txt");
+                assert_eq!(response[1].filename, "image.png");
             }
-        }
 
-        pub struct DbRow {
-            pub id: Uuid,
-            pub mime_type: Mime,
-            pub body: Vec<u8>,
-            pub size_after_compress: usize,
-            pub size_before_compress: usize,
-            pub hash_before_compress: String,
-            pub compression_strategy: CompressionStrategy,
-            pub created_at: DateTime<Utc>,
-            pub filename: String,
-        }
+            #[test]
+            fn test_service_search() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let ids = [
+                    "c3e5a7b9-3d4f-4a8b-8c2d-4e6f8a0b2c35",
+                    "d4f6b8ca-4e5a-4b9c-9d3e-5f7a9b1c3d46",
+                    "e5a7c9db-5f6b-4cad-8e4f-6a8b0c2d4e57",
+                ];
+                for (index, id) in ids.iter().enumerate() {
+                    service.upload(RequestUploadBlob {
+                        id: Uuid::from_str(id).unwrap(),
+                        body: "TEST".repeat(index + 1).as_bytes().to_vec(),
+                        path: "/tmp/search-service.txt".to_string(),
+                        content_type: None,
+                    }).unwrap();
+                }
 
-        pub struct DbRowWithoutBody {
-            pub id: Uuid,
-            pub mime_type: Mime,
-            pub size_after_compress: usize,
-            pub size_before_compress: usize,
-            pub hash_before_compress: String,
-            pub compression_strategy: CompressionStrategy,
-            pub created_at: DateTime<Utc>,
-            pub filename: String,
-        }
+                let first = service.search(RequestSearchBlobs {
+                    filename: Some("search-service.*".to_string()),
+                    min_size: Some(5),
+                    sort: BlobSort::Size,
+                    limit: Some(1),
+                    ..Default::default()
+                }).unwrap();
+                assert_eq!(first.items.len(), 1);
+                assert_eq!(first.items[0].id, Uuid::from_str(ids[2]).unwrap());
 
-        pub fn insert(row: DbRow, pool: &Pool<SqliteConnectionManager>) {
-            let connection = pool.get().unwrap();
-            let mut stmt = connection.prepare("INSERT INTO storage (id, mime_type, body, size_after_compress, size_before_compress, hash_before_compress, compression_strategy, created_at, filename) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7,?8,?9)").unwrap();
-            stmt.execute([
-                row.id.to_string().as_str(),
-                &row.mime_type.to_string(),
-                hex::encode(&row.body).as_str(),
-                &row.size_after_compress.to_string(),
-                &row.size_before_compress.to_string(),
-                &row.hash_before_compress.to_string(),
-                &row.compression_strategy.to_string(),
-                &row.created_at.timestamp().to_string(),
-                &row.filename.to_string(),
-            ]).unwrap();
-        }
+                let second = service.search(RequestSearchBlobs {
+                    filename: Some("search-service.*".to_string()),
+                    min_size: Some(5),
+                    sort: BlobSort::Size,
+                    limit: Some(1),
+                    cursor: first.next_cursor,
+                    ..Default::default()
+                }).unwrap();
+                assert_eq!(second.items.len(), 1);
+                assert_eq!(second.items[0].id, Uuid::from_str(ids[1]).unwrap());
 
-        pub fn delete(id: Uuid, filename: String, pool: &Pool<SqliteConnectionManager>) {
-            let connection = pool.get().unwrap();
-            let mut stmt = connection.prepare("DELETE FROM storage WHERE id = ?1 AND filename = ?2").unwrap();
-            stmt.execute([
-                id.to_string().as_str(),
-                filename.as_str(),
-            ]).unwrap();
-        }
+                let last = service.search(RequestSearchBlobs {
+                    filename: Some("search-service.*".to_string()),
+                    min_size: Some(5),
+                    sort: BlobSort::Size,
+                    limit: Some(1),
+                    cursor: second.next_cursor,
+                    ..Default::default()
+                }).unwrap();
+                assert!(last.items.is_empty());
+                assert!(last.next_cursor.is_none());
 
-        pub fn select(id: Uuid, filename: String, pool: &Pool<SqliteConnectionManager>) -> Result<DbRow, ErrNoId> {
-            let connection = pool.get().unwrap();
-            let mut stmt = connection.prepare(
-                "SELECT id, mime_type, body, size_after_compress, size_before_compress, hash_before_compress, compression_strategy, created_at, filename FROM storage WHERE id = ?1 AND filename = ?2"
-            ).unwrap();
-
-            let result_of_blob = stmt.query_row([id.to_string().as_str(), filename.as_str()], |row| Ok(DbRow {
-                id: Uuid::from_str(&row.get_unwrap::<_, String>(0)).unwrap(),
-                mime_type: Mime::from_str(&row.get_unwrap::<_, String>(1)).unwrap(),
-                body: hex::decode(row.get_unwrap::<_, String>(2)).unwrap(),
-                size_after_compress: row.get_unwrap::<_, usize>(3),
-                size_before_compress: row.get_unwrap::<_, usize>(4),
-                hash_before_compress: row.get_unwrap::<_, String>(5),
-                compression_strategy: CompressionStrategy::from_str(&row.get_unwrap::<_, String>(6)).unwrap(),
-                created_at: Utc.timestamp_millis_opt(row.get_unwrap::<_, i64>(7)).unwrap(),
-                filename: row.get_unwrap::<_, String>(8),
-            }));
-
-            match result_of_blob {
-                Ok(v) => Ok(v),
-                Err(r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows) => Err(ErrNoId {
-                    id: id,
-                    err: String::from(format!("Blob '{}' not found", id)),
-                }),
-                Err(e) => panic!("Error: {}", e)
-            }
-        }
-
-        pub fn select_without_body(id: Uuid, filename: String, pool: &Pool<SqliteConnectionManager>) -> Result<DbRowWithoutBody, ErrNoId> {
-            let connection = pool.get().unwrap();
-            let mut stmt = connection.prepare("SELECT id, mime_type, size_after_compress, size_before_compress, hash_before_compress, compression_strategy, created_at, filename FROM storage WHERE id = ?1 AND filename = ?2").unwrap();
-
-            let result_of_blob = stmt.query_row([id.to_string().as_str(), filename.as_str()], |row| Ok(DbRowWithoutBody {
-                id: Uuid::from_str(&row.get_unwrap::<_, String>(0)).unwrap(),
-                mime_type: Mime::from_str(&row.get_unwrap::<_, String>(1)).unwrap(),
-                size_after_compress: row.get_unwrap::<_, usize>(2),
-                size_before_compress: row.get_unwrap::<_, usize>(3),
-                hash_before_compress: row.get_unwrap::<_, String>(4),
-                compression_strategy: CompressionStrategy::from_str(&row.get_unwrap::<_, String>(5)).unwrap(),
-                created_at: Utc.timestamp_millis_opt(row.get_unwrap::<_, i64>(6)).unwrap(),
-                filename: row.get_unwrap::<_, String>(7),
-            }));
-
-            match result_of_blob {
-                Ok(v) => Ok(v),
-                Err(r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows) => Err(ErrNoId {
-                    id,
-                    err: String::from(format!("Blob '{}' not found", id)),
-                }),
-                Err(e) => panic!("Error: {}", e)
+                let none = service.search(RequestSearchBlobs {
+                    filename: Some("search-service.*".to_string()),
+                    mime_type: Some("image/*".to_string()),
+                    ..Default::default()
+                }).unwrap();
+                assert!(none.items.is_empty());
             }
-        }
 
-        #[cfg(test)]
-        mod tests {
-            use std::str::FromStr;
-            use blake2::{Blake2b512, Digest};
-            use chrono::{TimeZone, Utc};
-            use mime_guess::mime;
-            use crate::storage::storage::query::{CompressionStrategy, DbRow, delete, insert, select, select_without_body};
-            use crate::tests::{init_pool, initialize_db};
-            use uuid::Uuid;
+            #[test]
+            fn test_service_search_by_created_at() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let ids = [
+                    "f6b8daec-6a7c-4dbe-9f5a-7b9c1d3e5f68",
+                    "a7c9ebfd-7b8d-4ecf-8a6b-8c0d2e4f6a79",
+                    "b8daf0ae-8c9e-4fd0-9b7c-9d1e3f5a7b8a",
+                ];
+                for id in ids {
+                    service.upload(RequestUploadBlob {
+                        id: Uuid::from_str(id).unwrap(),
+                        body: "TEST".as_bytes().to_vec(),
+                        path: "/tmp/search-created.txt".to_string(),
+                        content_type: None,
+                    }).unwrap();
+                }
 
-            fn create_fixture_row(id: Uuid) -> DbRow {
-                let mut hasher = Blake2b512::new();
-                let body = "TEST".as_bytes().to_vec();
-                hasher.update(&body);
-                let output = hasher.finalize();
-                let hex = hex::encode(output);
-                let size = &body.len() * std::mem::size_of::<u8>();
-                let file_name = "test01.txt";
-                DbRow {
-                    id,
-                    mime_type: mime::TEXT_PLAIN,
-                    body,
-                    size_after_compress: size,
-                    size_before_compress: size,
-                    hash_before_compress: hex,
-                    compression_strategy: CompressionStrategy::Uncompressed,
-                    created_at: Utc.timestamp_millis_opt(1).unwrap(),
-                    filename: file_name.to_string(),
+                let mut found = Vec::new();
+                let mut cursor = None;
+                loop {
+                    let page = service.search(RequestSearchBlobs {
+                        filename: Some("search-created.txt".to_string()),
+                        limit: Some(1),
+                        cursor,
+                        ..Default::default()
+                    }).unwrap();
+                    for item in &page.items {
+                        assert!(Utc::now() - item.created_at < chrono::Duration::minutes(1));
+                    }
+                    found.extend(page.items.into_iter().map(|item| item.id.to_string()));
+                    cursor = page.next_cursor;
+                    if cursor.is_none() {
+                        break;
+                    }
                 }
+                found.sort();
+                let mut expected = ids.map(String::from).to_vec();
+                expected.sort();
+                assert_eq!(found, expected);
             }
 
             #[test]
-            fn test_insert() {
-                initialize_db();
+            fn test_service_search_when_cursor_is_malformed() {
                 let pool = init_pool();
-                let id = Uuid::from_str("6ac3f044-000d-4e3f-af0c-98c0005c0695").unwrap();
-                insert(create_fixture_row(id), &pool);
+                let service = init_service(&pool);
+                assert!(service.search(RequestSearchBlobs {
+                    cursor: Some("zz".to_string()),
+                    ..Default::default()
+                }).is_err());
+            }
+
+            fn insert_old_blob(id: Uuid, pool: &Pool<SqliteConnectionManager>) {
+                insert(DbRow {
+                    id,
+                    mime_type: mime_guess::mime::TEXT_PLAIN,
+                    body: "TEST".as_bytes().to_vec(),
+                    size_after_compress: 4,
+                    size_before_compress: 4,
+                    hash_before_compress: "".to_string(),
+                    compression_strategy: CompressionStrategy::Uncompressed,
+                    created_at: Utc.timestamp_opt(-86400 * 3650, 0).unwrap(),
+                    filename: "file.txt".to_string(),
+                    metadata: BlobMetaData::default(),
+                }, pool).unwrap();
+            }
+
+            #[test]
+            fn test_service_orphans() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("24d6f81a-5e7a-4c9d-9f3b-2e4a6c8d0fc5").unwrap();
+                insert_old_blob(id, &pool);
+                let response = service.orphans(RequestOrphanBlobs { older_than: Utc::now() }).unwrap();
+                assert!(response.iter().any(|blob| blob.id == id));
+            }
+
+            #[test]
+            fn test_service_remove_orphans() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("35e7092b-6f8b-4dae-8a4c-3f5b7d9e1ad6").unwrap();
+                insert_old_blob(id, &pool);
+                let response = service.remove_orphans(RequestOrphanBlobs { older_than: Utc.timestamp_opt(-86400 * 3649, 0).unwrap() }).unwrap();
+                assert!(response.iter().any(|blob| blob.id == id));
+                assert!(service.read(RequestReadBlob { id, filename: "file.txt".to_string() }).is_err());
+            }
+
+            #[test]
+            fn test_service_upload_extracts_metadata() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("ce7092b4-f203-4037-9d3f-c24e6a8b0d6f").unwrap();
+                let mut png = Cursor::new(Vec::new());
+                DynamicImage::ImageRgb8(RgbImage::new(64, 48)).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: png.into_inner(),
+                    path: "/tmp/image.png".to_string(),
+                    content_type: None,
+                }).unwrap();
+                let response = service.read_meta_data(RequestReadBlob { id, filename: "image.png".to_string() }).unwrap();
+                assert_eq!(response.metadata.width, Some(64));
+                assert_eq!(response.metadata.height, Some(48));
+            }
+
+            #[test]
+            fn test_service_thumbnail() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("ac5e7092-d0e1-4e15-9b1d-a02c4e6f8b4d").unwrap();
+                let mut png = Cursor::new(Vec::new());
+                DynamicImage::ImageRgb8(RgbImage::new(120, 80)).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: png.into_inner(),
+                    path: "/tmp/image.png".to_string(),
+                    content_type: None,
+                }).unwrap();
+                let request = || RequestThumbnailBlob {
+                    id,
+                    filename: "image.png".to_string(),
+                    width: Some(30),
+                    height: Some(30),
+                    fit: ThumbnailFit::Fill,
+                };
+
+                let response = service.thumbnail(request()).unwrap();
+                assert_eq!(response.mime_type, "image/png");
+                let thumbnail = image::load_from_memory(&response.body).unwrap();
+                assert_eq!((thumbnail.width(), thumbnail.height()), (30, 30));
+
+                let source = service.read(RequestReadBlob { id, filename: "image.png".to_string() }).unwrap();
+                let mut hasher = Blake2b512::new();
+                hasher.update(&source.body);
+                let hash = hex::encode(hasher.finalize());
+                assert!(select_derived(hash, "thumb:w=30;h=30;fit=fill".to_string(), &pool).unwrap().is_some());
+                assert_eq!(service.thumbnail(request()).unwrap().body, response.body);
+            }
+
+            #[test]
+            fn test_service_thumbnail_when_not_image() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("bd6f81a3-e1f2-4f26-8c2e-b13d5f7a9c5e").unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                }).unwrap();
+                let response = service.thumbnail(RequestThumbnailBlob {
+                    id,
+                    filename: "file.txt".to_string(),
+                    width: None,
+                    height: None,
+                    fit: ThumbnailFit::Contain,
+                });
+                assert!(response.is_err());
+            }
+
+            #[test]
+            fn test_service_usage() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("ce7092b4-ebfd-4a21-8b4e-c06b8d0e2a5f").unwrap();
+                let record_id = Uuid::from_str("df81a3c5-fc0e-4b32-9c5f-d17c9e1f3b60").unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                }).unwrap();
+                replace_record_attachments(record_id, &[
+                    Attachment { record_id, file_id: id, filename: "file.txt".to_string() },
+                ], &pool).unwrap();
+
+                let response = service.usage().unwrap();
+
+                assert!(response.total.files >= 1);
+                assert!(response.total.size_after_compress >= 4);
+                assert!(response.by_mime_type.iter().any(|usage| usage.mime_type == "text/plain" && usage.usage.files >= 1));
+                let by_record = response.by_record.iter().find(|usage| usage.record_id == record_id).unwrap();
+                assert_eq!(by_record.usage.files, 1);
+                assert_eq!(by_record.usage.size_before_compress, 4);
+            }
+
+            #[test]
+            fn test_service_upload_when_quota_exceeded() {
+                let pool = init_pool();
+                let service = init_service(&pool).with_quota(Some(0));
+                let id = Uuid::from_str("e092b4d6-0d1f-4c43-8d6a-e28d0f2a4c71").unwrap();
+                let response = service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                });
+                assert!(matches!(response, Err(Error::Storage(StorageFailure::QuotaExceeded, _))));
+                assert!(service.read_meta_data(RequestReadBlob { id, filename: "file.txt".to_string() }).is_err());
+            }
+
+            #[test]
+            fn test_service_rename() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("6d2f0b4c-8e3a-4f7b-9c1d-2e4f6a8b0c3d").unwrap();
+                let new_id = Uuid::from_str("7e3a1c5d-9f4b-4a8c-8d2e-3f5a7b9c1d4e").unwrap();
+                service.upload(RequestUploadBlob {
+                    id,
+                    body: "TEST".as_bytes().to_vec(),
+                    path: "/tmp/file.txt".to_string(),
+                    content_type: None,
+                }).unwrap();
+                let response = service.rename(RequestRenameBlob {
+                    id,
+                    filename: "file.txt".to_string(),
+                    new_id,
+                    new_filename: "renamed.txt".to_string(),
+                });
+                assert!(response.is_ok());
+                assert!(service.read(RequestReadBlob { id, filename: "file.txt".to_string() }).is_err());
+                assert!(service.read(RequestReadBlob { id: new_id, filename: "renamed.txt".to_string() }).is_ok());
+            }
+
+            #[test]
+            fn test_service_rename_when_target_exist() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("9a5c3e7f-1b6d-4cae-8f4a-5b7c9d1e3f60").unwrap();
+                for path in ["/tmp/file.txt", "/tmp/taken.txt"] {
+                    service.upload(RequestUploadBlob {
+                        id,
+                        body: "TEST".as_bytes().to_vec(),
+                        path: path.to_string(),
+                        content_type: None,
+                    }).unwrap();
+                }
+                let response = service.rename(RequestRenameBlob {
+                    id,
+                    filename: "file.txt".to_string(),
+                    new_id: id,
+                    new_filename: "/elsewhere/taken.txt".to_string(),
+                });
+                assert!(matches!(response, Err(Error::Storage(StorageFailure::Exists, _))));
+                assert!(service.read(RequestReadBlob { id, filename: "file.txt".to_string() }).is_ok());
+            }
+
+            #[test]
+            fn test_service_rename_when_not_exist() {
+                let pool = init_pool();
+                let service = init_service(&pool);
+                let id = Uuid::from_str("8f4b2d6e-0a5c-4b9d-9e3f-4a6b8c0d2e5f").unwrap();
+                let response = service.rename(RequestRenameBlob {
+                    id,
+                    filename: "file.txt".to_string(),
+                    new_id: id,
+                    new_filename: "renamed.txt".to_string(),
+                });
+                assert!(response.is_err());
+            }
+        }
+    }
+
+
+    mod sniff {
+        use std::str::FromStr;
+        use mime_guess::{mime, Mime};
+
+        const SNIFF_LIMIT: usize = 1024;
+        const RISKY_MIME_TYPES: [&str; 7] = [
+            "text/html",
+            "application/xhtml+xml",
+            "image/svg+xml",
+            "text/xml",
+            "application/xml",
+            "text/javascript",
+            "application/javascript",
+        ];
+
+        /// Picks the type to store for an upload. Magic bytes always win; the extension and then the
+        /// client `Content-Type` are only trusted for content without a signature, and never when they
+        /// claim a binary format whose signature is missing.
+        pub fn detect(from_extension: Option<Mime>, from_header: Option<&str>, body: &[u8]) -> Mime {
+            if let Some(detected) = sniff(body) {
+                return detected;
+            }
+            let claimed = from_extension
+                .filter(|m| *m != mime::APPLICATION_OCTET_STREAM)
+                .or_else(|| from_header.and_then(|h| Mime::from_str(h).ok()))
+                .map(|m| Mime::from_str(m.essence_str()).unwrap());
+            match claimed {
+                Some(m) if has_signature(&m) => mime::APPLICATION_OCTET_STREAM,
+                Some(m) => m,
+                None => mime::APPLICATION_OCTET_STREAM,
+            }
+        }
+
+        pub fn is_risky(mime_type: &str) -> bool {
+            let essence = mime_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+            RISKY_MIME_TYPES.contains(&essence.as_str())
+        }
+
+        fn sniff(body: &[u8]) -> Option<Mime> {
+            if is_svg(body) {
+                return Some(Mime::from_str("image/svg+xml").unwrap());
+            }
+            infer::get(body).and_then(|t| Mime::from_str(t.mime_type()).ok())
+        }
+
+        fn is_svg(body: &[u8]) -> bool {
+            let head = String::from_utf8_lossy(&body[..body.len().min(SNIFF_LIMIT)]).to_lowercase();
+            let head = head.trim_start_matches('\u{feff}').trim_start();
+            (head.starts_with("<svg") || head.starts_with("<?xml") || head.starts_with("<!doctype svg") || head.starts_with("<!--"))
+                && head.contains("<svg")
+        }
+
+        /// Formats which always start with magic bytes, a claim of one of them without the bytes is a lie.
+        fn has_signature(mime_type: &Mime) -> bool {
+            match (mime_type.type_(), mime_type.subtype().as_str()) {
+                (mime::IMAGE, "svg") => false,
+                (mime::IMAGE | mime::AUDIO | mime::VIDEO, _) => true,
+                (mime::APPLICATION, "pdf" | "zip" | "gzip" | "x-tar" | "x-7z-compressed" | "vnd.rar") => true,
+                _ => false,
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use std::str::FromStr;
+            use mime_guess::{mime, Mime};
+            use crate::storage::storage::sniff::{detect, is_risky};
+
+            const PNG_SIGNATURE: [u8; 16] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52];
+
+            #[test]
+            fn test_detect_by_magic_bytes_without_extension() {
+                assert_eq!(detect(None, None, &PNG_SIGNATURE), mime::IMAGE_PNG);
+            }
+
+            #[test]
+            fn test_detect_html_with_pdf_extension() {
+                let body = "<!DOCTYPE html><html><body><script>alert(1)</script></body></html>".as_bytes();
+                let detected = detect(Some(Mime::from_str("application/pdf").unwrap()), None, body);
+                assert_eq!(detected, mime::TEXT_HTML);
+            }
+
+            #[test]
+            fn test_detect_svg() {
+                let body = "<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>".as_bytes();
+                assert_eq!(detect(Some(mime::IMAGE_PNG), None, body).essence_str(), "image/svg+xml");
+            }
+
+            #[test]
+            fn test_detect_falls_back_to_extension_then_header() {
+                assert_eq!(detect(Some(mime::TEXT_CSV), Some("text/plain"), "a,b".as_bytes()), mime::TEXT_CSV);
+                assert_eq!(detect(None, Some("text/plain;charset=UTF-8"), "TEST".as_bytes()), mime::TEXT_PLAIN);
+                assert_eq!(detect(None, None, "TEST".as_bytes()), mime::APPLICATION_OCTET_STREAM);
+            }
+
+            #[test]
+            fn test_detect_rejects_binary_claim_without_signature() {
+                assert_eq!(detect(Some(mime::IMAGE_PNG), Some("image/png"), "TEST".as_bytes()), mime::APPLICATION_OCTET_STREAM);
+            }
+
+            #[test]
+            fn test_is_risky() {
+                assert!(is_risky("text/html"));
+                assert!(is_risky("image/svg+xml"));
+                assert!(is_risky("text/html; charset=utf-8"));
+                assert!(!is_risky("image/png"));
+                assert!(!is_risky("text/plain"));
+            }
+        }
+    }
+
+    mod metadata {
+        use std::io::Cursor;
+        use exif::{In, Tag, Value};
+        use mime_guess::{mime, Mime};
+        use crate::storage::storage::service::BlobMetaData;
+
+        pub fn extract(mime_type: &Mime, body: &[u8]) -> BlobMetaData {
+            match (mime_type.type_(), mime_type.subtype().as_str()) {
+                (mime::IMAGE, _) => extract_image(body),
+                (mime::APPLICATION, "pdf") => extract_pdf(body),
+                (mime::AUDIO, "wav" | "x-wav" | "wave") => BlobMetaData {
+                    duration: wav_duration(body),
+                    ..Default::default()
+                },
+                (mime::AUDIO | mime::VIDEO, "mp4" | "m4a" | "quicktime" | "x-m4a") => BlobMetaData {
+                    duration: mp4_duration(body),
+                    ..Default::default()
+                },
+                _ => BlobMetaData::default(),
+            }
+        }
+
+        fn extract_image(body: &[u8]) -> BlobMetaData {
+            let mut metadata = BlobMetaData::default();
+            if let Ok((width, height)) = image::io::Reader::new(Cursor::new(body))
+                .with_guessed_format()
+                .map_err(image::ImageError::from)
+                .and_then(|reader| reader.into_dimensions()) {
+                metadata.width = Some(width);
+                metadata.height = Some(height);
+            }
+            if let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(body)) {
+                let ascii = |tag: Tag| exif.get_field(tag, In::PRIMARY).and_then(|field| match &field.value {
+                    Value::Ascii(values) => values.first().map(|v| String::from_utf8_lossy(v).trim().to_string()),
+                    _ => None,
+                }).filter(|v| !v.is_empty());
+                metadata.taken_at = ascii(Tag::DateTimeOriginal).or_else(|| ascii(Tag::DateTime));
+                metadata.camera_make = ascii(Tag::Make);
+                metadata.camera_model = ascii(Tag::Model);
+            }
+            metadata
+        }
+
+        fn extract_pdf(body: &[u8]) -> BlobMetaData {
+            let document = match lopdf::Document::load_mem(body) {
+                Ok(v) => v,
+                Err(_) => return BlobMetaData::default(),
+            };
+            let title = document.trailer.get(b"Info")
+                .and_then(|info| info.as_reference())
+                .and_then(|id| document.get_dictionary(id))
+                .and_then(|info| info.get(b"Title"))
+                .and_then(|title| title.as_str())
+                .map(|title| lopdf::Document::decode_text(None, title))
+                .ok()
+                .filter(|title| !title.is_empty());
+            BlobMetaData {
+                page_count: Some(document.get_pages().len() as u32),
+                title,
+                ..Default::default()
+            }
+        }
+
+        /// Length of the `data` chunk divided by the byte rate from the `fmt ` chunk.
+        fn wav_duration(body: &[u8]) -> Option<f64> {
+            if body.len() < 12 || &body[0..4] != b"RIFF" || &body[8..12] != b"WAVE" {
+                return None;
+            }
+            let mut byte_rate: Option<u32> = None;
+            let mut offset = 12;
+            while offset + 8 <= body.len() {
+                let chunk_id = &body[offset..offset + 4];
+                let chunk_size = u32::from_le_bytes(body[offset + 4..offset + 8].try_into().ok()?);
+                match chunk_id {
+                    b"fmt " if offset + 20 <= body.len() => {
+                        byte_rate = Some(u32::from_le_bytes(body[offset + 16..offset + 20].try_into().ok()?));
+                    }
+                    b"data" => {
+                        return byte_rate.filter(|rate| *rate > 0).map(|rate| chunk_size as f64 / rate as f64);
+                    }
+                    _ => {}
+                }
+                offset += 8 + chunk_size as usize + (chunk_size as usize % 2);
+            }
+            None
+        }
+
+        /// Duration and time scale from the `mvhd` box inside the top level `moov` box.
+        fn mp4_duration(body: &[u8]) -> Option<f64> {
+            let moov = find_box(body, b"moov")?;
+            let mvhd = find_box(moov, b"mvhd")?;
+            let (time_scale, duration) = match mvhd.first()? {
+                0 if mvhd.len() >= 20 => (
+                    u32::from_be_bytes(mvhd[12..16].try_into().ok()?),
+                    u32::from_be_bytes(mvhd[16..20].try_into().ok()?) as u64,
+                ),
+                1 if mvhd.len() >= 32 => (
+                    u32::from_be_bytes(mvhd[20..24].try_into().ok()?),
+                    u64::from_be_bytes(mvhd[24..32].try_into().ok()?),
+                ),
+                _ => return None,
+            };
+            if time_scale == 0 {
+                return None;
+            }
+            Some(duration as f64 / time_scale as f64)
+        }
+
+        fn find_box<'a>(body: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+            let mut offset = 0;
+            while offset + 8 <= body.len() {
+                let size = u32::from_be_bytes(body[offset..offset + 4].try_into().ok()?) as usize;
+                let (header, size) = match size {
+                    0 => (8, body.len() - offset),
+                    1 if offset + 16 <= body.len() => (16, usize::try_from(u64::from_be_bytes(body[offset + 8..offset + 16].try_into().ok()?)).ok()?),
+                    _ => (8, size),
+                };
+                // A 64-bit size comes straight from the file and can be anything.
+                let end = offset.checked_add(size)?;
+                if size < header || end > body.len() {
+                    return None;
+                }
+                if &body[offset + 4..offset + 8] == box_type {
+                    return Some(&body[offset + header..end]);
+                }
+                offset = end;
+            }
+            None
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use std::io::Cursor;
+            use exif::{Field, In, Tag, Value};
+            use exif::experimental::Writer;
+            use image::{DynamicImage, ImageOutputFormat, RgbImage};
+            use lopdf::{dictionary, Document, Object, Stream};
+            use mime_guess::mime;
+            use mime_guess::Mime;
+            use std::str::FromStr;
+            use crate::storage::storage::metadata::extract;
+            use crate::storage::storage::service::BlobMetaData;
+
+            #[test]
+            fn test_extract_image_dimensions() {
+                let mut png = Cursor::new(Vec::new());
+                DynamicImage::ImageRgb8(RgbImage::new(40, 30)).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+                let metadata = extract(&mime::IMAGE_PNG, &png.into_inner());
+                assert_eq!(metadata.width, Some(40));
+                assert_eq!(metadata.height, Some(30));
+            }
+
+            #[test]
+            fn test_extract_image_exif() {
+                let make = Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Canon".to_vec()]) };
+                let model = Field { tag: Tag::Model, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"EOS 5D".to_vec()]) };
+                let taken_at = Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"2023:05:06 12:30:00".to_vec()]) };
+                let mut writer = Writer::new();
+                writer.push_field(&make);
+                writer.push_field(&model);
+                writer.push_field(&taken_at);
+                let mut tiff = Cursor::new(Vec::new());
+                writer.write(&mut tiff, false).unwrap();
+
+                let metadata = extract(&Mime::from_str("image/tiff").unwrap(), &tiff.into_inner());
+
+                assert_eq!(metadata.camera_make, Some("Canon".to_string()));
+                assert_eq!(metadata.camera_model, Some("EOS 5D".to_string()));
+                assert_eq!(metadata.taken_at, Some("2023:05:06 12:30:00".to_string()));
+            }
+
+            #[test]
+            fn test_extract_pdf() {
+                let mut document = Document::with_version("1.5");
+                let pages_id = document.new_object_id();
+                let content_id = document.add_object(Stream::new(dictionary! {}, Vec::new()));
+                let page_ids: Vec<Object> = (0..3).map(|_| document.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "Contents" => content_id,
+                }).into()).collect();
+                document.objects.insert(pages_id, Object::Dictionary(dictionary! {
+                    "Type" => "Pages",
+                    "Kids" => page_ids,
+                    "Count" => 3,
+                    "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+                }));
+                let catalog_id = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+                let info_id = document.add_object(dictionary! { "Title" => Object::string_literal("Quarterly report") });
+                document.trailer.set("Root", catalog_id);
+                document.trailer.set("Info", info_id);
+                let mut pdf = Vec::new();
+                document.save_to(&mut pdf).unwrap();
+
+                let metadata = extract(&Mime::from_str("application/pdf").unwrap(), &pdf);
+
+                assert_eq!(metadata.page_count, Some(3));
+                assert_eq!(metadata.title, Some("Quarterly report".to_string()));
+            }
+
+            #[test]
+            fn test_extract_wav_duration() {
+                let byte_rate: u32 = 8000;
+                let data = vec![0u8; 16000];
+                let mut wav = Vec::new();
+                wav.extend_from_slice(b"RIFF");
+                wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+                wav.extend_from_slice(b"WAVEfmt ");
+                wav.extend_from_slice(&16u32.to_le_bytes());
+                wav.extend_from_slice(&1u16.to_le_bytes());
+                wav.extend_from_slice(&1u16.to_le_bytes());
+                wav.extend_from_slice(&8000u32.to_le_bytes());
+                wav.extend_from_slice(&byte_rate.to_le_bytes());
+                wav.extend_from_slice(&1u16.to_le_bytes());
+                wav.extend_from_slice(&8u16.to_le_bytes());
+                wav.extend_from_slice(b"data");
+                wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                wav.extend_from_slice(&data);
+
+                let metadata = extract(&Mime::from_str("audio/wav").unwrap(), &wav);
+
+                assert_eq!(metadata.duration, Some(2.0));
+            }
+
+            #[test]
+            fn test_extract_mp4_duration() {
+                let mut mvhd = vec![0u8; 100];
+                mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+                mvhd[16..20].copy_from_slice(&4500u32.to_be_bytes());
+                let mut moov = Vec::new();
+                moov.extend_from_slice(&(8 + mvhd.len() as u32).to_be_bytes());
+                moov.extend_from_slice(b"mvhd");
+                moov.extend_from_slice(&mvhd);
+                let mut mp4 = Vec::new();
+                mp4.extend_from_slice(&16u32.to_be_bytes());
+                mp4.extend_from_slice(b"ftypisom");
+                mp4.extend_from_slice(&[0u8; 4]);
+                mp4.extend_from_slice(&(8 + moov.len() as u32).to_be_bytes());
+                mp4.extend_from_slice(b"moov");
+                mp4.extend_from_slice(&moov);
+
+                let metadata = extract(&Mime::from_str("video/mp4").unwrap(), &mp4);
+
+                assert_eq!(metadata.duration, Some(4.5));
+            }
+
+            #[test]
+            fn test_extract_mp4_with_oversized_box() {
+                let mut mp4 = Vec::new();
+                mp4.extend_from_slice(&16u32.to_be_bytes());
+                mp4.extend_from_slice(b"ftypisom");
+                mp4.extend_from_slice(&[0u8; 4]);
+                mp4.extend_from_slice(&1u32.to_be_bytes());
+                mp4.extend_from_slice(b"free");
+                mp4.extend_from_slice(&u64::MAX.to_be_bytes());
+
+                let metadata = extract(&Mime::from_str("video/mp4").unwrap(), &mp4);
+
+                assert_eq!(metadata.duration, None);
+            }
+
+            #[test]
+            fn test_extract_when_content_is_unknown() {
+                assert_eq!(extract(&mime::TEXT_PLAIN, "TEST".as_bytes()), BlobMetaData::default());
+                assert_eq!(extract(&mime::IMAGE_PNG, "TEST".as_bytes()), BlobMetaData::default());
+            }
+        }
+    }
+
+    mod thumbnail {
+        use std::io::Cursor;
+        use image::imageops::FilterType;
+        use image::{ImageFormat, ImageOutputFormat};
+        use mime_guess::{mime, Mime};
+        use crate::storage::storage::service::ThumbnailFit;
+
+        const DEFAULT_SIZE: u32 = 256;
+        const MAX_SIZE: u32 = 2048;
+        const JPEG_QUALITY: u8 = 85;
+
+        /// Box the thumbnail has to fit in. A missing side is unbounded, both missing fall back to the default.
+        pub fn dimensions(width: Option<u32>, height: Option<u32>) -> (u32, u32) {
+            match (width, height) {
+                (None, None) => (DEFAULT_SIZE, DEFAULT_SIZE),
+                (w, h) => (
+                    w.unwrap_or(MAX_SIZE).clamp(1, MAX_SIZE),
+                    h.unwrap_or(MAX_SIZE).clamp(1, MAX_SIZE),
+                ),
+            }
+        }
+
+        pub fn variant(width: u32, height: u32, fit: ThumbnailFit) -> String {
+            format!("thumb:w={};h={};fit={:?}", width, height, fit).to_lowercase()
+        }
+
+        pub fn render(body: &[u8], width: u32, height: u32, fit: ThumbnailFit) -> Result<(Vec<u8>, Mime), String> {
+            let format = image::guess_format(body).map_err(|e| e.to_string())?;
+            let source = image::load_from_memory_with_format(body, format).map_err(|e| e.to_string())?;
+            let resized = match fit {
+                ThumbnailFit::Contain if source.width() <= width && source.height() <= height => source,
+                ThumbnailFit::Contain => source.resize(width, height, FilterType::Lanczos3),
+                ThumbnailFit::Cover => source.resize_to_fill(width, height, FilterType::Lanczos3),
+                ThumbnailFit::Fill => source.resize_exact(width, height, FilterType::Lanczos3),
+            };
+
+            let mut output = Cursor::new(Vec::new());
+            let mime_type = match format {
+                ImageFormat::Jpeg => {
+                    resized.write_to(&mut output, ImageOutputFormat::Jpeg(JPEG_QUALITY)).map_err(|e| e.to_string())?;
+                    mime::IMAGE_JPEG
+                }
+                _ => {
+                    resized.write_to(&mut output, ImageOutputFormat::Png).map_err(|e| e.to_string())?;
+                    mime::IMAGE_PNG
+                }
+            };
+            Ok((output.into_inner(), mime_type))
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use std::io::Cursor;
+            use image::{DynamicImage, ImageOutputFormat, RgbImage};
+            use crate::storage::storage::service::ThumbnailFit;
+            use crate::storage::storage::thumbnail::{dimensions, render};
+
+            fn create_png(width: u32, height: u32) -> Vec<u8> {
+                let mut output = Cursor::new(Vec::new());
+                DynamicImage::ImageRgb8(RgbImage::new(width, height)).write_to(&mut output, ImageOutputFormat::Png).unwrap();
+                output.into_inner()
+            }
+
+            #[test]
+            fn test_dimensions() {
+                assert_eq!(dimensions(None, None), (256, 256));
+                assert_eq!(dimensions(Some(100), None), (100, 2048));
+                assert_eq!(dimensions(Some(0), Some(10000)), (1, 2048));
+            }
+
+            #[test]
+            fn test_render_contain() {
+                let (body, _) = render(&create_png(400, 200), 100, 100, ThumbnailFit::Contain).unwrap();
+                let thumbnail = image::load_from_memory(&body).unwrap();
+                assert_eq!((thumbnail.width(), thumbnail.height()), (100, 50));
+            }
+
+            #[test]
+            fn test_render_cover() {
+                let (body, _) = render(&create_png(400, 200), 100, 100, ThumbnailFit::Cover).unwrap();
+                let thumbnail = image::load_from_memory(&body).unwrap();
+                assert_eq!((thumbnail.width(), thumbnail.height()), (100, 100));
+            }
+
+            #[test]
+            fn test_render_when_body_is_not_image() {
+                assert!(render("TEST".as_bytes(), 100, 100, ThumbnailFit::Contain).is_err());
+            }
+        }
+    }
+
+    mod query {
+        use std::fmt;
+        use std::fmt::{Formatter};
+        use chrono::{DateTime, TimeZone, Utc};
+        use mime_guess::Mime;
+        use r2d2::{Pool};
+        use r2d2_sqlite::SqliteConnectionManager;
+        use r2d2_sqlite::rusqlite::{ErrorCode, OptionalExtension, params_from_iter, Row, ToSql};
+        use uuid::Uuid;
+        use crate::error::{Error, parse_column, StorageFailure};
+        use crate::storage::storage::service::BlobMetaData;
+
+        const WITHOUT_BODY_COLUMNS: &str = "s.id, s.mime_type, s.size_after_compress, s.size_before_compress, s.hash_before_compress, s.created_at, s.filename, s.metadata";
+
+        pub enum CompressionStrategy {
+            Lz4,
+            Uncompressed,
+        }
+
+        impl fmt::Display for CompressionStrategy {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                match self {
+                    CompressionStrategy::Lz4 => write!(f, "lz4"),
+                    CompressionStrategy::Uncompressed => write!(f, "uncompressed"),
+                }
+            }
+        }
+
+        impl std::str::FromStr for CompressionStrategy {
+            type Err = String;
+
+            fn from_str(input: &str) -> Result<Self, Self::Err> {
+                match input {
+                    "lz4" => Ok(CompressionStrategy::Lz4),
+                    "uncompressed" => Ok(CompressionStrategy::Uncompressed),
+                    _ => Err(format!("Unknown compression strategy '{}'", input))
+                }
+            }
+        }
+
+        pub struct DbRow {
+            pub id: Uuid,
+            pub mime_type: Mime,
+            pub body: Vec<u8>,
+            pub size_after_compress: usize,
+            pub size_before_compress: usize,
+            pub hash_before_compress: String,
+            pub compression_strategy: CompressionStrategy,
+            pub created_at: DateTime<Utc>,
+            pub filename: String,
+            pub metadata: BlobMetaData,
+        }
+
+        pub struct DbRowWithoutBody {
+            pub id: Uuid,
+            pub mime_type: Mime,
+            pub size_after_compress: usize,
+            pub size_before_compress: usize,
+            pub hash_before_compress: String,
+            pub created_at: DateTime<Utc>,
+            pub filename: String,
+            pub metadata: BlobMetaData,
+        }
+
+        fn row_without_body(row: &Row) -> r2d2_sqlite::rusqlite::Result<DbRowWithoutBody> {
+            Ok(DbRowWithoutBody {
+                id: parse_column(row, 0)?,
+                mime_type: parse_column(row, 1)?,
+                size_after_compress: row.get::<_, usize>(2)?,
+                size_before_compress: row.get::<_, usize>(3)?,
+                hash_before_compress: row.get::<_, String>(4)?,
+                created_at: Utc.timestamp_opt(row.get::<_, i64>(5)?, 0).unwrap(),
+                filename: row.get::<_, String>(6)?,
+                metadata: serde_json::from_str(&row.get::<_, String>(7)?).unwrap_or_default(),
+            })
+        }
+
+        fn row(row: &Row) -> r2d2_sqlite::rusqlite::Result<DbRow> {
+            Ok(DbRow {
+                id: parse_column(row, 0)?,
+                mime_type: parse_column(row, 1)?,
+                body: hex::decode(row.get::<_, String>(2)?).unwrap_or_default(),
+                size_after_compress: row.get::<_, usize>(3)?,
+                size_before_compress: row.get::<_, usize>(4)?,
+                hash_before_compress: row.get::<_, String>(5)?,
+                compression_strategy: parse_column(row, 6)?,
+                created_at: Utc.timestamp_opt(row.get::<_, i64>(7)?, 0).unwrap(),
+                filename: row.get::<_, String>(8)?,
+                metadata: serde_json::from_str(&row.get::<_, String>(9)?).unwrap_or_default(),
+            })
+        }
+
+        fn not_found(id: Uuid, filename: &str) -> Error {
+            Error::NotFound(format!("Blob '{}/{}' not found", id, filename))
+        }
+
+        pub fn insert(row: DbRow, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare("INSERT INTO storage (id, mime_type, body, size_after_compress, size_before_compress, hash_before_compress, compression_strategy, created_at, filename, metadata) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7,?8,?9,?10)")?;
+            stmt.execute([
+                row.id.to_string().as_str(),
+                row.mime_type.as_ref(),
+                hex::encode(&row.body).as_str(),
+                &row.size_after_compress.to_string(),
+                &row.size_before_compress.to_string(),
+                &row.hash_before_compress.to_string(),
+                &row.compression_strategy.to_string(),
+                &row.created_at.timestamp().to_string(),
+                &row.filename.to_string(),
+                &serde_json::to_string(&row.metadata).unwrap(),
+            ]).map_err(|err| match err.sqlite_error_code() {
+                Some(ErrorCode::ConstraintViolation) => Error::Storage(StorageFailure::Exists, format!("Blob '{}/{}' already exists", row.id, row.filename)),
+                _ => err.into(),
+            })?;
+            Ok(())
+        }
+
+        pub fn delete(id: Uuid, filename: String, pool: &Pool<SqliteConnectionManager>) -> Result<bool, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare("DELETE FROM storage WHERE id = ?1 AND filename = ?2")?;
+            Ok(stmt.execute([
+                id.to_string().as_str(),
+                filename.as_str(),
+            ])? > 0)
+        }
+
+        pub fn select(id: Uuid, filename: String, pool: &Pool<SqliteConnectionManager>) -> Result<DbRow, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare(
+                "SELECT id, mime_type, body, size_after_compress, size_before_compress, hash_before_compress, compression_strategy, created_at, filename, metadata FROM storage WHERE id = ?1 AND filename = ?2"
+            )?;
+
+            stmt.query_row([id.to_string().as_str(), filename.as_str()], row)
+                .optional()?
+                .ok_or_else(|| not_found(id, &filename))
+        }
+
+        /// Streams every stored blob through `callback`, one row in memory at a time.
+        pub fn for_each(pool: &Pool<SqliteConnectionManager>, mut callback: impl FnMut(DbRow)) -> Result<(), Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare(
+                "SELECT id, mime_type, body, size_after_compress, size_before_compress, hash_before_compress, compression_strategy, created_at, filename, metadata FROM storage ORDER BY id, filename"
+            )?;
+
+            let mut rows = stmt.query([])?;
+            while let Some(next) = rows.next()? {
+                callback(row(next)?);
+            }
+            Ok(())
+        }
+
+        pub fn select_without_body(id: Uuid, filename: String, pool: &Pool<SqliteConnectionManager>) -> Result<DbRowWithoutBody, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare(format!("SELECT {} FROM storage s WHERE s.id = ?1 AND s.filename = ?2", WITHOUT_BODY_COLUMNS).as_str())?;
+
+            stmt.query_row([id.to_string().as_str(), filename.as_str()], row_without_body)
+                .optional()?
+                .ok_or_else(|| not_found(id, &filename))
+        }
+
+        pub fn select_all_without_body(id: Uuid, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<DbRowWithoutBody>, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare(format!("SELECT {} FROM storage s WHERE s.id = ?1 ORDER BY s.filename", WITHOUT_BODY_COLUMNS).as_str())?;
+
+            let result_of_blobs = stmt.query_map([id.to_string().as_str()], row_without_body)?;
+
+            let mut blobs: Vec<DbRowWithoutBody> = Vec::new();
+
+            for result_of_blob in result_of_blobs {
+                blobs.push(result_of_blob?);
+            }
+
+            Ok(blobs)
+        }
+
+        #[derive(Clone, Copy)]
+        pub enum BlobSortColumn {
+            CreatedAt,
+            Size,
+        }
+
+        impl BlobSortColumn {
+            fn column(&self) -> &'static str {
+                match self {
+                    BlobSortColumn::CreatedAt => "s.created_at",
+                    BlobSortColumn::Size => "s.size_after_compress",
+                }
+            }
+
+            /// The value of the sort column for `row`, as stored.
+            pub fn key(&self, row: &DbRowWithoutBody) -> i64 {
+                match self {
+                    BlobSortColumn::CreatedAt => row.created_at.timestamp(),
+                    BlobSortColumn::Size => row.size_after_compress as i64,
+                }
+            }
+        }
+
+        pub struct BlobSearch {
+            pub mime_type: Option<String>,
+            pub filename: Option<String>,
+            pub min_size: Option<usize>,
+            pub max_size: Option<usize>,
+            pub created_after: Option<DateTime<Utc>>,
+            pub created_before: Option<DateTime<Utc>>,
+            pub sort: BlobSortColumn,
+            pub descending: bool,
+            /// Sort key, id and filename of the last blob already returned.
+            pub after: Option<(i64, Uuid, String)>,
+            pub limit: usize,
+        }
+
+        /// Keyset pagination over `(sort column, id, filename)`, which is unique since `(id, filename)` is the primary key.
+        pub fn select_search_without_body(search: BlobSearch, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<DbRowWithoutBody>, Error> {
+            let mut conditions: Vec<String> = Vec::new();
+            let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+            if let Some(mime_type) = search.mime_type {
+                match mime_type.strip_suffix("/*") {
+                    // A range rather than LIKE so the mime type indexes stay usable, '0' sorts right after '/'.
+                    Some(top_level) => {
+                        params.push(Box::new(format!("{}/", top_level)));
+                        params.push(Box::new(format!("{}0", top_level)));
+                        conditions.push(format!("s.mime_type >= ?{} AND s.mime_type < ?{}", params.len() - 1, params.len()));
+                    }
+                    None => {
+                        params.push(Box::new(mime_type));
+                        conditions.push(format!("s.mime_type = ?{}", params.len()));
+                    }
+                }
+            }
+            if let Some(filename) = search.filename {
+                params.push(Box::new(filename));
+                conditions.push(format!("s.filename GLOB ?{}", params.len()));
+            }
+            if let Some(min_size) = search.min_size {
+                params.push(Box::new(min_size as i64));
+                conditions.push(format!("s.size_after_compress >= ?{}", params.len()));
+            }
+            if let Some(max_size) = search.max_size {
+                params.push(Box::new(max_size as i64));
+                conditions.push(format!("s.size_after_compress <= ?{}", params.len()));
+            }
+            if let Some(created_after) = search.created_after {
+                params.push(Box::new(created_after.timestamp()));
+                conditions.push(format!("s.created_at >= ?{}", params.len()));
+            }
+            if let Some(created_before) = search.created_before {
+                params.push(Box::new(created_before.timestamp()));
+                conditions.push(format!("s.created_at < ?{}", params.len()));
+            }
+            let column = search.sort.column();
+            let (comparison, direction) = if search.descending { ("<", "DESC") } else { (">", "ASC") };
+            if let Some((key, id, filename)) = search.after {
+                params.push(Box::new(key));
+                params.push(Box::new(id.to_string()));
+                params.push(Box::new(filename));
+                conditions.push(format!("({}, s.id, s.filename) {} (?{}, ?{}, ?{})", column, comparison, params.len() - 2, params.len() - 1, params.len()));
+            }
+            params.push(Box::new(search.limit as i64));
+
+            let sql = format!(
+                "SELECT {} FROM storage s {} ORDER BY {} {}, s.id {}, s.filename {} LIMIT ?{}",
+                WITHOUT_BODY_COLUMNS,
+                if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) },
+                column, direction, direction, direction,
+                params.len(),
+            );
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare(sql.as_str())?;
+
+            let result_of_blobs = stmt.query_map(params_from_iter(params.iter()), row_without_body)?;
+
+            let mut blobs: Vec<DbRowWithoutBody> = Vec::new();
+
+            for result_of_blob in result_of_blobs {
+                blobs.push(result_of_blob?);
+            }
+
+            Ok(blobs)
+        }
+
+        /// Blobs created before `older_than` which no current record revision embeds.
+        pub fn select_orphans_without_body(older_than: DateTime<Utc>, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<DbRowWithoutBody>, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare(format!("SELECT {} FROM storage s WHERE s.created_at < ?1 AND NOT EXISTS (SELECT 1 FROM records_attachments ra WHERE ra.file_id = s.id AND ra.filename = s.filename) ORDER BY s.created_at", WITHOUT_BODY_COLUMNS).as_str())?;
+
+            let result_of_blobs = stmt.query_map([older_than.timestamp()], row_without_body)?;
+
+            let mut blobs: Vec<DbRowWithoutBody> = Vec::new();
+
+            for result_of_blob in result_of_blobs {
+                blobs.push(result_of_blob?);
+            }
+
+            Ok(blobs)
+        }
+
+        pub struct DerivedRow {
+            pub source_hash: String,
+            pub variant: String,
+            pub mime_type: Mime,
+            pub body: Vec<u8>,
+            pub size: usize,
+            pub created_at: DateTime<Utc>,
+        }
+
+        pub fn select_derived(source_hash: String, variant: String, pool: &Pool<SqliteConnectionManager>) -> Result<Option<DerivedRow>, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare("SELECT source_hash, variant, mime_type, body, size, created_at FROM storage_derived WHERE source_hash = ?1 AND variant = ?2")?;
+
+            Ok(stmt.query_row([source_hash.as_str(), variant.as_str()], |row| Ok(DerivedRow {
+                source_hash: row.get::<_, String>(0)?,
+                variant: row.get::<_, String>(1)?,
+                mime_type: parse_column(row, 2)?,
+                body: hex::decode(row.get::<_, String>(3)?).unwrap_or_default(),
+                size: row.get::<_, usize>(4)?,
+                created_at: Utc.timestamp_opt(row.get::<_, i64>(5)?, 0).unwrap(),
+            })).optional()?)
+        }
+
+        pub fn insert_derived(row: &DerivedRow, pool: &Pool<SqliteConnectionManager>) -> Result<(), Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare("INSERT INTO storage_derived (source_hash, variant, mime_type, body, size, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            stmt.execute([
+                row.source_hash.as_str(),
+                row.variant.as_str(),
+                row.mime_type.as_ref(),
+                hex::encode(&row.body).as_str(),
+                &row.size.to_string(),
+                &row.created_at.timestamp().to_string(),
+            ])?;
+            Ok(())
+        }
+
+        /// Drops derived blobs whose source content is no longer stored under any id.
+        pub fn delete_unreferenced_derived(pool: &Pool<SqliteConnectionManager>) -> Result<usize, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare("DELETE FROM storage_derived WHERE source_hash NOT IN (SELECT hash_before_compress FROM storage)")?;
+            Ok(stmt.execute([])?)
+        }
+
+        pub struct UsageRow {
+            pub key: String,
+            pub files: usize,
+            pub size_before_compress: usize,
+            pub size_after_compress: usize,
+        }
+
+        fn select_usage(sql: &str, pool: &Pool<SqliteConnectionManager>) -> Result<Vec<UsageRow>, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare(sql)?;
+
+            let result_of_rows = stmt.query_map([], |row| Ok(UsageRow {
+                key: row.get::<_, String>(0)?,
+                files: row.get::<_, usize>(1)?,
+                size_before_compress: row.get::<_, usize>(2)?,
+                size_after_compress: row.get::<_, usize>(3)?,
+            }))?;
+
+            let mut rows: Vec<UsageRow> = Vec::new();
+
+            for result_of_row in result_of_rows {
+                rows.push(result_of_row?);
+            }
+
+            Ok(rows)
+        }
+
+        /// An aggregate without `GROUP BY` always has exactly one row.
+        pub fn select_usage_total(pool: &Pool<SqliteConnectionManager>) -> Result<UsageRow, Error> {
+            Ok(select_usage("SELECT '', COUNT(*), COALESCE(SUM(size_before_compress), 0), COALESCE(SUM(size_after_compress), 0) FROM storage", pool)?.pop().unwrap())
+        }
+
+        pub fn select_usage_unreferenced(pool: &Pool<SqliteConnectionManager>) -> Result<UsageRow, Error> {
+            Ok(select_usage("SELECT '', COUNT(*), COALESCE(SUM(s.size_before_compress), 0), COALESCE(SUM(s.size_after_compress), 0) FROM storage s WHERE NOT EXISTS (SELECT 1 FROM records_attachments ra WHERE ra.file_id = s.id AND ra.filename = s.filename)", pool)?.pop().unwrap())
+        }
+
+        pub fn select_usage_by_mime_type(pool: &Pool<SqliteConnectionManager>) -> Result<Vec<UsageRow>, Error> {
+            select_usage("SELECT mime_type, COUNT(*), SUM(size_before_compress), SUM(size_after_compress) FROM storage GROUP BY mime_type ORDER BY 4 DESC, mime_type", pool)
+        }
+
+        pub fn select_usage_by_record(pool: &Pool<SqliteConnectionManager>) -> Result<Vec<UsageRow>, Error> {
+            select_usage("SELECT ra.record_id, COUNT(*), SUM(s.size_before_compress), SUM(s.size_after_compress) FROM records_attachments ra JOIN storage s ON s.id = ra.file_id AND s.filename = ra.filename GROUP BY ra.record_id ORDER BY 4 DESC, ra.record_id", pool)
+        }
+
+        /// Fails with [`StorageFailure::Exists`] when another blob has the new id and filename.
+        pub fn update_id_and_filename(id: Uuid, filename: String, new_id: Uuid, new_filename: String, pool: &Pool<SqliteConnectionManager>) -> Result<usize, Error> {
+            let connection = pool.get()?;
+            let mut stmt = connection.prepare("UPDATE storage SET id = ?3, filename = ?4 WHERE id = ?1 AND filename = ?2")?;
+            stmt.execute([
+                id.to_string().as_str(),
+                filename.as_str(),
+                new_id.to_string().as_str(),
+                new_filename.as_str(),
+            ]).map_err(|err| match err.sqlite_error_code() {
+                Some(ErrorCode::ConstraintViolation) => Error::Storage(StorageFailure::Exists, format!("Blob '{}/{}' already exists", new_id, new_filename)),
+                _ => err.into(),
+            })
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use std::str::FromStr;
+            use blake2::{Blake2b512, Digest};
+            use chrono::{TimeZone, Utc};
+            use mime_guess::mime;
+            use crate::storage::storage::query::{CompressionStrategy, DbRow, delete, insert, select, select_all_without_body, select_orphans_without_body, select_without_body, update_id_and_filename};
+            use crate::attachment::queries::{Attachment, replace_record_attachments};
+            use crate::storage::storage::service::BlobMetaData;
+            use crate::testing::init_pool;
+            use uuid::Uuid;
+
+            fn create_fixture_row(id: Uuid) -> DbRow {
+                create_fixture_row_with_filename(id, "test01.txt")
+            }
+
+            fn create_fixture_row_with_filename(id: Uuid, file_name: &str) -> DbRow {
+                let mut hasher = Blake2b512::new();
+                let body = "TEST".as_bytes().to_vec();
+                hasher.update(&body);
+                let output = hasher.finalize();
+                let hex = hex::encode(output);
+                let size = body.len();
+                DbRow {
+                    id,
+                    mime_type: mime::TEXT_PLAIN,
+                    body,
+                    size_after_compress: size,
+                    size_before_compress: size,
+                    hash_before_compress: hex,
+                    compression_strategy: CompressionStrategy::Uncompressed,
+                    created_at: Utc.timestamp_millis_opt(1).unwrap(),
+                    filename: file_name.to_string(),
+                    metadata: BlobMetaData::default(),
+                }
+            }
+
+            #[test]
+            fn test_insert() {
+                let pool = init_pool();
+                let id = Uuid::from_str("6ac3f044-000d-4e3f-af0c-98c0005c0695").unwrap();
+                insert(create_fixture_row(id), &pool).unwrap();
             }
 
             #[test]
             fn test_select() {
-                initialize_db();
                 let pool = init_pool();
                 let id = Uuid::from_str("a1be75d3-3de6-4d38-a182-396a7350387d").unwrap();
-                insert(create_fixture_row(id), &pool);
+                insert(create_fixture_row(id), &pool).unwrap();
                 let result = select(id, "test01.txt".to_string(), &pool);
                 assert!(result.is_ok());
             }
 
             #[test]
             fn test_select_when_not_exist() {
-                initialize_db();
                 let pool = init_pool();
                 let id = Uuid::from_str("0dab7ced-38b1-4080-9768-71acb24385ae").unwrap();
                 let result = select(id, "test01.txt".to_string(), &pool);
@@ -459,17 +1975,15 @@ pub mod storage {
 
             #[test]
             fn test_select_without_body() {
-                initialize_db();
                 let pool = init_pool();
                 let id = Uuid::from_str("e1987ed0-a0f1-403b-97b5-4754e9e86834").unwrap();
-                insert(create_fixture_row(id), &pool);
+                insert(create_fixture_row(id), &pool).unwrap();
                 let result = select_without_body(id, "test01.txt".to_string(), &pool);
                 assert!(result.is_ok());
             }
 
             #[test]
             fn test_select_without_body_when_not_exist() {
-                initialize_db();
                 let pool = init_pool();
                 let id = Uuid::from_str("52209560-bd34-45f4-bca3-bfa2d1808ae7").unwrap();
                 let result = select_without_body(id, "test01.txt".to_string(), &pool);
@@ -478,13 +1992,69 @@ pub mod storage {
 
             #[test]
             fn test_delete() {
-                initialize_db();
                 let pool = init_pool();
                 let id = Uuid::from_str("e0027c7d-4a1a-47cb-a098-ec612a3f3b87").unwrap();
-                insert(create_fixture_row(id), &pool);
+                insert(create_fixture_row(id), &pool).unwrap();
+                assert!(select_without_body(id, "test01.txt".to_string(), &pool).is_ok());
+                assert!(delete(id,"test01.txt".to_string(), &pool).unwrap());
+                assert!(select_without_body(id, "test01.txt".to_string(), &pool).is_err());
+                assert!(!delete(id,"test01.txt".to_string(), &pool).unwrap());
+            }
+
+            #[test]
+            fn test_insert_many_filenames_for_same_id() {
+                let pool = init_pool();
+                let id = Uuid::from_str("3f1f4f4e-58a1-4b7e-9a55-0f0e8a5c9d21").unwrap();
+                insert(create_fixture_row_with_filename(id, "test01.txt"), &pool).unwrap();
+                insert(create_fixture_row_with_filename(id, "test02.txt"), &pool).unwrap();
                 assert!(select_without_body(id, "test01.txt".to_string(), &pool).is_ok());
-                delete(id,"test01.txt".to_string(), &pool);
+                assert!(select_without_body(id, "test02.txt".to_string(), &pool).is_ok());
+            }
+
+            #[test]
+            fn test_select_all_without_body() {
+                let pool = init_pool();
+                let id = Uuid::from_str("a6b0d7e2-51e4-4d3c-8c3e-3e5d2f6c7b10").unwrap();
+                insert(create_fixture_row_with_filename(id, "b.txt"), &pool).unwrap();
+                insert(create_fixture_row_with_filename(id, "a.txt"), &pool).unwrap();
+                let result = select_all_without_body(id, &pool).unwrap();
+                assert_eq!(result.len(), 2);
+                assert_eq!(result[0].filename, "a.txt");
+                assert_eq!(result[1].filename, "b.txt");
+            }
+
+            #[test]
+            fn test_select_orphans_without_body() {
+                let pool = init_pool();
+                let orphan_id = Uuid::from_str("f1a3c5e7-2b4d-4f6a-8c0e-9b1d3f5a7c92").unwrap();
+                let attached_id = Uuid::from_str("02b4d6f8-3c5e-4a7b-9d1f-0c2e4a6b8da3").unwrap();
+                let record_id = Uuid::from_str("13c5e709-4d6f-4b8c-8e2a-1d3f5b7c9eb4").unwrap();
+                let mut orphan = create_fixture_row(orphan_id);
+                orphan.created_at = Utc.timestamp_opt(-86400 * 365, 0).unwrap();
+                let mut attached = create_fixture_row(attached_id);
+                attached.created_at = Utc.timestamp_opt(-86400 * 365, 0).unwrap();
+                insert(orphan, &pool).unwrap();
+                insert(attached, &pool).unwrap();
+                replace_record_attachments(record_id, &[
+                    Attachment { record_id, file_id: attached_id, filename: "test01.txt".to_string() },
+                ], &pool).unwrap();
+
+                let result = select_orphans_without_body(Utc.timestamp_opt(-86400 * 364, 0).unwrap(), &pool).unwrap();
+
+                assert!(result.iter().any(|row| row.id == orphan_id));
+                assert!(!result.iter().any(|row| row.id == attached_id));
+            }
+
+            #[test]
+            fn test_update_id_and_filename() {
+                let pool = init_pool();
+                let id = Uuid::from_str("d2a4c6e8-0b1d-4f3a-9c5e-7a9b1c3d5e7f").unwrap();
+                let new_id = Uuid::from_str("e3b5d7f9-1c2e-4a4b-8d6f-8b0c2d4e6f80").unwrap();
+                insert(create_fixture_row(id), &pool).unwrap();
+                let updated = update_id_and_filename(id, "test01.txt".to_string(), new_id, "test02.txt".to_string(), &pool).unwrap();
+                assert_eq!(updated, 1);
                 assert!(select_without_body(id, "test01.txt".to_string(), &pool).is_err());
+                assert!(select_without_body(new_id, "test02.txt".to_string(), &pool).is_ok());
             }
         }
     }
@@ -0,0 +1,262 @@
+use std::fs;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use r2d2::ManageConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde::Serialize;
+use uuid::Uuid;
+
+use think_core::{embedded, Think};
+use think_core::error::OptionalExt;
+use think_core::record::service::{Precondition, RecordView, RequestListRecords, RequestRecord};
+use think_core::storage::storage::service::{RequestListBlobs, RequestReadBlob, RequestUploadBlob};
+
+/// Maintenance of a Think database, works on the SQLite file directly so the server does not have to run.
+#[derive(Parser)]
+#[command(name = "think")]
+struct Cli {
+    #[arg(long, env = "THINK_DATABASE_PATH", default_value = "tmp/data.db")]
+    database_path: String,
+    /// Accepts records of this mime type besides `note/lexical` on create and import, can be repeated.
+    #[arg(long = "allow-mime-type")]
+    mime_types: Vec<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Applies pending migrations.
+    Migrate,
+    #[command(subcommand)]
+    Records(RecordsCommand),
+    #[command(subcommand)]
+    Files(FilesCommand),
+    /// Writes the latest revision of every record as one JSON object per line.
+    Export {
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Reads records as written by `export`, existing records get a new revision.
+    Import {
+        input: Option<PathBuf>,
+        #[arg(long)]
+        author: Option<String>,
+    },
+    /// Rebuilds the database file, giving space of deleted data back to the file system.
+    Vacuum,
+    /// Checks the database structure and the hash of every blob, exits with 1 on any problem.
+    Check,
+    Stats,
+}
+
+#[derive(Subcommand)]
+enum RecordsCommand {
+    /// Prints a summary of every record as one JSON object per line, latest first.
+    List {
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    Show {
+        id: Uuid,
+    },
+    /// Creates a record with the JSON body read from `body`, or stdin without it.
+    Create {
+        #[arg(long, default_value = "note/lexical")]
+        mime_type: String,
+        #[arg(long)]
+        id: Option<Uuid>,
+        #[arg(long)]
+        author: Option<String>,
+        body: Option<PathBuf>,
+    },
+    Delete {
+        id: Uuid,
+    },
+}
+
+#[derive(Subcommand)]
+enum FilesCommand {
+    List {
+        id: Uuid,
+    },
+    Upload {
+        id: Uuid,
+        path: PathBuf,
+        /// Stored name, the name of `path` by default.
+        #[arg(long)]
+        filename: Option<String>,
+        #[arg(long)]
+        content_type: Option<String>,
+    },
+    /// Writes the blob to `output`, or stdout without it.
+    Download {
+        id: Uuid,
+        filename: String,
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command, &cli.database_path, &cli.mime_types) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("think: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command, database_path: &str, mime_types: &[String]) -> Result<(), String> {
+    if let Command::Migrate = command {
+        let mut connection = SqliteConnectionManager::file(database_path).connect().map_err(|err| err.to_string())?;
+        let report = embedded::migrations::runner().run(&mut connection).map_err(|err| err.to_string())?;
+        for migration in report.applied_migrations() {
+            println!("{}", migration);
+        }
+        return Ok(());
+    }
+    // A single connection, nothing here runs concurrently.
+    let think = Think::open(database_path, 1).map_err(|err| err.to_string())?.with_mime_types(mime_types);
+    match command {
+        Command::Migrate => unreachable!("migrations run before the database is opened"),
+        Command::Records(command) => records(command, &think),
+        Command::Files(command) => files(command, &think),
+        Command::Export { output } => {
+            let records = think.records().list(RequestListRecords {
+                fields: Some(String::from("id,mime_type,body")),
+                ..Default::default()
+            }).map_err(|err| err.to_string())?.records;
+            let mut out = writer(output.as_deref())?;
+            for record in records {
+                writeln!(out, "{}", serde_json::to_string(&record).unwrap()).map_err(|err| err.to_string())?;
+            }
+            Ok(())
+        }
+        Command::Import { input, author } => {
+            let reader: Box<dyn BufRead> = match input {
+                Some(path) => Box::new(std::io::BufReader::new(fs::File::open(&path).map_err(|err| format!("{}: {}", path.display(), err))?)),
+                None => Box::new(std::io::stdin().lock()),
+            };
+            for (number, line) in reader.lines().enumerate() {
+                let line = line.map_err(|err| err.to_string())?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record: RequestRecord = serde_json::from_str(&line).map_err(|err| format!("line {}: {}", number + 1, err))?;
+                let precondition = match think.records().get(record.id).optional().map_err(|err| err.to_string())? {
+                    Some(current) => Precondition::Revisions(vec![current.revision]),
+                    None => Precondition::None,
+                };
+                think.records().save(record, author.clone(), precondition).map_err(|err| format!("line {}: {}", number + 1, err))?;
+            }
+            Ok(())
+        }
+        Command::Vacuum => think.pool().get().map_err(|err| err.to_string())?.execute_batch("VACUUM").map_err(|err| err.to_string()),
+        Command::Check => {
+            let database = {
+                // Given back before the scrub, which needs the pool's only connection.
+                let connection = think.pool().get().map_err(|err| err.to_string())?;
+                let mut stmt = connection.prepare("PRAGMA integrity_check").map_err(|err| err.to_string())?;
+                let rows = stmt.query_map([], |row| row.get::<_, String>(0))
+                    .and_then(|rows| rows.collect::<Result<Vec<String>, _>>())
+                    .map_err(|err| err.to_string())?;
+                rows
+            };
+            let blobs = think.files().scrub().map_err(|err| err.to_string())?;
+            let healthy = database == ["ok"] && blobs.corrupted.is_empty();
+            print_json(&serde_json::json!({ "database": database, "blobs": blobs }));
+            if healthy { Ok(()) } else { Err(String::from("integrity check failed")) }
+        }
+        Command::Stats => {
+            let records = think.records().stats().map_err(|err| err.to_string())?;
+            let storage = think.files().usage().map_err(|err| err.to_string())?;
+            print_json(&serde_json::json!({ "records": records, "storage": storage }));
+            Ok(())
+        }
+    }
+}
+
+fn records(command: RecordsCommand, think: &Think) -> Result<(), String> {
+    let records = think.records();
+    match command {
+        RecordsCommand::List { limit } => {
+            let page = records.list(RequestListRecords {
+                limit,
+                view: RecordView::Summary,
+                ..Default::default()
+            }).map_err(|err| err.to_string())?;
+            for record in page.records {
+                println!("{}", serde_json::to_string(&record).unwrap());
+            }
+            Ok(())
+        }
+        RecordsCommand::Show { id } => {
+            print_json(&records.get(id).map_err(|err| err.to_string())?);
+            Ok(())
+        }
+        RecordsCommand::Create { mime_type, id, author, body } => {
+            let body = serde_json::from_slice(&read(body.as_deref())?).map_err(|err| format!("body is not JSON: {}", err))?;
+            let saved = records.save(RequestRecord {
+                id: id.unwrap_or_else(Uuid::new_v4),
+                mime_type,
+                body,
+            }, author, Precondition::None).map_err(|err| err.to_string())?;
+            println!("{}", saved.record.id);
+            Ok(())
+        }
+        RecordsCommand::Delete { id } => {
+            records.get(id).map_err(|err| err.to_string())?;
+            records.remove(id).map_err(|err| err.to_string())
+        }
+    }
+}
+
+fn files(command: FilesCommand, think: &Think) -> Result<(), String> {
+    let service = think.files();
+    match command {
+        FilesCommand::List { id } => {
+            for blob in service.list(RequestListBlobs { id }).map_err(|err| err.to_string())? {
+                println!("{}", serde_json::to_string(&blob).unwrap());
+            }
+            Ok(())
+        }
+        FilesCommand::Upload { id, path, filename, content_type } => {
+            let body = read(Some(&path))?;
+            let path = match filename {
+                Some(filename) => filename,
+                None => path.file_name().and_then(|name| name.to_str()).ok_or_else(|| format!("{} has no file name, pass --filename", path.display()))?.to_string(),
+            };
+            service.upload(RequestUploadBlob { id, body, path, content_type }).map_err(|err| err.to_string())
+        }
+        FilesCommand::Download { id, filename, output } => {
+            let blob = service.read(RequestReadBlob { id, filename }).map_err(|err| err.to_string())?;
+            writer(output.as_deref())?.write_all(&blob.body).map_err(|err| err.to_string())
+        }
+    }
+}
+
+fn read(path: Option<&Path>) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    match path {
+        Some(path) => fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)),
+        None => std::io::stdin().read_to_end(&mut bytes),
+    }.map_err(|err| err.to_string())?;
+    Ok(bytes)
+}
+
+fn writer(path: Option<&Path>) -> Result<Box<dyn Write>, String> {
+    Ok(match path {
+        Some(path) => Box::new(fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+fn print_json(value: &impl Serialize) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
@@ -0,0 +1,83 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use r2d2_sqlite::rusqlite::Connection;
+use tempfile::TempDir;
+
+/// Runs the `think` binary on the database at `database`, `stdin` is piped in when given.
+fn think(database: &Path, args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_think"))
+        .arg("--database-path").arg(database)
+        .arg("--allow-mime-type").arg("text/plain")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin.unwrap_or_default().as_bytes()).unwrap();
+    drop(input);
+    child.wait_with_output().unwrap()
+}
+
+fn sorted_lines(path: &Path) -> Vec<String> {
+    let mut lines: Vec<String> = std::fs::read_to_string(path).unwrap().lines().map(String::from).collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn test_export_and_import() {
+    let directory = TempDir::new().unwrap();
+    let source = directory.path().join("source.db");
+    let target = directory.path().join("target.db");
+    for body in ["\"first\"", "{\"text\": \"second\"}"] {
+        let output = think(&source, &["records", "create", "--mime-type", "text/plain"], Some(body));
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    let exported = directory.path().join("source.jsonl");
+    assert!(think(&source, &["export", "--output", exported.to_str().unwrap()], None).status.success());
+    assert_eq!(sorted_lines(&exported).len(), 2);
+    assert!(think(&target, &["import", exported.to_str().unwrap()], None).status.success());
+    let reexported = directory.path().join("target.jsonl");
+    assert!(think(&target, &["export", "--output", reexported.to_str().unwrap()], None).status.success());
+    assert_eq!(sorted_lines(&reexported), sorted_lines(&exported));
+
+    // Importing again only adds a revision to every record.
+    assert!(think(&target, &["import", exported.to_str().unwrap()], None).status.success());
+    let stats: serde_json::Value = serde_json::from_slice(&think(&target, &["stats"], None).stdout).unwrap();
+    assert_eq!((&stats["records"]["records"], &stats["records"]["revisions"]), (&serde_json::json!(2), &serde_json::json!(4)));
+}
+
+#[test]
+fn test_check() {
+    let directory = TempDir::new().unwrap();
+    let database = directory.path().join("think.db");
+    let file = directory.path().join("file.txt");
+    std::fs::write(&file, "TEST").unwrap();
+    let id = "3c5e7a9b-1d2f-4a6c-8e0b-2d4f6a8c0e1f";
+    assert!(think(&database, &["files", "upload", id, file.to_str().unwrap()], None).status.success());
+
+    let output = think(&database, &["check"], None);
+    assert!(output.status.success());
+
+    Connection::open(&database).unwrap().execute("UPDATE storage SET hash_before_compress = 'corrupted'", []).unwrap();
+    let output = think(&database, &["check"], None);
+    assert_eq!(output.status.code(), Some(1));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["blobs"]["corrupted"][0]["filename"], "file.txt");
+}
+
+#[test]
+fn test_delete_unknown_record() {
+    let directory = TempDir::new().unwrap();
+    let database = directory.path().join("think.db");
+
+    let output = think(&database, &["records", "delete", "4d6f8a0b-2c3e-4b7d-9f1a-3e5a7b9c1d20"], None);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not found"));
+}